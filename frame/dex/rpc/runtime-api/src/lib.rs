@@ -0,0 +1,56 @@
+//! Runtime API definition for the DEX pallet.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+use sp_runtime::DispatchError;
+use sp_std::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+    /// Queries for pricing swaps and inspecting AMMs without reimplementing the pallet's math.
+    /// Queries that can fail return the pallet's error, e.g. `InvalidAmmId` or
+    /// `InsufficientLiquidity`.
+    pub trait DexApi<AmmId, AssetId, AccountId, Balance> where
+        AmmId: Codec,
+        AssetId: Codec,
+        AccountId: Codec,
+        Balance: Codec,
+    {
+        /// Amount of `asset_out` one would get if sending `amount` of `asset_in` to the AMM. See
+        /// `SimulateSwap::simulate_swap_between`.
+        fn quote_by_input(
+            amm_id: AmmId,
+            asset_in: AssetId,
+            asset_out: AssetId,
+            amount: Balance,
+        ) -> Result<Balance, DispatchError>;
+
+        /// Amount of `asset_in` one would need to get `amount` of `asset_out` back from the AMM.
+        /// See `SimulateSwap::output_price_between`.
+        fn quote_by_output(
+            amm_id: AmmId,
+            asset_in: AssetId,
+            asset_out: AssetId,
+            amount: Balance,
+        ) -> Result<Balance, DispatchError>;
+
+        /// Id of the AMM of two assets holding `asset_a` and `asset_b`, in any order, with
+        /// `fees_bps`.
+        fn pair_amm(asset_a: AssetId, asset_b: AssetId, fees_bps: Balance) -> Option<AmmId>;
+
+        /// Amount of `asset_out` one would get if sending `amount` of `asset_in` to the AMM of the
+        /// pair with `fees_bps`.
+        fn quote_pair_by_input(
+            asset_in: AssetId,
+            asset_out: AssetId,
+            fees_bps: Balance,
+            amount: Balance,
+        ) -> Result<Balance, DispatchError>;
+
+        /// Reserves of each asset of the AMM, in the order given at creation.
+        fn pool_reserves(amm_id: AmmId) -> Option<Vec<Balance>>;
+
+        /// Amounts of each asset `who` would get by withdrawing all of their LP shares.
+        fn lp_share_value(amm_id: AmmId, who: AccountId) -> Result<Vec<Balance>, DispatchError>;
+    }
+}
@@ -0,0 +1,281 @@
+//! RPC interface for the DEX pallet.
+//!
+//! Exposes the `DexApi` runtime API as `dex_*` JSON-RPC methods. Every method accepts an optional
+//! block hash, defaulting to the best block, so prices can be queried against historical state.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use codec::Codec;
+use jsonrpsee::{
+    core::{Error as JsonRpseeError, RpcResult},
+    proc_macros::rpc,
+    types::error::{CallError, ErrorObject},
+};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_rpc::number::NumberOrHex;
+use sp_runtime::{generic::BlockId, traits::Block as BlockT, DispatchError};
+
+pub use pallet_dex_rpc_runtime_api::DexApi as DexRuntimeApi;
+
+#[rpc(client, server)]
+pub trait DexApi<BlockHash, AmmId, AssetId, AccountId> {
+    /// Amount of `asset_out` one would get if sending `amount` of `asset_in` to the AMM.
+    #[method(name = "dex_quoteByInput")]
+    fn quote_by_input(
+        &self,
+        amm_id: AmmId,
+        asset_in: AssetId,
+        asset_out: AssetId,
+        amount: NumberOrHex,
+        at: Option<BlockHash>,
+    ) -> RpcResult<NumberOrHex>;
+
+    /// Amount of `asset_in` one would need to get `amount` of `asset_out` back from the AMM.
+    #[method(name = "dex_quoteByOutput")]
+    fn quote_by_output(
+        &self,
+        amm_id: AmmId,
+        asset_in: AssetId,
+        asset_out: AssetId,
+        amount: NumberOrHex,
+        at: Option<BlockHash>,
+    ) -> RpcResult<NumberOrHex>;
+
+    /// Id of the AMM of two assets holding `asset_a` and `asset_b`, in any order, with
+    /// `fees_bps`.
+    #[method(name = "dex_pairAmm")]
+    fn pair_amm(
+        &self,
+        asset_a: AssetId,
+        asset_b: AssetId,
+        fees_bps: NumberOrHex,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Option<AmmId>>;
+
+    /// Amount of `asset_out` one would get if sending `amount` of `asset_in` to the AMM of the
+    /// pair with `fees_bps`.
+    #[method(name = "dex_quotePairByInput")]
+    fn quote_pair_by_input(
+        &self,
+        asset_in: AssetId,
+        asset_out: AssetId,
+        fees_bps: NumberOrHex,
+        amount: NumberOrHex,
+        at: Option<BlockHash>,
+    ) -> RpcResult<NumberOrHex>;
+
+    /// Reserves of each asset of the AMM, in the order given at creation.
+    #[method(name = "dex_poolReserves")]
+    fn pool_reserves(
+        &self,
+        amm_id: AmmId,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Option<Vec<NumberOrHex>>>;
+
+    /// Amounts of each asset `who` would get by withdrawing all of their LP shares.
+    #[method(name = "dex_lpShareValue")]
+    fn lp_share_value(
+        &self,
+        amm_id: AmmId,
+        who: AccountId,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Vec<NumberOrHex>>;
+}
+
+/// Error codes returned by the DEX RPC.
+pub enum Error {
+    /// The call to the runtime failed.
+    RuntimeError,
+    /// A balance could not be converted from or to its RPC representation.
+    InvalidBalance,
+    /// The pallet could not answer the query, e.g. for an unknown AMM.
+    QueryFailed,
+}
+
+impl From<Error> for i32 {
+    fn from(e: Error) -> i32 {
+        match e {
+            Error::RuntimeError => 1,
+            Error::InvalidBalance => 2,
+            Error::QueryFailed => 3,
+        }
+    }
+}
+
+/// Implementation of the DEX RPC, backed by the `DexApi` runtime API.
+pub struct Dex<C, Block> {
+    client: Arc<C>,
+    _marker: PhantomData<Block>,
+}
+
+impl<C, Block> Dex<C, Block> {
+    /// Create a new instance of the DEX RPC.
+    pub fn new(client: Arc<C>) -> Self {
+        Self {
+            client,
+            _marker: Default::default(),
+        }
+    }
+
+    fn block_id(&self, at: Option<Block::Hash>) -> BlockId<Block>
+    where
+        Block: BlockT,
+        C: HeaderBackend<Block>,
+    {
+        BlockId::hash(at.unwrap_or_else(|| self.client.info().best_hash))
+    }
+}
+
+fn runtime_error(e: impl std::fmt::Debug) -> JsonRpseeError {
+    CallError::Custom(ErrorObject::owned(
+        Error::RuntimeError.into(),
+        "Unable to query the DEX runtime API.",
+        Some(format!("{:?}", e)),
+    ))
+    .into()
+}
+
+fn invalid_balance() -> JsonRpseeError {
+    CallError::Custom(ErrorObject::owned(
+        Error::InvalidBalance.into(),
+        "Balance does not fit the runtime's balance type.",
+        None::<()>,
+    ))
+    .into()
+}
+
+fn query_failed(e: DispatchError) -> JsonRpseeError {
+    CallError::Custom(ErrorObject::owned(
+        Error::QueryFailed.into(),
+        "The DEX could not answer the query.",
+        Some(format!("{:?}", e)),
+    ))
+    .into()
+}
+
+fn decode_balance<Balance: TryFrom<NumberOrHex>>(amount: NumberOrHex) -> RpcResult<Balance> {
+    amount.try_into().map_err(|_| invalid_balance())
+}
+
+fn encode_balance<Balance: TryInto<NumberOrHex>>(amount: Balance) -> RpcResult<NumberOrHex> {
+    amount.try_into().map_err(|_| invalid_balance())
+}
+
+fn encode_balances<Balance: TryInto<NumberOrHex>>(
+    amounts: Vec<Balance>,
+) -> RpcResult<Vec<NumberOrHex>> {
+    amounts.into_iter().map(encode_balance).collect()
+}
+
+impl<C, Block, AmmId, AssetId, AccountId, Balance>
+    DexApiServer<<Block as BlockT>::Hash, AmmId, AssetId, AccountId> for Dex<C, Block>
+where
+    Block: BlockT,
+    C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+    C::Api: DexRuntimeApi<Block, AmmId, AssetId, AccountId, Balance>,
+    AmmId: Codec + Send + Sync + 'static,
+    AssetId: Codec + Send + Sync + 'static,
+    AccountId: Codec + Send + Sync + 'static,
+    Balance: Codec + TryFrom<NumberOrHex> + TryInto<NumberOrHex> + Send + Sync + 'static,
+{
+    fn quote_by_input(
+        &self,
+        amm_id: AmmId,
+        asset_in: AssetId,
+        asset_out: AssetId,
+        amount: NumberOrHex,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<NumberOrHex> {
+        let api = self.client.runtime_api();
+        api.quote_by_input(
+            &self.block_id(at),
+            amm_id,
+            asset_in,
+            asset_out,
+            decode_balance(amount)?,
+        )
+        .map_err(runtime_error)?
+        .map_err(query_failed)
+        .and_then(encode_balance)
+    }
+
+    fn quote_by_output(
+        &self,
+        amm_id: AmmId,
+        asset_in: AssetId,
+        asset_out: AssetId,
+        amount: NumberOrHex,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<NumberOrHex> {
+        let api = self.client.runtime_api();
+        api.quote_by_output(
+            &self.block_id(at),
+            amm_id,
+            asset_in,
+            asset_out,
+            decode_balance(amount)?,
+        )
+        .map_err(runtime_error)?
+        .map_err(query_failed)
+        .and_then(encode_balance)
+    }
+
+    fn pair_amm(
+        &self,
+        asset_a: AssetId,
+        asset_b: AssetId,
+        fees_bps: NumberOrHex,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<Option<AmmId>> {
+        let api = self.client.runtime_api();
+        api.pair_amm(&self.block_id(at), asset_a, asset_b, decode_balance(fees_bps)?)
+            .map_err(runtime_error)
+    }
+
+    fn quote_pair_by_input(
+        &self,
+        asset_in: AssetId,
+        asset_out: AssetId,
+        fees_bps: NumberOrHex,
+        amount: NumberOrHex,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<NumberOrHex> {
+        let api = self.client.runtime_api();
+        api.quote_pair_by_input(
+            &self.block_id(at),
+            asset_in,
+            asset_out,
+            decode_balance(fees_bps)?,
+            decode_balance(amount)?,
+        )
+        .map_err(runtime_error)?
+        .map_err(query_failed)
+        .and_then(encode_balance)
+    }
+
+    fn pool_reserves(
+        &self,
+        amm_id: AmmId,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<Option<Vec<NumberOrHex>>> {
+        let api = self.client.runtime_api();
+        api.pool_reserves(&self.block_id(at), amm_id)
+            .map_err(runtime_error)?
+            .map(encode_balances)
+            .transpose()
+    }
+
+    fn lp_share_value(
+        &self,
+        amm_id: AmmId,
+        who: AccountId,
+        at: Option<Block::Hash>,
+    ) -> RpcResult<Vec<NumberOrHex>> {
+        let api = self.client.runtime_api();
+        api.lp_share_value(&self.block_id(at), amm_id, who)
+            .map_err(runtime_error)?
+            .map_err(query_failed)
+            .and_then(encode_balances)
+    }
+}
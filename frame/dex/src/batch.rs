@@ -0,0 +1,251 @@
+//! Batch mode of constant product AMMs, where the swaps submitted during a block are cleared
+//! together at a single price at the end of it, so that their order within the block does not
+//! matter.
+
+use crate::{
+    curves::constant_product, helpers::*, traits::SimulateSwap, types::*, AmmStates, BatchSwaps,
+    Config, Error, Event, Pallet,
+};
+use frame_support::{
+    ensure,
+    pallet_prelude::{DispatchError, DispatchResult, Get},
+    storage::{with_transaction, TransactionOutcome},
+    traits::fungibles::Transfer,
+};
+use sp_runtime::traits::{AccountIdConversion, Zero};
+use sp_std::{vec, vec::Vec};
+
+/// How the swaps of a batch against an AMM of two assets clear.
+struct Clearing<T: Config> {
+    /// Total amount of each asset sold in the batch.
+    sold: [T::Balance; 2],
+    /// Total amount of the other asset owed to the sellers of each asset.
+    owed: [T::Balance; 2],
+    /// The index of the asset sold in excess and the amount of it swapped against the AMM, unless
+    /// sellers of both assets matched each other entirely.
+    pool_input: Option<(usize, T::Balance)>,
+}
+
+impl<T: Config> Clearing<T> {
+    /// Output of a swap of `input_amount` of the asset at `index`, its pro-rata part of what the
+    /// sellers of that asset are owed.
+    fn output(&self, input_amount: T::Balance, index: usize) -> Result<T::Balance, DispatchError> {
+        Ok(mul_div(input_amount, self.owed[index], self.sold[index], Rounding::Down)?)
+    }
+}
+
+impl<T: Config> Pallet<T> {
+    /// The account holding the input of swaps submitted to AMMs in batch mode until the end of the
+    /// block.
+    pub fn batch_escrow_account() -> T::AccountId {
+        T::PalletId::get().into_sub_account_truncating(b"batches")
+    }
+
+    /// Escrow the input of a swap against an AMM in batch mode, to be cleared at the end of the
+    /// block.
+    pub(crate) fn submit_batch_swap(
+        caller: &T::AccountId,
+        amm_id: T::AmmId,
+        asset_in: T::AssetId,
+        asset_out: T::AssetId,
+        input_amount: T::Balance,
+        output_min: T::Balance,
+    ) -> DispatchResult {
+        ensure!(!input_amount.is_zero(), Error::<T>::ZeroAmount);
+        Self::ensure_not_paused(&amm_id)?;
+
+        let amm_state = Self::try_get_amm_state(&amm_id)?;
+        Self::swap_indices(&amm_state, &asset_in, &asset_out)?;
+        ensure!(amm_state.is_initialized(), Error::<T>::ZeroLiquidity);
+
+        let swap = BatchSwap { user: caller.clone(), asset_in, input_amount, output_min };
+        BatchSwaps::<T>::try_mutate(&amm_id, |swaps| {
+            swaps.try_push(swap).map_err(|_| Error::<T>::BatchFull)
+        })?;
+        T::Assets::transfer(asset_in, caller, &Self::batch_escrow_account(), input_amount, false)?;
+
+        Self::deposit_event(Event::<T>::BatchSwapSubmitted {
+            user: caller.clone(),
+            amm_id,
+            asset_in,
+            input_amount,
+            output_min,
+        });
+
+        Ok(())
+    }
+
+    /// Clear the swaps submitted to each AMM in batch mode during the block.
+    pub(crate) fn clear_batches() {
+        let batches = BatchSwaps::<T>::drain().collect::<Vec<_>>();
+        for (amm_id, swaps) in batches {
+            let cleared = with_transaction(|| match Self::clear_batch(&amm_id, &swaps) {
+                Ok(()) => TransactionOutcome::Commit(Ok(())),
+                Err(error) => TransactionOutcome::Rollback(Err(error)),
+            });
+
+            // The AMM was paused, destroyed or could not take the excess, so nothing was swapped.
+            if cleared.is_err() {
+                for swap in swaps.iter() {
+                    // Only fails if the escrow was drained, leaving nothing to refund.
+                    let _ = Self::refund_batch_swap(&amm_id, swap);
+                }
+            }
+        }
+    }
+
+    /// Fill the swaps of a batch which get at least their `output_min` at the batch's price and
+    /// refund the others. The excess of the asset sold the most is swapped against the AMM, and
+    /// the amounts left over from rounding payouts down go to its reserves.
+    fn clear_batch(amm_id: &T::AmmId, swaps: &[BatchSwap<T>]) -> DispatchResult {
+        Self::ensure_not_paused(amm_id)?;
+        let amm_state = Self::try_get_amm_state(amm_id)?;
+        let indices = swaps
+            .iter()
+            .map(|swap| amm_state.asset_index(&swap.asset_in).ok_or(Error::<T>::InvalidAsset))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Leaving a swap out moves the price against the other swaps of the same side, so checks
+        // start over until every swap left in gets its minimum. Each round leaves one out at
+        // least, or is the last.
+        let mut included = vec![true; swaps.len()];
+        let clearing = loop {
+            let clearing = Self::batch_clearing(amm_id, &amm_state, swaps, &indices, &included)?;
+            let mut changed = false;
+            for ((swap, index), included) in swaps.iter().zip(&indices).zip(included.iter_mut()) {
+                if *included {
+                    let output = clearing.output(swap.input_amount, *index)?;
+                    if output.is_zero() || output < swap.output_min {
+                        *included = false;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break clearing;
+            }
+        };
+
+        let escrow = Self::batch_escrow_account();
+        let amm_account = Self::amm_account(amm_id);
+        if let Some((index_in, amount)) = clearing.pool_input {
+            let asset_in = amm_state.assets[index_in];
+            let asset_out = amm_state.assets[1 - index_in];
+            let (output_amount, fees) = Self::swap_reserves(*amm_id, asset_in, asset_out, amount)?;
+            let pool_input = amount.try_sub(&fees.protocol)?;
+            T::Assets::transfer(asset_in, &escrow, &amm_account, pool_input, false)?;
+            if !fees.protocol.is_zero() {
+                let recipient = T::ProtocolFeeRecipient::get();
+                T::Assets::transfer(asset_in, &escrow, &recipient, fees.protocol, false)?;
+            }
+            T::Assets::transfer(asset_out, &amm_account, &escrow, output_amount, false)?;
+        }
+
+        let mut paid = [T::Balance::zero(); 2];
+        for ((swap, index), included) in swaps.iter().zip(&indices).zip(&included) {
+            if !included {
+                Self::refund_batch_swap(amm_id, swap)?;
+                continue;
+            }
+
+            let asset_out = amm_state.assets[1 - index];
+            let output_amount = clearing.output(swap.input_amount, *index)?;
+            T::Assets::transfer(asset_out, &escrow, &swap.user, output_amount, false)?;
+            paid[*index] = paid[*index].try_add(&output_amount)?;
+
+            Self::deposit_event(Event::<T>::BatchSwapFilled {
+                user: swap.user.clone(),
+                amm_id: *amm_id,
+                asset_in: swap.asset_in,
+                asset_out,
+                input_amount: swap.input_amount,
+                output_amount,
+            });
+        }
+
+        let mut amm_state = Self::try_get_amm_state(amm_id)?;
+        for (index, reserve) in amm_state.reserves.iter_mut().enumerate() {
+            // Owed to the sellers of the other asset, who were paid rounded down.
+            let dust = clearing.owed[1 - index].try_sub(&paid[1 - index])?;
+            if !dust.is_zero() {
+                T::Assets::transfer(amm_state.assets[index], &escrow, &amm_account, dust, false)?;
+                *reserve = reserve.try_add(&dust)?;
+            }
+        }
+        AmmStates::<T>::insert(amm_id, amm_state);
+
+        let unit = Self::unit();
+        let price = if !clearing.sold[0].is_zero() {
+            Some(mul_div(clearing.owed[0], unit, clearing.sold[0], Rounding::Down)?)
+        } else if !clearing.owed[1].is_zero() {
+            Some(mul_div(clearing.sold[1], unit, clearing.owed[1], Rounding::Down)?)
+        } else {
+            None
+        };
+        if let Some(price) = price {
+            Self::deposit_event(Event::<T>::BatchCleared { amm_id: *amm_id, price });
+        }
+
+        Ok(())
+    }
+
+    /// Price the swaps of a batch still `included`, whose inputs are the assets at `indices` of
+    /// the AMM. Sellers of each asset are matched with each other, and what one side sells in
+    /// excess is swapped against the AMM, up to where both sides get the same price.
+    fn batch_clearing(
+        amm_id: &T::AmmId,
+        amm_state: &Amm<T>,
+        swaps: &[BatchSwap<T>],
+        indices: &[usize],
+        included: &[bool],
+    ) -> Result<Clearing<T>, DispatchError> {
+        let mut sold = [T::Balance::zero(); 2];
+        for ((swap, index), included) in swaps.iter().zip(indices).zip(included) {
+            if *included {
+                sold[*index] = sold[*index].try_add(&swap.input_amount)?;
+            }
+        }
+
+        let mut owed = [sold[1], sold[0]];
+        let mut pool_input = None;
+        // At most one side sells in excess of what the other one can match at the AMM's price.
+        for index_in in [0, 1] {
+            let index_out = 1 - index_in;
+            let amount = from_u256(constant_product::batch_net_input(
+                amm_state.reserves[index_in].into(),
+                amm_state.reserves[index_out].into(),
+                sold[index_in].into(),
+                sold[index_out].into(),
+                amm_state.fees_bps.into(),
+            )?)?;
+            if !amount.is_zero() {
+                let output_amount = <Self as SimulateSwap>::simulate_swap_between(
+                    *amm_id,
+                    amm_state.assets[index_in],
+                    amm_state.assets[index_out],
+                    amount,
+                )?;
+                owed[index_in] = owed[index_in].try_add(&output_amount)?;
+                owed[index_out] = owed[index_out].try_sub(&amount)?;
+                pool_input = Some((index_in, amount));
+            }
+        }
+
+        Ok(Clearing { sold, owed, pool_input })
+    }
+
+    /// Send the input of a swap submitted to an AMM in batch mode back to its user.
+    fn refund_batch_swap(amm_id: &T::AmmId, swap: &BatchSwap<T>) -> DispatchResult {
+        let escrow = Self::batch_escrow_account();
+        T::Assets::transfer(swap.asset_in, &escrow, &swap.user, swap.input_amount, false)?;
+
+        Self::deposit_event(Event::<T>::BatchSwapRefunded {
+            user: swap.user.clone(),
+            amm_id: *amm_id,
+            asset_in: swap.asset_in,
+            input_amount: swap.input_amount,
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+//! Swaps and liquidity accounting of concentrated liquidity AMMs.
+
+use crate::{
+    curves::concentrated::*, helpers::*, types::*, Config, ConcentratedAmms, Error, Pallet,
+    Positions,
+};
+use frame_support::{
+    ensure,
+    pallet_prelude::{DispatchError, DispatchResult},
+    traits::fungibles::Transfer,
+};
+use sp_core::U256;
+use sp_runtime::{traits::Zero, ArithmeticError};
+
+impl<T: Config> Pallet<T> {
+    pub(crate) fn try_get_concentrated_amm(
+        amm_id: &T::AmmId,
+    ) -> Result<ConcentratedAmm<T>, DispatchError> {
+        ConcentratedAmms::<T>::get(amm_id).ok_or_else(|| Error::<T>::InvalidAmmId.into())
+    }
+
+    /// The position `position_id`, if `who` owns it.
+    pub(crate) fn try_get_owned_position(
+        position_id: &PositionId,
+        who: &T::AccountId,
+    ) -> Result<Position<T>, DispatchError> {
+        let position = Positions::<T>::get(position_id).ok_or(Error::<T>::InvalidPositionId)?;
+        ensure!(position.owner == *who, Error::<T>::NotPositionOwner);
+        Ok(position)
+    }
+
+    /// Send `amounts` of each asset of a concentrated liquidity AMM to `who`.
+    pub(crate) fn pay_out(
+        amm_id: &T::AmmId,
+        amm: &ConcentratedAmm<T>,
+        who: &T::AccountId,
+        amounts: [T::Balance; 2],
+    ) -> DispatchResult {
+        let amm_account = Self::amm_account(amm_id);
+        for (asset, amount) in amm.assets.iter().zip(amounts) {
+            if !amount.is_zero() {
+                T::Assets::transfer(*asset, &amm_account, who, amount, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ensure a position within `[lower_tick, upper_tick)` can be opened in `amm`.
+    pub(crate) fn ensure_valid_ticks(
+        amm: &ConcentratedAmm<T>,
+        lower_tick: i32,
+        upper_tick: i32,
+    ) -> DispatchResult {
+        let spacing = amm.tick_spacing as i32;
+        ensure!(
+            MIN_TICK <= lower_tick
+                && lower_tick < upper_tick
+                && upper_tick <= MAX_TICK
+                && lower_tick % spacing == 0
+                && upper_tick % spacing == 0,
+            Error::<T>::InvalidTicks
+        );
+        Ok(())
+    }
+
+    /// Amounts of each asset backing `liquidity` within `[lower_tick, upper_tick)` at the current
+    /// price, rounded up when depositing and down when withdrawing.
+    pub(crate) fn position_amounts(
+        amm: &ConcentratedAmm<T>,
+        lower_tick: i32,
+        upper_tick: i32,
+        liquidity: u128,
+        round_up: bool,
+    ) -> Result<[T::Balance; 2], DispatchError> {
+        let lower = sqrt_price_at_tick(lower_tick)?;
+        let upper = sqrt_price_at_tick(upper_tick)?;
+        // Only the first asset is needed below the range, and only the second one above it.
+        let price = amm.sqrt_price.max(lower).min(upper);
+
+        Ok([
+            from_u256(amount_0_delta(price, upper, liquidity.into(), round_up)?)?,
+            from_u256(amount_1_delta(lower, price, liquidity.into(), round_up)?)?,
+        ])
+    }
+
+    /// The most liquidity `amounts` can back within `[lower_tick, upper_tick)` at the current
+    /// price.
+    pub(crate) fn liquidity_for_amounts(
+        amm: &ConcentratedAmm<T>,
+        lower_tick: i32,
+        upper_tick: i32,
+        amounts: [T::Balance; 2],
+    ) -> Result<u128, DispatchError> {
+        let lower = sqrt_price_at_tick(lower_tick)?;
+        let upper = sqrt_price_at_tick(upper_tick)?;
+        let price = amm.sqrt_price.max(lower).min(upper);
+
+        let from_0 = if price < upper {
+            liquidity_for_amount_0(price, upper, amounts[0].into())?
+        } else {
+            U256::max_value()
+        };
+        let from_1 = if price > lower {
+            liquidity_for_amount_1(lower, price, amounts[1].into())?
+        } else {
+            U256::max_value()
+        };
+
+        Ok(u128::try_from(from_0.min(from_1)).map_err(|_| ArithmeticError::Overflow)?)
+    }
+
+    /// Add `liquidity` to a position, or remove it if not `add`, accruing the fees it earned so
+    /// far. Adding no liquidity only accrues fees.
+    pub(crate) fn update_position(
+        amm: &mut ConcentratedAmm<T>,
+        position: &mut Position<T>,
+        liquidity: u128,
+        add: bool,
+    ) -> DispatchResult {
+        let (lower_tick, upper_tick) = (position.lower_tick, position.upper_tick);
+
+        // Ticks must be initialized before computing the fees earned within them, and cleared
+        // only after.
+        if add {
+            Self::update_tick(amm, lower_tick, liquidity, false, true)?;
+            Self::update_tick(amm, upper_tick, liquidity, true, true)?;
+        }
+
+        let fee_growths_inside = amm.fee_growths_inside(lower_tick, upper_tick);
+        for (index, fees_owed) in position.fees_owed.iter_mut().enumerate() {
+            let growth = fee_growths_inside[index]
+                .overflowing_sub(position.fee_growths_inside[index])
+                .0;
+            let earned = fees(growth, position.liquidity.into())?;
+            *fees_owed = fees_owed.try_add(&from_u256(earned)?)?;
+        }
+        position.fee_growths_inside = fee_growths_inside;
+
+        if !add {
+            Self::update_tick(amm, lower_tick, liquidity, false, false)?;
+            Self::update_tick(amm, upper_tick, liquidity, true, false)?;
+        }
+
+        let in_range = lower_tick <= amm.tick && amm.tick < upper_tick;
+        if add {
+            position.liquidity = position.liquidity.try_add(&liquidity)?;
+            if in_range {
+                amm.liquidity = amm.liquidity.try_add(&liquidity)?;
+            }
+        } else {
+            position.liquidity = position.liquidity.try_sub(&liquidity)?;
+            if in_range {
+                amm.liquidity = amm.liquidity.try_sub(&liquidity)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add `liquidity` starting at `tick`, or ending there if `upper`, or remove it if not `add`.
+    /// Ticks are initialized on first use and cleared once no position refers to them.
+    fn update_tick(
+        amm: &mut ConcentratedAmm<T>,
+        tick: i32,
+        liquidity: u128,
+        upper: bool,
+        add: bool,
+    ) -> DispatchResult {
+        let net = i128::try_from(liquidity).map_err(|_| ArithmeticError::Overflow)?;
+        let net = if upper == add { -net } else { net };
+
+        match amm.ticks.binary_search_by_key(&tick, |(tick, _)| *tick) {
+            Ok(index) => {
+                let mut cleared = false;
+                if let Some((_, info)) = amm.ticks.iter_mut().nth(index) {
+                    info.liquidity_gross = if add {
+                        info.liquidity_gross.try_add(&liquidity)?
+                    } else {
+                        info.liquidity_gross.try_sub(&liquidity)?
+                    };
+                    info.liquidity_net = info.liquidity_net.try_add(&net)?;
+                    cleared = info.liquidity_gross.is_zero();
+                }
+                if cleared {
+                    amm.ticks.remove(index);
+                }
+            }
+            Err(index) => {
+                ensure!(add, ArithmeticError::Underflow);
+                // By convention, all fees so far were earned below the current tick.
+                let fee_growths_outside = if tick <= amm.tick {
+                    amm.fee_growths
+                } else {
+                    Default::default()
+                };
+                let info = Tick {
+                    liquidity_gross: liquidity,
+                    liquidity_net: net,
+                    fee_growths_outside,
+                };
+                amm.ticks
+                    .try_insert(index, (tick, info))
+                    .map_err(|_| Error::<T>::TooManyTicks)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Swap against `amm`, crossing as many ticks as needed, and return the input including fees,
+    /// the output and the fees. `amount` is the input if `exact_in`, or the output otherwise.
+    pub(crate) fn concentrated_swap(
+        amm: &mut ConcentratedAmm<T>,
+        index_in: usize,
+        amount: T::Balance,
+        exact_in: bool,
+    ) -> Result<(T::Balance, T::Balance, SwapFees<T::Balance>), DispatchError> {
+        let zero_for_one = index_in == 0;
+        let limit_tick = if zero_for_one { MIN_TICK } else { MAX_TICK };
+        let limit_price = sqrt_price_at_tick(limit_tick)?;
+        let fees_bps: U256 = amm.fees_bps.into();
+
+        let mut remaining: U256 = amount.into();
+        let mut input = U256::zero();
+        let mut output = U256::zero();
+        let mut lp_fees = U256::zero();
+        let mut protocol_fees = U256::zero();
+        while !remaining.is_zero() {
+            ensure!(amm.sqrt_price != limit_price, Error::<T>::InsufficientLiquidity);
+
+            // The closest initialized tick in the direction of the swap, where liquidity changes.
+            let next = if zero_for_one {
+                amm.ticks.iter().rposition(|(tick, _)| *tick <= amm.tick)
+            } else {
+                amm.ticks.iter().position(|(tick, _)| *tick > amm.tick)
+            };
+            let next_tick = next.map_or(limit_tick, |index| amm.ticks[index].0);
+            let target = sqrt_price_at_tick(next_tick)?;
+
+            let step = swap_step(
+                amm.sqrt_price,
+                target,
+                amm.liquidity.into(),
+                remaining,
+                exact_in,
+                fees_bps,
+            )?;
+            let step_input = step
+                .amount_in
+                .checked_add(step.fee)
+                .ok_or(ArithmeticError::Overflow)?;
+            let consumed = if exact_in { step_input } else { step.amount_out };
+            remaining = remaining
+                .checked_sub(consumed)
+                .ok_or(ArithmeticError::Underflow)?;
+            input = input.checked_add(step_input).ok_or(ArithmeticError::Overflow)?;
+            output = output
+                .checked_add(step.amount_out)
+                .ok_or(ArithmeticError::Overflow)?;
+
+            let protocol_fee = Self::protocol_share(step.fee);
+            let lp_fee = step.fee - protocol_fee;
+            lp_fees = lp_fees.checked_add(lp_fee).ok_or(ArithmeticError::Overflow)?;
+            protocol_fees = protocol_fees
+                .checked_add(protocol_fee)
+                .ok_or(ArithmeticError::Overflow)?;
+            if amm.liquidity > 0 {
+                let growth = fee_growth(lp_fee, amm.liquidity.into())?;
+                amm.fee_growths[index_in] = amm.fee_growths[index_in].overflowing_add(growth).0;
+            }
+
+            amm.sqrt_price = step.sqrt_price;
+            match next {
+                Some(index) if step.sqrt_price == target => {
+                    let fee_growths = amm.fee_growths;
+                    let mut net = 0;
+                    if let Some((_, info)) = amm.ticks.iter_mut().nth(index) {
+                        for (outside, global) in
+                            info.fee_growths_outside.iter_mut().zip(fee_growths.iter())
+                        {
+                            *outside = global.overflowing_sub(*outside).0;
+                        }
+                        net = info.liquidity_net;
+                    }
+
+                    // Positions starting at the tick become active when crossing it upwards, and
+                    // those ending there when crossing it downwards.
+                    let net = if zero_for_one { -net } else { net };
+                    amm.liquidity = if net < 0 {
+                        amm.liquidity.try_sub(&net.unsigned_abs())?
+                    } else {
+                        amm.liquidity.try_add(&net.unsigned_abs())?
+                    };
+                    amm.tick = if zero_for_one { next_tick - 1 } else { next_tick };
+                }
+                _ => amm.tick = tick_at_sqrt_price(amm.sqrt_price)?,
+            }
+        }
+
+        let fees = SwapFees {
+            lp: from_u256(lp_fees)?,
+            protocol: from_u256(protocol_fees)?,
+        };
+        Ok((from_u256(input)?, from_u256(output)?, fees))
+    }
+}
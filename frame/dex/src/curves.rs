@@ -0,0 +1,689 @@
+//! Invariants of the bonding curves supported by AMMs, computed over widened integers so that
+//! intermediate products of reserves do not overflow the pallet's balance type.
+
+use sp_core::U256;
+use sp_runtime::{
+    ArithmeticError::{self, *},
+    DispatchError,
+};
+
+/// Maximum number of Newton iterations before giving up on convergence.
+const MAX_ITERATIONS: usize = 255;
+
+fn add(a: U256, b: U256) -> Result<U256, ArithmeticError> {
+    a.checked_add(b).ok_or(Overflow)
+}
+
+fn sub(a: U256, b: U256) -> Result<U256, ArithmeticError> {
+    a.checked_sub(b).ok_or(Underflow)
+}
+
+fn mul(a: U256, b: U256) -> Result<U256, ArithmeticError> {
+    a.checked_mul(b).ok_or(Overflow)
+}
+
+fn div(a: U256, b: U256) -> Result<U256, ArithmeticError> {
+    a.checked_div(b).ok_or(DivisionByZero)
+}
+
+fn div_ceil(a: U256, b: U256) -> Result<U256, ArithmeticError> {
+    let quotient = div(a, b)?;
+    if mul(quotient, b)? == a {
+        Ok(quotient)
+    } else {
+        add(quotient, U256::one())
+    }
+}
+
+fn converged(a: U256, b: U256) -> bool {
+    let diff = if a > b { a - b } else { b - a };
+    diff <= U256::one()
+}
+
+/// Uniswap-style constant product invariant between two reserves, `x * y = k`.
+pub mod constant_product {
+    use super::*;
+    use sp_core::U512;
+
+    /// Compute the part of `amount` to swap into the other asset of a pool holding `reserve` of
+    /// the deposited asset, so that the rest and the swap output are in the pool's new ratio.
+    ///
+    /// With `g = 1 - fees`, swapping `s` yields a balanced deposit when
+    /// `g * s^2 + (1 + g) * reserve * s - amount * reserve = 0`, whose positive root is rounded
+    /// down.
+    pub fn single_sided_swap(
+        reserve: U256,
+        amount: U256,
+        fees_bps: U256,
+    ) -> Result<U256, ArithmeticError> {
+        let full = U256::from(10_000);
+        let net = sub(full, fees_bps)?;
+
+        // Multiplying the equation by 10000 keeps all coefficients integers.
+        let b = mul(reserve, add(full, net)?)?;
+        let c = mul(mul(full, net)?, mul(amount, reserve)?)?;
+        let discriminant = add(mul(b, b)?, mul(c, U256::from(4))?)?;
+
+        div(sub(discriminant.integer_sqrt(), b)?, mul(net, U256::from(2))?)
+    }
+
+    /// Compute how much of the asset a batch of swaps sells in excess to swap against a pool
+    /// holding `reserve_in` of it and `reserve_out` of the other asset, so that the whole batch
+    /// clears at a single price. `sold_in` and `sold_out` are the total amounts of each asset the
+    /// batch sells; what isn't swapped against the pool is matched between sellers.
+    ///
+    /// With `g = 1 - fees`, swapping `d` against the pool yields the same price as the sellers of
+    /// the other asset get when
+    /// `g * reserve_out / (reserve_in + g * d) = sold_out / (sold_in - d)`, so
+    /// `d = (g * reserve_out * sold_in - reserve_in * sold_out) / (g * (reserve_out + sold_out))`
+    /// rounded down. It is zero when `sold_out` covers `sold_in` at the pool's price.
+    pub fn batch_net_input(
+        reserve_in: U256,
+        reserve_out: U256,
+        sold_in: U256,
+        sold_out: U256,
+        fees_bps: U256,
+    ) -> Result<U256, ArithmeticError> {
+        let full = U512::from(10_000);
+        let net = full.checked_sub(U512::from(fees_bps)).ok_or(Underflow)?;
+        let mul = |a: U512, b: U256| a.checked_mul(U512::from(b)).ok_or(Overflow);
+
+        // Multiplying both terms by 10000 keeps them integers.
+        let excess = mul(mul(net, reserve_out)?, sold_in)?;
+        let matched = mul(mul(full, reserve_in)?, sold_out)?;
+        if excess <= matched {
+            return Ok(U256::zero());
+        }
+
+        let denominator = mul(net, add(reserve_out, sold_out)?)?;
+        let amount = (excess - matched).checked_div(denominator).ok_or(DivisionByZero)?;
+        U256::try_from(amount).map_err(|_| Overflow)
+    }
+
+    /// Compute the invariant `k` of a pool holding `reserves`, the product of all of them. It is
+    /// taken over 512 bits, so that it can't overflow for up to four 128-bit reserves.
+    pub fn invariant(reserves: &[U256]) -> Result<U512, ArithmeticError> {
+        reserves.iter().try_fold(U512::one(), |acc, x| {
+            acc.checked_mul(U512::from(*x)).ok_or(Overflow)
+        })
+    }
+
+    /// Compute the geometric mean of `amounts`, rounded down. For two assets, this is the
+    /// `sqrt(x * y)` Uniswap v2 mints as shares for the first deposit into a pool.
+    pub fn geometric_mean(amounts: &[U256]) -> Result<U256, ArithmeticError> {
+        let n = amounts.len();
+        let product = invariant(amounts)?;
+        let root = if n < 2 || product.is_zero() {
+            product
+        } else if n == 2 {
+            product.integer_sqrt()
+        } else {
+            nth_root(product, n)
+        };
+
+        U256::try_from(root).map_err(|_| Overflow)
+    }
+
+    /// Newton's method for the `n`-th root of `value`, decreasing from above it until it settles.
+    fn nth_root(value: U512, n: usize) -> U512 {
+        let n_u512 = U512::from(n);
+        let mut root = U512::one() << ((value.bits() + n - 1) / n);
+        loop {
+            let power = (2..n).try_fold(root, |acc, _| acc.checked_mul(root));
+            let quotient = power.map_or(U512::zero(), |power| value / power);
+            // Both terms are below `n * root`, which is far from overflowing.
+            let next = (root * (n_u512 - 1) + quotient) / n_u512;
+            if next >= root {
+                return root;
+            }
+            root = next;
+        }
+    }
+}
+
+/// Curve-style StableSwap invariant, which behaves like a constant sum near the balanced point and
+/// like a constant product away from it:
+///
+/// `A * n^n * sum(x_i) + D = A * D * n^n + D^(n + 1) / (n^n * prod(x_i))`
+///
+/// As in Curve's implementation, `amplification` is the coefficient `A * n^(n - 1)`.
+pub mod stable_swap {
+    use super::*;
+    use sp_core::U512;
+
+    /// Compute the invariant `D` of a pool holding `reserves`.
+    pub fn invariant(reserves: &[U256], amplification: u32) -> Result<U256, DispatchError> {
+        let n = U256::from(reserves.len());
+        let sum = reserves.iter().try_fold(U256::zero(), |acc, x| add(acc, *x))?;
+        if sum.is_zero() {
+            return Ok(U256::zero());
+        }
+
+        let ann = mul(U256::from(amplification), n)?;
+        let mut d = sum;
+        for _ in 0..MAX_ITERATIONS {
+            // d_p = D^(n + 1) / (n^n * prod(x_i))
+            let mut d_p = d;
+            for x in reserves {
+                d_p = div(mul(d_p, d)?, mul(*x, n)?)?;
+            }
+
+            let previous = d;
+            // D = (Ann * S + D_P * n) * D / ((Ann - 1) * D + (n + 1) * D_P)
+            let numerator = mul(add(mul(ann, sum)?, mul(d_p, n)?)?, d)?;
+            let denominator = add(
+                mul(sub(ann, U256::one())?, d)?,
+                mul(add(n, U256::one())?, d_p)?,
+            )?;
+            d = div(numerator, denominator)?;
+
+            if converged(d, previous) {
+                return Ok(d);
+            }
+        }
+
+        Err(DispatchError::Other("StableSwap invariant did not converge"))
+    }
+
+    /// Compute the reserve of the asset at `index` that keeps the invariant at `d`, given the
+    /// reserves of every other asset. The value of `reserves[index]` is ignored.
+    pub fn reserve(
+        reserves: &[U256],
+        index: usize,
+        d: U256,
+        amplification: u32,
+    ) -> Result<U256, DispatchError> {
+        let n = U256::from(reserves.len());
+        let ann = mul(U256::from(amplification), n)?;
+
+        // c = D^(n + 1) / (n^n * prod(x_j) * Ann * n), b = S' + D / Ann, over all j != index
+        let mut c = d;
+        let mut sum = U256::zero();
+        for (_, x) in reserves.iter().enumerate().filter(|(j, _)| *j != index) {
+            sum = add(sum, *x)?;
+            c = div(mul(c, d)?, mul(*x, n)?)?;
+        }
+        c = div(mul(c, d)?, mul(ann, n)?)?;
+        let b = add(sum, div(d, ann)?)?;
+
+        // Newton's method on y^2 + (b - D) * y = c
+        let mut y = d;
+        for _ in 0..MAX_ITERATIONS {
+            let previous = y;
+            y = div(
+                add(mul(y, y)?, c)?,
+                sub(add(mul(y, U256::from(2))?, b)?, d)?,
+            )?;
+
+            if converged(y, previous) {
+                return Ok(y);
+            }
+        }
+
+        Err(DispatchError::Other("StableSwap reserve did not converge"))
+    }
+
+    /// Compute the marginal price of the asset at `index` in terms of the asset at `quote_index`,
+    /// scaled by `unit`. This is the ratio of the partial derivatives of the invariant by each
+    /// reserve, `(Ann + D_P / x_i) / (Ann + D_P / x_j)` with `D_P = D^(n + 1) / (n^n * prod(x))`,
+    /// which tends to one near the balanced point and to `x_j / x_i` away from it.
+    pub fn spot_price(
+        reserves: &[U256],
+        index: usize,
+        quote_index: usize,
+        amplification: u32,
+        unit: U256,
+    ) -> Result<U256, DispatchError> {
+        let n = U256::from(reserves.len());
+        let ann = mul(U256::from(amplification), n)?;
+        let d = invariant(reserves, amplification)?;
+        let mut d_p = d;
+        for x in reserves {
+            d_p = div(mul(d_p, d)?, mul(*x, n)?)?;
+        }
+
+        // price = unit * x_j * (Ann * x_i + D_P) / (x_i * (Ann * x_j + D_P))
+        let (x_i, x_j) = (reserves[index], reserves[quote_index]);
+        let wide_mul = |a: U256, b: U256| U512::from(a).checked_mul(U512::from(b)).ok_or(Overflow);
+        let numerator = wide_mul(x_j, add(mul(ann, x_i)?, d_p)?)?
+            .checked_mul(U512::from(unit))
+            .ok_or(Overflow)?;
+        let denominator = wide_mul(x_i, add(mul(ann, x_j)?, d_p)?)?;
+        let price = numerator.checked_div(denominator).ok_or(DivisionByZero)?;
+
+        U256::try_from(price).map_err(|_| Overflow.into())
+    }
+}
+
+/// Balancer-style weighted invariant, `prod(x_i ^ w_i) = V`, which keeps the value of each asset
+/// in the pool at a fixed share `w_i / sum(w_j)` of the total.
+///
+/// Non-integer powers are computed over fixed-point numbers with 18 decimals: whole exponents by
+/// squaring and the fractional remainder with a binomial series. The series only converges quickly
+/// for bases close to one, so callers must bound trade sizes relative to the reserves.
+pub mod weighted {
+    use super::*;
+
+    /// Terms of the binomial series smaller than this are dropped, which bounds the error of
+    /// [`pow`] to about `1e-14`.
+    const PRECISION: u64 = 10_000;
+
+    fn one() -> U256 {
+        U256::exp10(18)
+    }
+
+    fn mul_fixed(a: U256, b: U256) -> Result<U256, ArithmeticError> {
+        div(mul(a, b)?, one())
+    }
+
+    fn div_fixed(a: U256, b: U256) -> Result<U256, ArithmeticError> {
+        div(mul(a, one())?, b)
+    }
+
+    fn div_fixed_ceil(a: U256, b: U256) -> Result<U256, ArithmeticError> {
+        div_ceil(mul(a, one())?, b)
+    }
+
+    fn powi(mut base: U256, mut exponent: U256) -> Result<U256, ArithmeticError> {
+        let mut result = one();
+        while !exponent.is_zero() {
+            if exponent.bit(0) {
+                result = mul_fixed(result, base)?;
+            }
+            exponent = exponent / U256::from(2);
+            if !exponent.is_zero() {
+                base = mul_fixed(base, base)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// `base ^ exponent` for a fractional `exponent` in `[0, 1)`, with `base` in `(0, 2)`.
+    fn pow_fraction(base: U256, exponent: U256) -> Result<U256, DispatchError> {
+        // (1 + x) ^ a = sum(k >= 0, (a choose k) * x ^ k), where each term is the previous one
+        // multiplied by (a - k + 1) * x / k.
+        let (x, x_negative) = if base >= one() {
+            (base - one(), false)
+        } else {
+            (one() - base, true)
+        };
+        let mut term = one();
+        let mut sum = one();
+        let mut negative = false;
+        for k in 1..=MAX_ITERATIONS {
+            let big_k = mul(U256::from(k), one())?;
+            let previous_k = sub(big_k, one())?;
+            let (c, c_negative) = if exponent >= previous_k {
+                (exponent - previous_k, false)
+            } else {
+                (previous_k - exponent, true)
+            };
+            term = div_fixed(mul_fixed(term, mul_fixed(c, x)?)?, big_k)?;
+            if term.is_zero() {
+                return Ok(sum);
+            }
+
+            if x_negative {
+                negative = !negative;
+            }
+            if c_negative {
+                negative = !negative;
+            }
+            sum = if negative { sub(sum, term)? } else { add(sum, term)? };
+
+            if term < U256::from(PRECISION) {
+                return Ok(sum);
+            }
+        }
+
+        Err(DispatchError::Other("Weighted power did not converge"))
+    }
+
+    /// `base ^ exponent`, both fixed-point numbers with 18 decimals. `base` must be in `(0, 2)`.
+    pub fn pow(base: U256, exponent: U256) -> Result<U256, DispatchError> {
+        if base.is_zero() || base >= mul(one(), U256::from(2))? {
+            return Err(DispatchError::Other("Weighted power base out of range"));
+        }
+
+        let whole = div(exponent, one())?;
+        let fraction = exponent % one();
+        let whole_pow = powi(base, whole)?;
+        if fraction.is_zero() {
+            return Ok(whole_pow);
+        }
+
+        Ok(mul_fixed(whole_pow, pow_fraction(base, fraction)?)?)
+    }
+
+    /// [`pow`] plus a bound on its error, so that the result is never an underestimate.
+    fn pow_up(base: U256, exponent: U256) -> Result<U256, DispatchError> {
+        let power = pow(base, exponent)?;
+        // The series' error is relative to the fractional power, which is less than two, and
+        // is then scaled by the whole power.
+        let relative_error = div(mul(power, U256::from(2 * PRECISION))?, one())?;
+        Ok(add(add(power, relative_error)?, U256::from(PRECISION))?)
+    }
+
+    /// Amount of output asset to send back for `input_amount` of net input, rounded down:
+    ///
+    /// `out = output_reserve * (1 - (input_reserve / (input_reserve + in)) ^ (w_in / w_out))`
+    pub fn output(
+        input_reserve: U256,
+        input_weight: U256,
+        output_reserve: U256,
+        output_weight: U256,
+        input_amount: U256,
+    ) -> Result<U256, DispatchError> {
+        // Round the base and the power up and the exponent down, all against the trader.
+        let base = div_fixed_ceil(input_reserve, add(input_reserve, input_amount)?)?;
+        let exponent = div_fixed(input_weight, output_weight)?;
+        let power = pow_up(base, exponent)?;
+
+        Ok(mul_fixed(output_reserve, one().saturating_sub(power))?)
+    }
+
+    /// Amount of net input asset needed to get `output_amount` back, rounded up:
+    ///
+    /// `in = input_reserve * ((output_reserve / (output_reserve - out)) ^ (w_out / w_in) - 1)`
+    pub fn input(
+        output_reserve: U256,
+        output_weight: U256,
+        input_reserve: U256,
+        input_weight: U256,
+        output_amount: U256,
+    ) -> Result<U256, DispatchError> {
+        // Ask for more output than needed to make up for what `output` rounds away, bounded by
+        // three times the error of the power plus two units lost to integer division.
+        let margin = div(mul(output_reserve, U256::from(3 * PRECISION))?, one())?;
+        let output_amount = add(add(output_amount, margin)?, U256::from(2))?;
+
+        // Round the base, the exponent and the power up, all against the trader.
+        let base = div_fixed_ceil(output_reserve, sub(output_reserve, output_amount)?)?;
+        let exponent = div_fixed_ceil(output_weight, input_weight)?;
+        let power = pow_up(base, exponent)?;
+
+        Ok(div_ceil(mul(input_reserve, sub(power, one())?)?, one())?)
+    }
+}
+
+/// Uniswap v3-style concentrated liquidity, where the price of the first asset in terms of the
+/// second one is `1.0001 ^ tick`. Within a range of ticks, liquidity `L` behaves like constant
+/// product reserves `x * y = L^2` with `sqrt(p) = y / x`, only holding enough of each asset to
+/// trade across that range.
+///
+/// Square roots of prices are fixed-point numbers with 96 fractional bits. Amounts are rounded in
+/// favour of the pool.
+pub mod concentrated {
+    use super::*;
+    use sp_core::U512;
+
+    /// The lowest tick a position can start at, with a price of about `2.9e-39`.
+    pub const MIN_TICK: i32 = -887_272;
+    /// The highest tick a position can end at, with a price of about `3.4e38`.
+    pub const MAX_TICK: i32 = 887_272;
+
+    /// `2^128 / 1.0001 ^ (2^i / 2)` for each bit `i` of a tick, rounded up.
+    const TICK_FACTORS: [u128; 20] = [
+        0xfffcb933bd6fad37aa2d162d1a594001,
+        0xfff97272373d413259a46990580e213a,
+        0xfff2e50f5f656932ef12357cf3c7fdcc,
+        0xffe5caca7e10e4e61c3624eaa0941cd0,
+        0xffcb9843d60f6159c9db58835c926644,
+        0xff973b41fa98c081472e6896dfb254c0,
+        0xff2ea16466c96a3843ec78b326b52861,
+        0xfe5dee046a99a2a811c461f1969c3053,
+        0xfcbe86c7900a88aedcffc83b479aa3a4,
+        0xf987a7253ac413176f2b074cf7815e54,
+        0xf3392b0822b70005940c7a398e4b70f3,
+        0xe7159475a2c29b7443b29c7fa6e889d9,
+        0xd097f3bdfd2022b8845ad8f792aa5825,
+        0xa9f746462d870fdf8a65dc1f90e061e5,
+        0x70d869a156d2a1b890bb3df62baf32f7,
+        0x31be135f97d08fd981231505542fcfa6,
+        0x9aa508b5b7a84e1c677de54f3e99bc9,
+        0x5d6af8dedb81196699c329225ee604,
+        0x2216e584f5fa1ea926041bedfe98,
+        0x48a170391f7dc42444e8fa2,
+    ];
+
+    fn q96() -> U256 {
+        U256::one() << 96
+    }
+
+    /// `a * b / c`, without overflowing on the intermediate product.
+    fn mul_div(a: U256, b: U256, c: U256) -> Result<U256, ArithmeticError> {
+        if c.is_zero() {
+            return Err(DivisionByZero);
+        }
+        U256::try_from(a.full_mul(b) / U512::from(c)).map_err(|_| Overflow)
+    }
+
+    /// Same as `mul_div`, but rounding the quotient up instead of down.
+    fn mul_div_ceil(a: U256, b: U256, c: U256) -> Result<U256, ArithmeticError> {
+        if c.is_zero() {
+            return Err(DivisionByZero);
+        }
+        let (quotient, remainder) = a.full_mul(b).div_mod(U512::from(c));
+        let quotient = U256::try_from(quotient).map_err(|_| Overflow)?;
+        if remainder.is_zero() {
+            Ok(quotient)
+        } else {
+            add(quotient, U256::one())
+        }
+    }
+
+    /// Square root of the price at `tick`.
+    pub fn sqrt_price_at_tick(tick: i32) -> Result<U256, ArithmeticError> {
+        let abs_tick = tick.unsigned_abs();
+        if abs_tick > MAX_TICK.unsigned_abs() {
+            return Err(Overflow);
+        }
+
+        // 1.0001 ^ (-|tick| / 2) with 128 fractional bits
+        let mut ratio = U256::one() << 128;
+        for (bit, factor) in TICK_FACTORS.iter().enumerate() {
+            if abs_tick & (1 << bit) != 0 {
+                ratio = mul(ratio, U256::from(*factor))? >> 128;
+            }
+        }
+        if tick > 0 {
+            ratio = U256::max_value() / ratio;
+        }
+
+        // Round up, so that the tick at the resulting price is `tick` itself.
+        let sqrt_price = ratio >> 32;
+        if (ratio & U256::from(u32::MAX)).is_zero() {
+            Ok(sqrt_price)
+        } else {
+            add(sqrt_price, U256::one())
+        }
+    }
+
+    /// The greatest tick whose price is at most the one of `sqrt_price`.
+    pub fn tick_at_sqrt_price(sqrt_price: U256) -> Result<i32, ArithmeticError> {
+        let (mut low, mut high) = (MIN_TICK, MAX_TICK);
+        while low < high {
+            let middle = low + (high - low + 1) / 2;
+            if sqrt_price_at_tick(middle)? <= sqrt_price {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        Ok(low)
+    }
+
+    /// Amount of the first asset backing `liquidity` between two prices:
+    ///
+    /// `L * (sqrt(p_upper) - sqrt(p_lower)) / (sqrt(p_upper) * sqrt(p_lower))`
+    pub fn amount_0_delta(
+        lower: U256,
+        upper: U256,
+        liquidity: U256,
+        round_up: bool,
+    ) -> Result<U256, ArithmeticError> {
+        let numerator = mul(liquidity, q96())?;
+        let difference = sub(upper, lower)?;
+        if round_up {
+            div_ceil(mul_div_ceil(numerator, difference, upper)?, lower)
+        } else {
+            div(mul_div(numerator, difference, upper)?, lower)
+        }
+    }
+
+    /// Amount of the second asset backing `liquidity` between two prices:
+    ///
+    /// `L * (sqrt(p_upper) - sqrt(p_lower))`
+    pub fn amount_1_delta(
+        lower: U256,
+        upper: U256,
+        liquidity: U256,
+        round_up: bool,
+    ) -> Result<U256, ArithmeticError> {
+        let difference = sub(upper, lower)?;
+        if round_up {
+            mul_div_ceil(liquidity, difference, q96())
+        } else {
+            mul_div(liquidity, difference, q96())
+        }
+    }
+
+    /// Liquidity that `amount` of the first asset can back between two prices, rounded down.
+    pub fn liquidity_for_amount_0(
+        lower: U256,
+        upper: U256,
+        amount: U256,
+    ) -> Result<U256, ArithmeticError> {
+        mul_div(amount, mul_div(lower, upper, q96())?, sub(upper, lower)?)
+    }
+
+    /// Liquidity that `amount` of the second asset can back between two prices, rounded down.
+    pub fn liquidity_for_amount_1(
+        lower: U256,
+        upper: U256,
+        amount: U256,
+    ) -> Result<U256, ArithmeticError> {
+        mul_div(amount, q96(), sub(upper, lower)?)
+    }
+
+    /// Fees earned by `liquidity` over a growth of fees per unit of liquidity, which has 128
+    /// fractional bits.
+    pub fn fees(fee_growth: U256, liquidity: U256) -> Result<U256, ArithmeticError> {
+        mul_div(fee_growth, liquidity, U256::one() << 128)
+    }
+
+    /// Growth of fees per unit of liquidity from charging `fee` on `liquidity`.
+    pub fn fee_growth(fee: U256, liquidity: U256) -> Result<U256, ArithmeticError> {
+        mul_div(fee, U256::one() << 128, liquidity)
+    }
+
+    /// Price after adding `amount` of the first asset if `zero_for_one`, or of the second asset
+    /// otherwise.
+    fn next_sqrt_price_from_input(
+        sqrt_price: U256,
+        liquidity: U256,
+        amount: U256,
+        zero_for_one: bool,
+    ) -> Result<U256, ArithmeticError> {
+        if zero_for_one {
+            // sqrt(p') = L * sqrt(p) / (L + amount * sqrt(p)), rounded up
+            let numerator = mul(liquidity, q96())?;
+            let denominator = add(numerator, mul(amount, sqrt_price)?)?;
+            mul_div_ceil(numerator, sqrt_price, denominator)
+        } else {
+            // sqrt(p') = sqrt(p) + amount / L, rounded down
+            add(sqrt_price, mul_div(amount, q96(), liquidity)?)
+        }
+    }
+
+    /// Price after taking `amount` of the second asset out if `zero_for_one`, or of the first
+    /// asset otherwise.
+    fn next_sqrt_price_from_output(
+        sqrt_price: U256,
+        liquidity: U256,
+        amount: U256,
+        zero_for_one: bool,
+    ) -> Result<U256, ArithmeticError> {
+        if zero_for_one {
+            // sqrt(p') = sqrt(p) - amount / L, rounded down
+            sub(sqrt_price, mul_div_ceil(amount, q96(), liquidity)?)
+        } else {
+            // sqrt(p') = L * sqrt(p) / (L - amount * sqrt(p)), rounded up
+            let numerator = mul(liquidity, q96())?;
+            let denominator = sub(numerator, mul(amount, sqrt_price)?)?;
+            mul_div_ceil(numerator, sqrt_price, denominator)
+        }
+    }
+
+    /// Outcome of swapping within a range of constant liquidity.
+    pub struct SwapStep {
+        /// Square root of the price after the step.
+        pub sqrt_price: U256,
+        /// Input amount, excluding fees.
+        pub amount_in: U256,
+        pub amount_out: U256,
+        /// Fees charged on top of `amount_in`.
+        pub fee: U256,
+    }
+
+    /// Swap as much of `amount` as possible without moving the price past `target`, with
+    /// `amount` being the input including fees if `exact_in`, or the output otherwise. The
+    /// direction of the swap is given by whether `target` is below the current price.
+    pub fn swap_step(
+        sqrt_price: U256,
+        target: U256,
+        liquidity: U256,
+        amount: U256,
+        exact_in: bool,
+        fees_bps: U256,
+    ) -> Result<SwapStep, ArithmeticError> {
+        let zero_for_one = target <= sqrt_price;
+        let full_bps = U256::from(10_000);
+        let net_bps = sub(full_bps, fees_bps)?;
+
+        // Amount of input, or output, needed to move the price all the way to `target`.
+        let amount_to_target = match (exact_in, zero_for_one) {
+            (true, true) => amount_0_delta(target, sqrt_price, liquidity, true)?,
+            (true, false) => amount_1_delta(sqrt_price, target, liquidity, true)?,
+            (false, true) => amount_1_delta(target, sqrt_price, liquidity, false)?,
+            (false, false) => amount_0_delta(sqrt_price, target, liquidity, false)?,
+        };
+        let net_amount = if exact_in { div(mul(amount, net_bps)?, full_bps)? } else { amount };
+        let next = if net_amount >= amount_to_target {
+            target
+        } else if exact_in {
+            next_sqrt_price_from_input(sqrt_price, liquidity, net_amount, zero_for_one)?
+        } else {
+            next_sqrt_price_from_output(sqrt_price, liquidity, net_amount, zero_for_one)?
+        };
+
+        let (amount_in, mut amount_out) = if zero_for_one {
+            (
+                amount_0_delta(next, sqrt_price, liquidity, true)?,
+                amount_1_delta(next, sqrt_price, liquidity, false)?,
+            )
+        } else {
+            (
+                amount_1_delta(sqrt_price, next, liquidity, true)?,
+                amount_0_delta(sqrt_price, next, liquidity, false)?,
+            )
+        };
+        if !exact_in && amount_out > amount {
+            amount_out = amount;
+        }
+
+        // Whatever is left of the input once the price stops short of `target` goes to fees.
+        let fee = if exact_in && next != target {
+            sub(amount, amount_in)?
+        } else {
+            div_ceil(mul(amount_in, fees_bps)?, net_bps)?
+        };
+
+        Ok(SwapStep {
+            sqrt_price: next,
+            amount_in,
+            amount_out,
+            fee,
+        })
+    }
+}
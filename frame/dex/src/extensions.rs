@@ -0,0 +1,113 @@
+//! Transaction pool integration for the deadlines of DEX calls.
+
+use crate::{Call, Config};
+use codec::{Decode, Encode};
+use frame_support::traits::IsSubType;
+use scale_info::TypeInfo;
+use sp_runtime::{
+    traits::{DispatchInfoOf, SaturatedConversion, SignedExtension},
+    transaction_validity::{
+        InvalidTransaction, TransactionValidity, TransactionValidityError, ValidTransaction,
+    },
+};
+use sp_std::{fmt::Debug, marker::PhantomData};
+
+impl<T: Config> Call<T> {
+    /// The last block the call can be executed in, if it was given a deadline.
+    pub fn deadline(&self) -> Option<T::BlockNumber> {
+        match self {
+            Call::provide_liquidity { deadline, .. }
+            | Call::withdraw { deadline, .. }
+            | Call::provide_liquidity_single { deadline, .. }
+            | Call::withdraw_single { deadline, .. }
+            | Call::swap { deadline, .. }
+            | Call::swap_between { deadline, .. }
+            | Call::swap_pair { deadline, .. }
+            | Call::swap_exact_out { deadline, .. }
+            | Call::swap_route { deadline, .. } => *deadline,
+            _ => None,
+        }
+    }
+}
+
+/// Drops DEX calls from the transaction pool once their deadline has passed, instead of including
+/// them only to fail with `DeadlineExpired`.
+///
+/// Transactions with a deadline are only valid until then, and those past it are rejected as stale
+/// by the pool and by block authors.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct CheckDeadline<T: Config + Send + Sync>(PhantomData<T>);
+
+impl<T: Config + Send + Sync> CheckDeadline<T> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: Config + Send + Sync> Default for CheckDeadline<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Config + Send + Sync> Debug for CheckDeadline<T> {
+    #[cfg(feature = "std")]
+    fn fmt(&self, f: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+        write!(f, "CheckDeadline")
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn fmt(&self, _: &mut sp_std::fmt::Formatter) -> sp_std::fmt::Result {
+        Ok(())
+    }
+}
+
+impl<T: Config + Send + Sync> SignedExtension for CheckDeadline<T>
+where
+    <T as frame_system::Config>::Call: IsSubType<Call<T>>,
+{
+    const IDENTIFIER: &'static str = "CheckDeadline";
+    type AccountId = T::AccountId;
+    type Call = <T as frame_system::Config>::Call;
+    type AdditionalSigned = ();
+    type Pre = ();
+
+    fn additional_signed(&self) -> Result<(), TransactionValidityError> {
+        Ok(())
+    }
+
+    fn validate(
+        &self,
+        _who: &Self::AccountId,
+        call: &Self::Call,
+        _info: &DispatchInfoOf<Self::Call>,
+        _len: usize,
+    ) -> TransactionValidity {
+        let deadline = match call.is_sub_type().and_then(Call::<T>::deadline) {
+            Some(deadline) => deadline,
+            None => return Ok(ValidTransaction::default()),
+        };
+
+        // Transactions are validated as if included in the next block.
+        let now = frame_system::Pallet::<T>::block_number();
+        if now > deadline {
+            return InvalidTransaction::Stale.into();
+        }
+
+        Ok(ValidTransaction {
+            longevity: (deadline - now).saturated_into::<u64>().saturating_add(1),
+            ..Default::default()
+        })
+    }
+
+    fn pre_dispatch(
+        self,
+        who: &Self::AccountId,
+        call: &Self::Call,
+        info: &DispatchInfoOf<Self::Call>,
+        len: usize,
+    ) -> Result<(), TransactionValidityError> {
+        self.validate(who, call, info, len).map(|_| ())
+    }
+}
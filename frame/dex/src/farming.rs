@@ -0,0 +1,139 @@
+//! Liquidity mining farms, which emit a reward asset to the accounts staking shares of an AMM.
+//!
+//! Rewards are tracked MasterChef-style: each farm accumulates the rewards emitted per staked share
+//! as a fixed-point number with 128 fractional bits, and each stake records the accumulator as of
+//! its last update. What a stake earned in between is its amount times the difference. Stakes keep
+//! the fractions of units they earned until they add up, so that claiming often loses nothing,
+//! and the growth of the accumulator is rounded down, so that stakers are never paid more than
+//! was emitted.
+
+use crate::{helpers::*, types::*, Config, Error, Farms, Pallet, Stakes};
+use frame_support::{
+    ensure,
+    pallet_prelude::{DispatchError, DispatchResult, Get},
+};
+use sp_core::U256;
+use sp_runtime::{
+    traits::{AccountIdConversion, Saturating, Zero},
+    ArithmeticError,
+};
+
+/// Growth of the rewards per staked share from emitting `rewards` to `staked` shares.
+fn reward_per_share_growth(rewards: U256, staked: U256) -> Result<U256, ArithmeticError> {
+    let scaled = rewards.checked_mul(U256::one() << 128).ok_or(ArithmeticError::Overflow)?;
+    scaled.checked_div(staked).ok_or(ArithmeticError::DivisionByZero)
+}
+
+/// Rewards earned by `amount` shares over a growth of the rewards per staked share, with 128
+/// fractional bits.
+fn earned(growth: U256, amount: U256) -> Result<U256, ArithmeticError> {
+    U256::try_from(growth.full_mul(amount)).map_err(|_| ArithmeticError::Overflow)
+}
+
+impl<T: Config> Pallet<T> {
+    /// The account holding the staked shares and the unclaimed rewards of a farm.
+    pub fn farm_account(farm_id: &FarmId) -> T::AccountId {
+        T::PalletId::get().into_sub_account_truncating((b"farm", farm_id))
+    }
+
+    /// The account out of which `T::AdminOrigin` funds farms. Anyone can add rewards to it, but
+    /// only the admin can spend them.
+    pub fn farm_rewards_account() -> T::AccountId {
+        T::PalletId::get().into_sub_account_truncating(b"rewards")
+    }
+
+    pub(crate) fn try_get_farm(farm_id: &FarmId) -> Result<Farm<T>, DispatchError> {
+        Farms::<T>::get(farm_id).ok_or_else(|| Error::<T>::InvalidFarmId.into())
+    }
+
+    /// Ensure a farm emitting from `start` to `end` still has blocks left to emit in.
+    pub(crate) fn ensure_valid_farm_period(
+        start: T::BlockNumber,
+        end: T::BlockNumber,
+    ) -> DispatchResult {
+        let now = frame_system::Pallet::<T>::block_number();
+        ensure!(start < end && now < end, Error::<T>::InvalidFarmPeriod);
+        Ok(())
+    }
+
+    /// Emit the rewards of a farm up to the current block, or up to its end if earlier. Nothing is
+    /// emitted while no shares are staked, and at most what is left of the rewards funded.
+    pub(crate) fn update_farm(farm: &mut Farm<T>) -> DispatchResult {
+        let now = frame_system::Pallet::<T>::block_number();
+        let until = now.min(farm.end);
+        if until <= farm.last_update {
+            return Ok(());
+        }
+
+        if !farm.total_staked.is_zero() {
+            let blocks = Self::blocks_to_balance(until - farm.last_update);
+            let rewards = farm.reward_per_block.saturating_mul(blocks).min(farm.unallocated);
+            let growth = reward_per_share_growth(rewards.into(), farm.total_staked.into())?;
+            farm.reward_per_share =
+                farm.reward_per_share.checked_add(growth).ok_or(ArithmeticError::Overflow)?;
+            farm.unallocated = farm.unallocated.try_sub(&rewards)?;
+        }
+        farm.last_update = until;
+
+        Ok(())
+    }
+
+    /// Add what a stake earned since its last update to its rewards owed, then change its amount
+    /// by `amount`, added if `add` or subtracted otherwise. The farm must have been updated first.
+    pub(crate) fn update_stake(
+        farm: &mut Farm<T>,
+        stake: &mut Stake<T>,
+        amount: T::Balance,
+        add: bool,
+    ) -> DispatchResult {
+        let growth = farm.reward_per_share.saturating_sub(stake.reward_per_share);
+        let rewards = earned(growth, stake.amount.into())?;
+        stake.rewards_owed =
+            stake.rewards_owed.checked_add(rewards).ok_or(ArithmeticError::Overflow)?;
+        stake.reward_per_share = farm.reward_per_share;
+
+        if add {
+            stake.amount = stake.amount.try_add(&amount)?;
+            farm.total_staked = farm.total_staked.try_add(&amount)?;
+        } else {
+            ensure!(amount <= stake.amount, Error::<T>::InsufficientStake);
+            stake.amount = stake.amount.try_sub(&amount)?;
+            farm.total_staked = farm.total_staked.try_sub(&amount)?;
+        }
+
+        Ok(())
+    }
+
+    /// The whole units of rewards owed to a stake.
+    pub(crate) fn claimable_rewards(stake: &Stake<T>) -> Result<T::Balance, ArithmeticError> {
+        from_u256(stake.rewards_owed >> 128)
+    }
+
+    /// Take the whole units of rewards owed to a stake, leaving the fraction of a unit.
+    pub(crate) fn take_rewards(stake: &mut Stake<T>) -> Result<T::Balance, ArithmeticError> {
+        let rewards = Self::claimable_rewards(stake)?;
+        stake.rewards_owed = stake.rewards_owed - (U256::from(rewards) << 128);
+        Ok(rewards)
+    }
+
+    /// Store a stake, or remove it once it holds neither shares nor whole units of rewards.
+    pub(crate) fn save_stake(farm_id: FarmId, who: &T::AccountId, stake: Stake<T>) {
+        if stake.amount.is_zero() && (stake.rewards_owed >> 128).is_zero() {
+            Stakes::<T>::remove(farm_id, who);
+        } else {
+            Stakes::<T>::insert(farm_id, who, stake);
+        }
+    }
+
+    /// Rewards `who` could claim from a farm if it was updated in the current block.
+    pub fn pending_rewards(
+        farm_id: FarmId,
+        who: &T::AccountId,
+    ) -> Result<T::Balance, DispatchError> {
+        let mut farm = Self::try_get_farm(&farm_id)?;
+        let mut stake = Stakes::<T>::get(farm_id, who).ok_or(Error::<T>::InsufficientStake)?;
+        Self::update_farm(&mut farm)?;
+        Self::update_stake(&mut farm, &mut stake, Zero::zero(), true)?;
+        Ok(Self::claimable_rewards(&stake)?)
+    }
+}
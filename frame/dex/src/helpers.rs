@@ -1,5 +1,6 @@
+use sp_core::U256;
 use sp_runtime::{
-    traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub},
+    traits::{Bounded, CheckedAdd, CheckedDiv, CheckedSub},
     ArithmeticError::{self, *},
 };
 
@@ -9,12 +10,6 @@ pub trait TryAdd: CheckedAdd {
     }
 }
 
-pub trait TryMul: CheckedMul {
-    fn try_mul(&self, other: &Self) -> Result<Self, ArithmeticError> {
-        self.checked_mul(other).ok_or(Overflow)
-    }
-}
-
 pub trait TryDiv: CheckedDiv {
     fn try_div(&self, other: &Self) -> Result<Self, ArithmeticError> {
         self.checked_div(other).ok_or(DivisionByZero)
@@ -29,8 +24,81 @@ pub trait TrySub: CheckedSub {
 
 impl<T: CheckedAdd> TryAdd for T {}
 
-impl<T: CheckedMul> TryMul for T {}
-
 impl<T: CheckedDiv> TryDiv for T {}
 
 impl<T: CheckedSub> TrySub for T {}
+
+/// Direction to round the quotient of `mul_div` in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+/// Compute `a * b / c` for balances. The product is taken over 256 bits, so it can't overflow for
+/// balances of up to 128 bits, and only a quotient that does not fit back in a balance fails.
+///
+/// Balances are never multiplied together directly, so that pools stay usable with reserves close
+/// to the limits of the balance type.
+pub fn mul_div<B>(a: B, b: B, c: B, rounding: Rounding) -> Result<B, ArithmeticError>
+where
+    B: Into<U256> + TryFrom<U256>,
+{
+    let (a, b, c): (U256, U256, U256) = (a.into(), b.into(), c.into());
+    if c.is_zero() {
+        return Err(DivisionByZero);
+    }
+
+    let product = a.checked_mul(b).ok_or(Overflow)?;
+    let (quotient, remainder) = product.div_mod(c);
+    let quotient = match rounding {
+        Rounding::Up if !remainder.is_zero() => quotient.checked_add(U256::one()).ok_or(Overflow)?,
+        _ => quotient,
+    };
+
+    from_u256(quotient)
+}
+
+/// Convert a widened value back into a balance, failing if it does not fit.
+pub fn from_u256<B: TryFrom<U256>>(value: U256) -> Result<B, ArithmeticError> {
+    B::try_from(value).map_err(|_| Overflow)
+}
+
+/// Reduce a widened value modulo one more than the largest balance, i.e. wrap it around like
+/// unsigned integers do. Balances are unsigned integers of up to 256 bits, so this modulus is a
+/// power of two dividing `2^256`, and values that wrapped around `U256` already are still correct.
+fn wrap<B>(value: U256) -> Result<B, ArithmeticError>
+where
+    B: Bounded + Into<U256> + TryFrom<U256>,
+{
+    let max: U256 = B::max_value().into();
+    let (modulus, overflowed) = max.overflowing_add(U256::one());
+    from_u256(if overflowed { value } else { value % modulus })
+}
+
+/// Compute `a + b` for balances, wrapping around past the largest balance.
+pub fn wrapping_add<B>(a: B, b: B) -> Result<B, ArithmeticError>
+where
+    B: Bounded + Into<U256> + TryFrom<U256>,
+{
+    let (a, b): (U256, U256) = (a.into(), b.into());
+    wrap(a.overflowing_add(b).0)
+}
+
+/// Compute `a - b` for balances, wrapping around below zero.
+pub fn wrapping_sub<B>(a: B, b: B) -> Result<B, ArithmeticError>
+where
+    B: Bounded + Into<U256> + TryFrom<U256>,
+{
+    let (a, b): (U256, U256) = (a.into(), b.into());
+    wrap(a.overflowing_sub(b).0)
+}
+
+/// Compute `a * b` for balances, wrapping around past the largest balance.
+pub fn wrapping_mul<B>(a: B, b: B) -> Result<B, ArithmeticError>
+where
+    B: Bounded + Into<U256> + TryFrom<U256>,
+{
+    let (a, b): (U256, U256) = (a.into(), b.into());
+    wrap(a.overflowing_mul(b).0)
+}
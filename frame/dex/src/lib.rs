@@ -1,10 +1,19 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+pub use extensions::CheckDeadline;
 pub use pallet::*;
 
+mod batch;
+mod concentrated;
+mod curves;
+mod extensions;
+mod farming;
 mod helpers;
+pub mod migrations;
+mod orders;
 pub mod traits;
 pub mod types;
+pub mod weights;
 
 #[cfg(test)]
 mod mock;
@@ -14,24 +23,39 @@ mod tests;
 
 #[frame_support::pallet]
 pub mod pallet {
-    use crate::{helpers::*, traits::SimulateSwap, types::*};
+    use crate::{
+        curves::{
+            concentrated::{sqrt_price_at_tick, MAX_TICK, MIN_TICK},
+            constant_product, stable_swap, weighted,
+        },
+        helpers::*,
+        traits::{ExecuteSwap, FlashBorrower, PriceOracle, SimulateSwap},
+        types::*,
+        weights::WeightInfo,
+    };
     use codec::FullCodec;
     use frame_support::{
         pallet_prelude::*,
-        traits::fungibles::{
-            metadata::Mutate as MutateMetadata, Create, Inspect, InspectMetadata, Mutate, Transfer,
+        storage::{with_transaction, TransactionOutcome},
+        traits::{
+            fungibles::{
+                metadata::Mutate as MutateMetadata, Create, Destroy, Inspect, InspectMetadata,
+                Mutate, Transfer,
+            },
+            Currency, ExistenceRequirement, ReservableCurrency,
         },
         PalletId,
     };
     use frame_system::pallet_prelude::*;
+    use sp_core::U256;
     use sp_runtime::{
         traits::{
-            AccountIdConversion, CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, One, Saturating,
-            Zero,
+            AccountIdConversion, Bounded, CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, One,
+            SaturatedConversion, Saturating, Zero,
         },
-        ArithmeticError,
+        ArithmeticError, Perbill, Permill,
     };
-    use sp_std::fmt::Debug;
+    use sp_std::{fmt::Debug, vec, vec::Vec};
 
     // ---------------------------------------------------------------------------------------------
     //                                      Config
@@ -39,6 +63,9 @@ pub mod pallet {
 
     #[pallet::config]
     pub trait Config: frame_system::Config {
+        /// Origin allowed to change the parameters of AMMs, and to pause them or the whole pallet.
+        type AdminOrigin: EnsureOrigin<Self::Origin>;
+
         /// Unique identifier for an AMM instance.
         type AmmId: Clone
             + Copy
@@ -54,10 +81,19 @@ pub mod pallet {
             + TypeInfo;
 
         /// The asset identifier type.
-        type AssetId: Clone + Copy + Debug + Decode + Encode + MaxEncodedLen + PartialEq + TypeInfo;
+        type AssetId: Clone
+            + Copy
+            + Debug
+            + Decode
+            + Encode
+            + MaxEncodedLen
+            + Ord
+            + PartialEq
+            + TypeInfo;
 
         /// Asset transfer mechanism.
         type Assets: Create<Self::AccountId, AssetId = Self::AssetId, Balance = Self::Balance>
+            + Destroy<Self::AccountId, AssetId = Self::AssetId, Balance = Self::Balance>
             + Inspect<Self::AccountId, AssetId = Self::AssetId, Balance = Self::Balance>
             + InspectMetadata<Self::AccountId, AssetId = Self::AssetId, Balance = Self::Balance>
             + Mutate<Self::AccountId, AssetId = Self::AssetId, Balance = Self::Balance>
@@ -65,7 +101,8 @@ pub mod pallet {
             + Transfer<Self::AccountId, AssetId = Self::AssetId, Balance = Self::Balance>;
 
         /// Type of balances for user accounts and AMM reserves.
-        type Balance: CheckedAdd
+        type Balance: Bounded
+            + CheckedAdd
             + CheckedDiv
             + CheckedMul
             + CheckedSub
@@ -76,31 +113,101 @@ pub mod pallet {
             + Encode
             + From<u64>
             + FullCodec
+            + Into<U256>
             + MaxEncodedLen
             + One
             + Ord
             + PartialEq
             + Saturating
+            + TryFrom<U256>
             + TypeInfo
             + Zero;
 
+        /// Native currency, in which `PoolDeposit` is reserved.
+        type Currency: ReservableCurrency<Self::AccountId>;
+
         /// Default number of decimal digits for AMM share asset.
         type DefaultDecimals: Get<u8>;
 
         /// Event type.
         type Event: From<Event<Self>> + IsType<<Self as frame_system::Config>::Event>;
 
+        /// Called in the middle of flash swaps, for borrowers to use the assets lent to them and
+        /// pay them back.
+        type FlashBorrower: FlashBorrower<Self::AccountId, Self::AmmId, Self::Balance>;
+
+        /// Maximum number of assets an AMM can hold. Must be at least two.
+        #[pallet::constant]
+        type MaxAssetsPerPool: Get<u32>;
+
+        /// Maximum number of swaps submitted to an AMM in batch mode within a block.
+        #[pallet::constant]
+        type MaxBatchSwaps: Get<u32>;
+
+        /// Maximum number of price observations kept per AMM for computing time-weighted average
+        /// prices. Bounds the longest window that can be queried to roughly this many blocks with
+        /// activity.
+        #[pallet::constant]
+        type MaxPriceObservations: Get<u32>;
+
+        /// Maximum number of hops in a routed swap.
+        #[pallet::constant]
+        type MaxRouteLength: Get<u32>;
+
+        /// Maximum number of ticks where positions of a concentrated liquidity AMM start or end.
+        /// Bounds the work of swapping against it.
+        #[pallet::constant]
+        type MaxTicksPerPool: Get<u32>;
+
+        /// Amount of LP shares minted to the pallet's account on the first deposit into an AMM and
+        /// locked there forever, so that the value of a share can't be inflated enough to round
+        /// later deposits down to nothing.
+        #[pallet::constant]
+        type MinimumLiquidity: Get<Self::Balance>;
+
         /// The `AccountId` of the pallet.
         #[pallet::constant]
         type PalletId: Get<PalletId>;
+
+        /// Amount of native currency reserved from the creator of an AMM, refunded when the AMM is
+        /// destroyed. Makes creating AMMs nobody uses costly.
+        #[pallet::constant]
+        type PoolDeposit: Get<NativeBalanceOf<Self>>;
+
+        /// Share of the swap fees of every AMM sent to `ProtocolFeeRecipient` instead of being
+        /// kept for liquidity providers.
+        #[pallet::constant]
+        type ProtocolFee: Get<Permill>;
+
+        /// Account collecting the protocol's share of swap fees, such as a treasury.
+        type ProtocolFeeRecipient: Get<Self::AccountId>;
+
+        /// Amount of native currency moved from the creator of an AMM to its account, which pays
+        /// the deposit for the metadata of the LP share asset. Must cover the metadata deposit of
+        /// `Assets` for the generated name and symbol, plus the existential deposit. What is left
+        /// returns to the creator when the AMM is destroyed.
+        #[pallet::constant]
+        type ShareMetadataDeposit: Get<NativeBalanceOf<Self>>;
+
+        /// Maximum length of the name and symbol of LP share assets, which are truncated to fit.
+        /// Must not exceed the `StringLimit` of `Assets`.
+        #[pallet::constant]
+        type StringLimit: Get<u32>;
+
+        /// Weights of the pallet's calls and hooks.
+        type WeightInfo: WeightInfo;
     }
 
     // ---------------------------------------------------------------------------------------------
     //                                      Pallet Type
     // ---------------------------------------------------------------------------------------------
 
+    /// The current storage version.
+    const STORAGE_VERSION: StorageVersion = StorageVersion::new(2);
+
     #[pallet::pallet]
     #[pallet::generate_store(pub(super) trait Store)]
+    #[pallet::storage_version(STORAGE_VERSION)]
     pub struct Pallet<T>(_);
 
     // ---------------------------------------------------------------------------------------------
@@ -116,12 +223,120 @@ pub mod pallet {
     #[pallet::getter(fn amm_count)]
     pub type AmmCount<T: Config> = StorageValue<_, T::AmmId, ValueQuery>;
 
+    /// Mapping from pairs of assets, lowest asset id first, and fee tiers to the only AMM of two
+    /// assets allowed for each of them. Use `pair_amm` to look up a pair in any order.
+    #[pallet::storage]
+    pub type Pairs<T: Config> =
+        StorageMap<_, Blake2_128Concat, (T::AssetId, T::AssetId, T::Balance), T::AmmId>;
+
+    /// Cumulative price observations for each AMM, oldest first starting from the position in
+    /// `PriceObservationHeads` and wrapping around. A new one is recorded on the first interaction
+    /// with the AMM in each block, before its reserves change. Use `price_history` to get them in
+    /// order.
+    #[pallet::storage]
+    #[pallet::getter(fn price_observations)]
+    pub type PriceObservations<T: Config> = StorageMap<
+        _,
+        Blake2_128Concat,
+        T::AmmId,
+        BoundedVec<PriceObservation<T>, T::MaxPriceObservations>,
+        ValueQuery,
+    >;
+
+    /// Position of the oldest observation in `PriceObservations` for each AMM. Zero until the AMM
+    /// has `MaxPriceObservations` of them, after which each new one overwrites the oldest and moves
+    /// this past it, so that recording one does not shift the others.
+    #[pallet::storage]
+    pub type PriceObservationHeads<T: Config> =
+        StorageMap<_, Blake2_128Concat, T::AmmId, u32, ValueQuery>;
+
+    /// Mapping from ids of concentrated liquidity AMMs to corresponding states. These AMMs share
+    /// ids with those in `AmmStates`.
+    #[pallet::storage]
+    #[pallet::getter(fn concentrated_amm)]
+    pub type ConcentratedAmms<T: Config> =
+        StorageMap<_, Blake2_128Concat, T::AmmId, ConcentratedAmm<T>>;
+
+    /// Mapping from ids of concentrated liquidity positions to corresponding states.
+    #[pallet::storage]
+    #[pallet::getter(fn position)]
+    pub type Positions<T: Config> = StorageMap<_, Blake2_128Concat, PositionId, Position<T>>;
+
+    #[pallet::storage]
+    #[pallet::getter(fn position_count)]
+    pub type PositionCount<T: Config> = StorageValue<_, PositionId, ValueQuery>;
+
     /// The share of the pool for each liquidity provider (LP tokens).
     #[pallet::storage]
     #[pallet::getter(fn shares)]
     pub type Shares<T: Config> =
         StorageDoubleMap<_, Twox64Concat, T::AmmId, Blake2_128Concat, T::AccountId, T::Balance>;
 
+    /// AMMs where swaps and deposits are halted. Withdrawals remain possible.
+    #[pallet::storage]
+    #[pallet::getter(fn is_paused)]
+    pub type PausedAmms<T: Config> = StorageMap<_, Twox64Concat, T::AmmId, bool, ValueQuery>;
+
+    /// Account that created each AMM and the `PoolDeposit` reserved from it at the time.
+    #[pallet::storage]
+    #[pallet::getter(fn pool_deposit)]
+    pub type PoolDeposits<T: Config> =
+        StorageMap<_, Blake2_128Concat, T::AmmId, (T::AccountId, NativeBalanceOf<T>)>;
+
+    /// Whether the whole pallet is halted, as if every AMM was paused and no new one could be
+    /// created.
+    #[pallet::storage]
+    #[pallet::getter(fn is_frozen)]
+    pub type Frozen<T: Config> = StorageValue<_, bool, ValueQuery>;
+
+    /// Mapping from ids of resting limit orders to corresponding orders.
+    #[pallet::storage]
+    #[pallet::getter(fn limit_order)]
+    pub type LimitOrders<T: Config> = StorageMap<_, Twox64Concat, OrderId, LimitOrder<T>>;
+
+    #[pallet::storage]
+    #[pallet::getter(fn limit_order_count)]
+    pub type LimitOrderCount<T: Config> = StorageValue<_, OrderId, ValueQuery>;
+
+    /// The last limit order checked in `on_idle`, after which checks resume in the next block.
+    /// Orders are checked in storage key order, not by id, so orders placed at a key before this
+    /// one are only checked once checks start over.
+    #[pallet::storage]
+    pub type LastCheckedOrder<T: Config> = StorageValue<_, OrderId>;
+
+    /// AMMs in batch mode, where swaps are cleared together at a single price at the end of each
+    /// block instead of executing in order. Counted to reserve the weight of clearing them.
+    #[pallet::storage]
+    #[pallet::getter(fn is_batched)]
+    pub type BatchedAmms<T: Config> =
+        CountedStorageMap<_, Twox64Concat, T::AmmId, bool, ValueQuery>;
+
+    /// Swaps submitted during the current block to each AMM in batch mode.
+    #[pallet::storage]
+    #[pallet::getter(fn batch_swaps)]
+    pub type BatchSwaps<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        T::AmmId,
+        BoundedVec<BatchSwap<T>, T::MaxBatchSwaps>,
+        ValueQuery,
+    >;
+
+    /// Mapping from farm ids to corresponding farms.
+    #[pallet::storage]
+    #[pallet::getter(fn farm)]
+    pub type Farms<T: Config> = StorageMap<_, Twox64Concat, FarmId, Farm<T>>;
+
+    #[pallet::storage]
+    #[pallet::getter(fn farm_count)]
+    pub type FarmCount<T: Config> = StorageValue<_, FarmId, ValueQuery>;
+
+    /// Shares staked by each account in each farm, along with the rewards they earned.
+    #[pallet::storage]
+    #[pallet::getter(fn stake_of)]
+    pub type Stakes<T: Config> =
+        StorageDoubleMap<_, Twox64Concat, FarmId, Blake2_128Concat, T::AccountId, Stake<T>>;
+
     // ---------------------------------------------------------------------------------------------
     //                                      Events
     // ---------------------------------------------------------------------------------------------
@@ -142,15 +357,196 @@ pub mod pallet {
             amm_id: T::AmmId,
             user: T::AccountId,
             shares: T::Balance,
+            amounts: BoundedVec<T::Balance, T::MaxAssetsPerPool>,
         },
         /// Emitted when a user swaps against an AMM.
         Swapped {
             user: T::AccountId,
             amm_id: T::AmmId,
-            asset_type: AssetType,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            input_amount: T::Balance,
+            output_amount: T::Balance,
+            /// Part of `input_amount` kept by the AMM for liquidity providers.
+            lp_fee: T::Balance,
+            /// Part of `input_amount` sent to the protocol fee recipient.
+            protocol_fee: T::Balance,
+        },
+        /// Emitted when a user completes a swap through multiple AMMs.
+        RouteSwapped {
+            user: T::AccountId,
+            asset_in: T::AssetId,
+            route: Route<T>,
+            input_amount: T::Balance,
+            output_amount: T::Balance,
+        },
+        /// Emitted when a user opens a position in a concentrated liquidity AMM.
+        PositionOpened {
+            position_id: PositionId,
+            owner: T::AccountId,
+            amm_id: T::AmmId,
+            lower_tick: i32,
+            upper_tick: i32,
+            liquidity: u128,
+            amounts: [T::Balance; 2],
+        },
+        /// Emitted when the owner of a concentrated liquidity position collects its fees.
+        FeesCollected {
+            position_id: PositionId,
+            owner: T::AccountId,
+            amounts: [T::Balance; 2],
+        },
+        /// Emitted when the owner of a concentrated liquidity position withdraws all of its assets
+        /// and fees.
+        PositionClosed {
+            position_id: PositionId,
+            owner: T::AccountId,
+            amounts: [T::Balance; 2],
+        },
+        /// Emitted when a concentrated liquidity position changes owner.
+        PositionTransferred {
+            position_id: PositionId,
+            from: T::AccountId,
+            to: T::AccountId,
+        },
+        /// Emitted when the swap fees of an AMM are updated.
+        FeesUpdated {
+            amm_id: T::AmmId,
+            fees_bps: T::Balance,
+        },
+        /// Emitted when the amplification of a StableSwap AMM is updated.
+        AmplificationUpdated {
+            amm_id: T::AmmId,
+            amplification: u32,
+        },
+        /// Emitted when swaps and deposits are halted in an AMM.
+        AmmPaused { amm_id: T::AmmId },
+        /// Emitted when swaps and deposits are allowed again in an AMM.
+        AmmUnpaused { amm_id: T::AmmId },
+        /// Emitted when the whole pallet is halted.
+        Frozen,
+        /// Emitted when the pallet is no longer halted.
+        Unfrozen,
+        /// Emitted when an AMM without liquidity is destroyed.
+        AmmDestroyed { amm_id: T::AmmId },
+        /// Emitted when a user borrows assets from an AMM and pays them back within the same call.
+        FlashSwapped {
+            user: T::AccountId,
+            amm_id: T::AmmId,
+            /// Amounts of each asset lent to the user.
+            amounts_out: BoundedVec<T::Balance, T::MaxAssetsPerPool>,
+            /// Amounts of each asset paid back, fees included.
+            amounts_in: BoundedVec<T::Balance, T::MaxAssetsPerPool>,
+        },
+        /// Emitted when a user places a limit order, escrowing its input.
+        LimitOrderPlaced {
+            order_id: OrderId,
+            owner: T::AccountId,
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            input_amount: T::Balance,
+            output_min: T::Balance,
+        },
+        /// Emitted when a user cancels a limit order, getting its input back.
+        LimitOrderCancelled {
+            order_id: OrderId,
+            owner: T::AccountId,
+        },
+        /// Emitted when a limit order is filled, sending its output to the owner.
+        LimitOrderFilled {
+            order_id: OrderId,
+            owner: T::AccountId,
+            output_amount: T::Balance,
+        },
+        /// Emitted when the admin puts an AMM in batch mode or takes it out of it.
+        BatchModeSet {
+            amm_id: T::AmmId,
+            enabled: bool,
+        },
+        /// Emitted when a user submits a swap to an AMM in batch mode, escrowing its input until
+        /// the end of the block.
+        BatchSwapSubmitted {
+            user: T::AccountId,
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            input_amount: T::Balance,
+            output_min: T::Balance,
+        },
+        /// Emitted when a swap submitted to an AMM in batch mode is filled at the batch's price.
+        BatchSwapFilled {
+            user: T::AccountId,
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
             input_amount: T::Balance,
             output_amount: T::Balance,
         },
+        /// Emitted when a swap submitted to an AMM in batch mode is refunded, because the batch's
+        /// price would not pay out its `output_min` or the batch could not be cleared.
+        BatchSwapRefunded {
+            user: T::AccountId,
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            input_amount: T::Balance,
+        },
+        /// Emitted when the swaps submitted to an AMM in batch mode are cleared. `price` is the
+        /// amount of the AMM's second asset paid for one unit of the first one.
+        BatchCleared {
+            amm_id: T::AmmId,
+            price: T::Balance,
+        },
+        /// Emitted when the admin creates a farm for the stakers of an AMM's shares.
+        FarmCreated {
+            farm_id: FarmId,
+            amm_id: T::AmmId,
+            reward_asset: T::AssetId,
+            reward_per_block: T::Balance,
+            start: T::BlockNumber,
+            end: T::BlockNumber,
+        },
+        /// Emitted when rewards are added to a farm.
+        FarmFunded {
+            farm_id: FarmId,
+            funder: T::AccountId,
+            amount: T::Balance,
+        },
+        /// Emitted when the admin changes the emissions of a farm.
+        FarmUpdated {
+            farm_id: FarmId,
+            reward_per_block: T::Balance,
+            end: T::BlockNumber,
+        },
+        /// Emitted when the admin ends a farm, refunding the rewards it had not emitted yet.
+        FarmEnded {
+            farm_id: FarmId,
+            refunded: T::Balance,
+        },
+        /// Emitted when a user stakes shares in a farm.
+        Staked {
+            farm_id: FarmId,
+            user: T::AccountId,
+            amount: T::Balance,
+        },
+        /// Emitted when a user unstakes shares from a farm.
+        Unstaked {
+            farm_id: FarmId,
+            user: T::AccountId,
+            amount: T::Balance,
+        },
+        /// Emitted when a user claims the rewards earned in a farm.
+        RewardsClaimed {
+            farm_id: FarmId,
+            user: T::AccountId,
+            amount: T::Balance,
+        },
+        /// Emitted when a user withdraws all of their shares from a farm, forfeiting their
+        /// rewards.
+        EmergencyWithdrawn {
+            farm_id: FarmId,
+            user: T::AccountId,
+            amount: T::Balance,
+        },
     }
 
     // ---------------------------------------------------------------------------------------------
@@ -159,26 +555,132 @@ pub mod pallet {
 
     #[pallet::error]
     pub enum Error<T> {
+        /// Raised when swapping against an AMM in batch mode other than with `swap`, `swap_between`
+        /// or `swap_pair`.
+        AmmInBatchMode,
+        /// Raised when swapping against or depositing into a paused AMM.
+        AmmIsPaused,
+        /// Raised when destroying an AMM that still holds liquidity.
+        AmmNotEmpty,
+        /// Raised when the number of amounts given does not match the number of assets in the AMM,
+        /// or when swapping by `AssetType` against an AMM not holding exactly two assets.
+        AssetCountMismatch,
+        /// Raised when an AMM in batch mode already holds `MaxBatchSwaps` swaps for the block.
+        BatchFull,
+        /// Raised when a call is executed after the deadline given for it.
+        DeadlineExpired,
+        /// Raised when the first deposit into an AMM would not mint more than `MinimumLiquidity`
+        /// shares.
+        InsufficientInitialLiquidity,
         /// Raised when a swap would completely drain one side of the pool.
         InsufficientLiquidity,
+        /// Raised when querying a time-weighted average price over an empty window or one that
+        /// starts before the oldest price observation of an AMM.
+        InsufficientPriceHistory,
+        /// Raised when a flash swap is not paid back with enough assets to keep the AMM's
+        /// invariant, net of fees, from decreasing.
+        InsufficientRepayment,
+        /// Raised when unstaking more shares than staked in a farm, or claiming from a farm
+        /// without a stake in it.
+        InsufficientStake,
+        /// Raised when a withdrawal would pay out less of an asset than the minimum required by a
+        /// user.
+        InsufficientWithdrawal,
         /// Raised when an operation targets a nonexistent AMM.
         InvalidAmmId,
+        /// Raised when an operation refers to an asset the AMM does not hold, or when swapping an
+        /// asset for itself.
+        InvalidAsset,
+        /// Raised when creating an AMM with fewer than two assets, or with the same asset more than
+        /// once.
+        InvalidAssets,
+        /// Raised when creating an AMM with invalid curve parameters or a weighted AMM with more
+        /// than two assets, or when providing single-sided liquidity to an AMM whose curve does
+        /// not support it.
+        InvalidCurve,
+        /// Raised when an operation targets a nonexistent farm.
+        InvalidFarmId,
+        /// Raised when a farm would end before it starts, or no later than the current block.
+        InvalidFarmPeriod,
+        /// Raised when creating an AMM or setting its fees with 100% or more.
+        InvalidFees,
+        /// Raised when an operation targets a nonexistent limit order.
+        InvalidOrderId,
+        /// Raised when an operation targets a nonexistent concentrated liquidity position.
+        InvalidPositionId,
+        /// Raised when a route is empty, or longer than `MaxRouteLength`.
+        InvalidRoute,
         /// Raised when trying to withdraw more LP shares than a user has in their account.
         InvalidShareAmount,
         /// Raised when failing to create a new asset type for LP shares.
         InvalidShareAsset,
-        /// Raised when trying to provide liquidity with non-equivalent values of the two assets in
-        /// the pool.
-        NonEquivalentValue,
-        /// Raised when swap output is below the minimum required by a user.
+        /// Raised when a tick is out of bounds or not a multiple of the tick spacing, when a
+        /// position's lower tick is not below its upper tick, or when creating a concentrated
+        /// liquidity AMM with a tick spacing of zero.
+        InvalidTicks,
+        /// Raised when someone other than its creator or the admin origin destroys an AMM.
+        NotAmmCreator,
+        /// Raised when someone other than its owner cancels a limit order.
+        NotOrderOwner,
+        /// Raised when someone other than its owner operates on a concentrated liquidity position.
+        NotPositionOwner,
+        /// Raised when creating an AMM of two assets, or moving one to another fee tier, while an
+        /// AMM of the same pair and fee tier already exists.
+        PairAlreadyExists,
+        /// Raised when swapping, depositing or creating an AMM while the pallet is frozen.
+        PalletIsFrozen,
+        /// Raised when the liquidity of an AMM changes while it lends assets in a flash swap.
+        ReentrantFlashSwap,
+        /// Raised when swap output is below the minimum required by a user, or the input required
+        /// for an exact output is above the maximum allowed.
         SlippageExceeded,
-        /// Raised when trying to swap a zero amount of asset.
+        /// Raised when a swap against a weighted AMM would add more than half of its input
+        /// reserves, net of fees, beyond which its prices can't be computed accurately. Quotes for
+        /// an exact output are also rejected above a third of the output reserves, and flash swaps
+        /// repaid into a weighted AMM are held to the same limit on their input.
+        SwapTooLarge,
+        /// Raised when opening a position would initialize more than `MaxTicksPerPool` ticks in a
+        /// concentrated liquidity AMM.
+        TooManyTicks,
+        /// Raised when creating an AMM of an asset that does not exist.
+        UnknownAsset,
+        /// Raised when no AMM of two assets exists for a pair and fee tier.
+        UnknownPair,
+        /// Raised when trying to swap a zero amount of asset, or when a swap would pay out nothing.
         ZeroAmount,
         /// Raised when interacting with an uninitialized AMM while the operation requires
         /// otherwise.
         ZeroLiquidity,
     }
 
+    // ---------------------------------------------------------------------------------------------
+    //                                      Hooks
+    // ---------------------------------------------------------------------------------------------
+
+    #[pallet::hooks]
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        /// Fill the limit orders that became executable after the block's swaps, with whatever
+        /// weight is left in the block.
+        fn on_idle(_n: BlockNumberFor<T>, remaining_weight: Weight) -> Weight {
+            Self::fill_limit_orders(remaining_weight)
+        }
+
+        /// Reserve the weight of clearing batches in `on_finalize`, as if every AMM in batch mode
+        /// received `MaxBatchSwaps` swaps during the block.
+        fn on_initialize(_n: BlockNumberFor<T>) -> Weight {
+            let batches = Weight::from(BatchedAmms::<T>::count());
+            T::WeightInfo::clear_batch(T::MaxBatchSwaps::get())
+                .saturating_mul(batches)
+                .saturating_add(T::DbWeight::get().reads(1))
+        }
+
+        /// Clear the swaps submitted to AMMs in batch mode during the block, within the weight
+        /// reserved by `on_initialize`.
+        fn on_finalize(_n: BlockNumberFor<T>) {
+            Self::clear_batches();
+        }
+    }
+
     // ---------------------------------------------------------------------------------------------
     //                                      Extrinsics
     // ---------------------------------------------------------------------------------------------
@@ -188,51 +690,81 @@ pub mod pallet {
         /// Create a new AMM.
         ///
         /// The caller has to specify:
-        /// - `base_asset`: the asset id of the first one in the pool pair
-        /// - `quote_asset`: the asset id of the second one in the pool pair
+        /// - `assets`: the ids of the assets to pool, at least two and all different. Calls taking
+        ///   one amount per asset expect them in this same order.
         /// - `share_asset`: the asset id of the liquidity provider token to be created and managed
         ///   by this AMM
         /// - `fees_bps`: the share of input asset to be subtracted as fees for liquidity providers
-        ///   in the `swap` extrinsic.
+        ///   in the `swap` extrinsic, below 10,000.
+        /// - `curve`: the bonding curve used to price swaps, `Curve::ConstantProduct` if `None`.
+        ///   Constant product suits most pairs, `Curve::StableSwap` is meant for assets pegged to
+        ///   each other and `Curve::Weighted` for pairs where the first asset should make up more
+        ///   or less of the value, with a `base_weight` between 2% and 98%. Weighted AMMs hold two
+        ///   assets only.
+        ///
+        /// There can only be one AMM of two assets, of any kind, for each pair and `fees_bps`. It
+        /// can then be found with `pair_amm` and swapped against with `swap_pair`.
+        ///
+        /// `T::PoolDeposit` is reserved from the caller until the AMM is destroyed. The caller also
+        /// funds the metadata of the share asset, named after the symbols of the pooled assets
+        /// (e.g. "DOT-USDC LP") with `T::DefaultDecimals`.
         #[pallet::weight(1_000)]
         pub fn create_amm(
             origin: OriginFor<T>,
-            base_asset: T::AssetId,
-            quote_asset: T::AssetId,
+            assets: BoundedVec<T::AssetId, T::MaxAssetsPerPool>,
             share_asset: T::AssetId,
             fees_bps: T::Balance,
+            curve: Option<Curve>,
         ) -> DispatchResult {
-            ensure_signed(origin)?;
+            let caller = ensure_signed(origin)?;
+            let curve = curve.unwrap_or_default();
+
+            ensure!(!Self::is_frozen(), Error::<T>::PalletIsFrozen);
+            ensure!(assets.len() >= 2, Error::<T>::InvalidAssets);
+            for (index, asset) in assets.iter().enumerate() {
+                ensure!(!assets[..index].contains(asset), Error::<T>::InvalidAssets);
+                ensure!(Self::asset_exists(asset), Error::<T>::UnknownAsset);
+            }
+            ensure!(fees_bps < 10_000_u64.into(), Error::<T>::InvalidFees);
+
+            match curve {
+                Curve::ConstantProduct => {}
+                Curve::StableSwap { amplification } => {
+                    ensure!(amplification > 0, Error::<T>::InvalidCurve);
+                }
+                Curve::Weighted { base_weight } => {
+                    ensure!(
+                        assets.len() == 2
+                            && base_weight >= Perbill::from_percent(2)
+                            && base_weight <= Perbill::from_percent(98),
+                        Error::<T>::InvalidCurve
+                    );
+                }
+            }
 
             let amm_id = Self::amm_count();
+            Self::index_pair(&amm_id, &assets, fees_bps)?;
+            Self::reserve_pool_deposit(&amm_id, &caller)?;
+            let reserves = BoundedVec::try_from(vec![Zero::zero(); assets.len()])
+                .map_err(|_| Error::<T>::InvalidAssets)?;
             let amm_state = Amm {
-                base_asset,
-                base_reserves: Zero::zero(),
-                quote_asset,
-                quote_reserves: Zero::zero(),
+                assets,
+                reserves,
                 share_asset,
                 total_shares: Zero::zero(),
                 fees_bps,
+                curve,
             };
 
             let amm_account = Self::amm_account(&amm_id);
             T::Assets::create(
                 share_asset,
-                amm_account,
+                amm_account.clone(),
                 true,
                 One::one(), // Any share amount is fair game
             )
             .map_err(|_| Error::<T>::InvalidShareAsset)?;
-
-            // Mutating metadata requires a deposit, so calling this function with the just-created
-            // `amm_account` raises an "InsufficientBalance" error.
-            // <T::Assets as MutateMetadata<T::AccountId>>::set(
-            //     share_asset,
-            //     &amm_account,
-            //     (*b"").into(),
-            //     (*b"").into(),
-            //     T::DefaultDecimals::get(),
-            // )?;
+            Self::set_share_metadata(&caller, &amm_account, share_asset, &amm_state.assets)?;
 
             AmmCount::<T>::set(
                 amm_id
@@ -249,13 +781,23 @@ pub mod pallet {
         ///
         /// The caller must specify the following parameters
         /// - `amm_id`: the if of the AMM to add assets to
-        /// - `base_amount`: the amount of base asset to add
-        /// - `quote_amount`: the amount of quote asset to add
+        /// - `max_amounts`: the maximum amount of each asset to add, in the order given at
+        ///   creation
+        /// - `min_shares`: the minimum amount of LP shares to get in return. Prevents against
+        ///   slippage.
+        /// - `deadline`: if given, the last block the call can be executed in. Prevents against it
+        ///   being held back and executed at stale prices.
         ///
-        /// The caller has to make sure that the ratio of `base_amount` to `quote_amount` matches
-        /// exactly the proportion of these assets in the pool, if it already has liquidity. If the
-        /// caller is the first to provide liquidity, it sets the ratio of these assets and the
-        /// implied invariant.
+        /// For constant product and weighted AMMs with liquidity, the pallet takes the largest
+        /// amounts within `max_amounts` that match the proportion of the assets in the pool at
+        /// execution time, so deposits computed from slightly outdated reserves still go through.
+        /// Only the amounts actually used are transferred. If the caller is the first to provide
+        /// liquidity, all of `max_amounts` is deposited, setting the ratio of these assets and the
+        /// implied invariant. As in Uniswap v2, the first deposit mints the geometric mean of the
+        /// amounts as shares, or the invariant for StableSwap AMMs, of which
+        /// `T::MinimumLiquidity` are locked in the pallet's account forever. StableSwap AMMs take
+        /// all of `max_amounts` in any ratio, but charge the swap fee on the part of the deposit
+        /// that unbalances the pool.
         ///
         /// The pallet mints LP 'shares' as the asset which was created during the call to
         /// `create_amm`. The asset amount represents the LP's share of the pool's liquidity, which
@@ -264,67 +806,31 @@ pub mod pallet {
         pub fn provide_liquidity(
             origin: OriginFor<T>,
             amm_id: T::AmmId,
-            base_amount: T::Balance,
-            quote_amount: T::Balance,
+            max_amounts: BoundedVec<T::Balance, T::MaxAssetsPerPool>,
+            min_shares: T::Balance,
+            deadline: Option<T::BlockNumber>,
         ) -> DispatchResult {
             let caller = ensure_signed(origin)?;
+            Self::ensure_before_deadline(deadline)?;
 
             let mut state = Self::try_get_amm_state(&amm_id)?;
-
-            let shares = if state.total_shares.is_zero() {
-                let unit: T::Balance = 10_u64
-                    .saturating_pow(T::DefaultDecimals::get() as u32)
-                    .into();
-                unit.saturating_mul(100_u64.into())
+            Self::ensure_not_paused(&amm_id)?;
+            ensure!(max_amounts.len() == state.assets.len(), Error::<T>::AssetCountMismatch);
+            Self::record_price_observation(&amm_id, &state)?;
+
+            let (amounts, shares) = if state.total_shares.is_zero() {
+                let shares = Self::initial_shares(&state, &max_amounts)?;
+                Self::lock_minimum_liquidity(&mut state)?;
+                (max_amounts.into_inner(), shares)
+            } else if let Curve::StableSwap { amplification } = state.curve {
+                let shares = Self::stable_swap_shares(&state, &max_amounts, amplification)?;
+                (max_amounts.into_inner(), shares)
             } else {
-                let share1 = state
-                    .total_shares
-                    .try_mul(&base_amount)?
-                    .try_div(&state.base_reserves)?;
-                let share2 = state
-                    .total_shares
-                    .try_mul(&quote_amount)?
-                    .try_div(&state.quote_reserves)?;
-
-                // This might be too strict. By the time the extrinsic is received and executed by
-                // the node, the reserves may have changed (even if slightly) from the when the
-                // caller calculated the amount of each asset.
-                ensure!(share1 == share2, Error::<T>::NonEquivalentValue);
-
-                share1
+                Self::proportional_deposit(&state, &max_amounts)?
             };
+            ensure!(shares >= min_shares, Error::<T>::SlippageExceeded);
 
-            T::Assets::transfer(
-                state.base_asset,
-                &caller,
-                &Self::amm_account(&amm_id),
-                base_amount,
-                false,
-            )?;
-
-            T::Assets::transfer(
-                state.quote_asset,
-                &caller,
-                &Self::amm_account(&amm_id),
-                quote_amount,
-                false,
-            )?;
-
-            T::Assets::mint_into(state.share_asset, &caller, shares)?;
-
-            state.base_reserves = state.base_reserves.try_add(&base_amount)?;
-            state.quote_reserves = state.quote_reserves.try_add(&quote_amount)?;
-            state.total_shares = state.total_shares.try_add(&shares)?;
-
-            AmmStates::<T>::insert(&amm_id, state);
-
-            Self::deposit_event(Event::<T>::LiquidityAdded {
-                amm_id,
-                user: caller,
-                shares,
-            });
-
-            Ok(())
+            Self::deposit_liquidity(&caller, amm_id, state, &amounts, shares)
         }
 
         /// Withdraw liquidity from an AMM's pool.
@@ -332,225 +838,2208 @@ pub mod pallet {
         /// The caller must specify the following arguments
         /// - `amm_id`: the id of the AMM
         /// - `amount`: quantity of LP shares to burn from the caller's account in order to return
-        ///   its corresponding share of the pool's liquidity.
+        ///   its corresponding share of each of the pool's assets.
+        /// - `min_amounts`: the minimum amount of each asset to get in return, in the order given
+        ///   at creation. Prevents against the reserves being moved right before the withdrawal.
+        /// - `deadline`: if given, the last block the call can be executed in. Prevents against it
+        ///   being held back and executed at stale prices.
         #[pallet::weight(1_000)]
         pub fn withdraw(
             origin: OriginFor<T>,
             amm_id: T::AmmId,
             amount: T::Balance,
+            min_amounts: BoundedVec<T::Balance, T::MaxAssetsPerPool>,
+            deadline: Option<T::BlockNumber>,
         ) -> DispatchResult {
             let caller = ensure_signed(origin)?;
+            Self::ensure_before_deadline(deadline)?;
 
-            let mut amm_state = Self::try_get_amm_state(&amm_id)?;
-
-            T::Assets::burn_from(amm_state.share_asset, &caller, amount)
-                .map_err(|_| Error::<T>::InvalidShareAmount)?;
-
-            let base_amount = amount
-                .try_mul(&amm_state.base_reserves)?
-                .try_div(&amm_state.total_shares)?;
-            let quote_amount = amount
-                .try_mul(&amm_state.quote_reserves)?
-                .try_div(&amm_state.total_shares)?;
+            let amm_state = Self::try_get_amm_state(&amm_id)?;
+            ensure!(
+                min_amounts.len() == amm_state.assets.len(),
+                Error::<T>::AssetCountMismatch
+            );
 
-            let amm_account = Self::amm_account(&amm_id);
-            T::Assets::transfer(
-                amm_state.base_asset,
-                &amm_account,
-                &caller,
-                base_amount,
-                false,
-            )?;
-            T::Assets::transfer(
-                amm_state.quote_asset,
-                &amm_account,
-                &caller,
-                quote_amount,
-                false,
-            )?;
+            let amounts = Self::do_withdraw(&caller, amm_id, amount)?;
+            for (asset_amount, min_amount) in amounts.iter().zip(min_amounts.iter()) {
+                ensure!(asset_amount >= min_amount, Error::<T>::InsufficientWithdrawal);
+            }
 
-            amm_state.total_shares = amm_state.total_shares.try_sub(&amount)?;
-            amm_state.base_reserves = amm_state.base_reserves.try_sub(&base_amount)?;
-            amm_state.quote_reserves = amm_state.quote_reserves.try_sub(&quote_amount)?;
+            Ok(())
+        }
 
-            AmmStates::<T>::insert(&amm_id, amm_state);
+        /// Provide liquidity to an AMM with a single one of its assets.
+        ///
+        /// The caller must specify the following arguments
+        /// - `amm_id`: the id of the AMM to add liquidity to
+        /// - `asset`: the asset to deposit
+        /// - `amount`: the amount of `asset` to deposit
+        /// - `min_shares`: the minimum amount of LP shares to get in return. Prevents against
+        ///   slippage.
+        /// - `deadline`: if given, the last block the call can be executed in. Prevents against it
+        ///   being held back and executed at stale prices.
+        ///
+        /// In two-asset constant product AMMs, the part of `amount` that balances the deposit is
+        /// first swapped through the same AMM, paying the usual fee, and any rounding dust left
+        /// over stays with the caller. StableSwap AMMs take the whole amount as an unbalanced
+        /// deposit. Other AMMs are not supported.
+        #[pallet::weight(1_000)]
+        pub fn provide_liquidity_single(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            asset: T::AssetId,
+            amount: T::Balance,
+            min_shares: T::Balance,
+            deadline: Option<T::BlockNumber>,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+            Self::ensure_before_deadline(deadline)?;
+
+            ensure!(!amount.is_zero(), Error::<T>::ZeroAmount);
+
+            let state = Self::try_get_amm_state(&amm_id)?;
+            Self::ensure_not_paused(&amm_id)?;
+            let index = state.asset_index(&asset).ok_or(Error::<T>::InvalidAsset)?;
+            ensure!(state.is_initialized(), Error::<T>::ZeroLiquidity);
+
+            let mut amounts = vec![Zero::zero(); state.assets.len()];
+            let (state, amounts, shares) = match state.curve {
+                Curve::ConstantProduct if state.assets.len() == 2 => {
+                    let other = 1 - index;
+                    let swap_amount = constant_product::single_sided_swap(
+                        state.reserves[index].into(),
+                        amount.into(),
+                        state.fees_bps.into(),
+                    )?;
+                    let swap_amount: T::Balance = from_u256(swap_amount)?;
+                    amounts[index] = amount.try_sub(&swap_amount)?;
+                    amounts[other] = Self::do_swap(
+                        &caller,
+                        amm_id,
+                        asset,
+                        state.assets[other],
+                        swap_amount,
+                        Zero::zero(),
+                    )?;
 
-            Self::deposit_event(Event::<T>::LiquidityRemoved {
-                amm_id,
-                user: caller,
-                shares: amount,
-            });
+                    // The swap moved the reserves, so they only match the remaining amounts now.
+                    let state = Self::try_get_amm_state(&amm_id)?;
+                    let (amounts, shares) = Self::proportional_deposit(&state, &amounts)?;
+                    (state, amounts, shares)
+                }
+                Curve::StableSwap { amplification } => {
+                    Self::record_price_observation(&amm_id, &state)?;
+                    amounts[index] = amount;
+                    let shares = Self::stable_swap_shares(&state, &amounts, amplification)?;
+                    (state, amounts, shares)
+                }
+                _ => return Err(Error::<T>::InvalidCurve.into()),
+            };
+            ensure!(shares >= min_shares, Error::<T>::SlippageExceeded);
 
-            Ok(())
+            Self::deposit_liquidity(&caller, amm_id, state, &amounts, shares)
         }
 
-        /// Swap either token through the AMM.
+        /// Withdraw liquidity from an AMM's pool as a single one of its assets.
         ///
         /// The caller must specify the following arguments
-        /// - `amm_id`: the id of the AMM to swap against
-        /// - `asset_type`: which of the two asset types in the pool to use as input
-        /// - `input_amount`: amount of input asset to add to the AMM
-        /// - `output_min`: the minimum amount of the opposite asset to get in return. Prevents
-        ///   against slippage.
+        /// - `amm_id`: the id of the AMM
+        /// - `amount`: quantity of LP shares to burn from the caller's account
+        /// - `asset`: the asset to get all of the withdrawal in
+        /// - `output_min`: the minimum amount of `asset` to get in return. Prevents against
+        ///   slippage.
+        /// - `deadline`: if given, the last block the call can be executed in. Prevents against it
+        ///   being held back and executed at stale prices.
+        ///
+        /// The shares are withdrawn as with `withdraw`, then every other asset is swapped for
+        /// `asset` through the same AMM, paying the usual fee.
         #[pallet::weight(1_000)]
-        pub fn swap(
+        pub fn withdraw_single(
             origin: OriginFor<T>,
             amm_id: T::AmmId,
-            asset_type: AssetType,
-            input_amount: T::Balance,
+            amount: T::Balance,
+            asset: T::AssetId,
             output_min: T::Balance,
+            deadline: Option<T::BlockNumber>,
         ) -> DispatchResult {
             let caller = ensure_signed(origin)?;
+            Self::ensure_before_deadline(deadline)?;
 
-            ensure!(!input_amount.is_zero(), Error::<T>::ZeroAmount);
-
-            let mut amm_state = Self::try_get_amm_state(&amm_id)?;
-            ensure!(amm_state.is_initialized()?, Error::<T>::ZeroLiquidity);
-
-            let output_amount = <Self as SimulateSwap>::simulate_swap(amm_id, asset_type, input_amount)?;
-            ensure!(output_amount > output_min, Error::<T>::SlippageExceeded);
+            let state = Self::try_get_amm_state(&amm_id)?;
+            let index = state.asset_index(&asset).ok_or(Error::<T>::InvalidAsset)?;
 
-            let amm_account = Self::amm_account(&amm_id);
-            match asset_type {
-                AssetType::Base => {
-                    T::Assets::transfer(
-                        amm_state.base_asset,
-                        &caller,
-                        &amm_account,
-                        input_amount,
-                        false,
-                    )?;
-                    T::Assets::transfer(
-                        amm_state.quote_asset,
-                        &amm_account,
-                        &caller,
-                        output_amount,
-                        false,
-                    )?;
+            let amounts = Self::do_withdraw(&caller, amm_id, amount)?;
 
-                    amm_state.base_reserves = amm_state.base_reserves.try_add(&input_amount)?;
-                    amm_state.quote_reserves = amm_state.quote_reserves.try_sub(&output_amount)?;
-                }
-                AssetType::Quote => {
-                    T::Assets::transfer(
-                        amm_state.base_asset,
-                        &amm_account,
+            let mut output = amounts[index];
+            for (other, other_amount) in amounts.iter().enumerate() {
+                if other != index && !other_amount.is_zero() {
+                    let swapped = Self::do_swap(
                         &caller,
-                        output_amount,
-                        false,
+                        amm_id,
+                        state.assets[other],
+                        asset,
+                        *other_amount,
+                        Zero::zero(),
                     )?;
-                    T::Assets::transfer(
-                        amm_state.quote_asset,
-                        &caller,
-                        &amm_account,
-                        input_amount,
-                        false,
-                    )?;
-
-                    amm_state.base_reserves = amm_state.base_reserves.try_sub(&output_amount)?;
-                    amm_state.quote_reserves = amm_state.quote_reserves.try_add(&input_amount)?;
+                    output = output.try_add(&swapped)?;
                 }
             }
-
-            AmmStates::<T>::insert(&amm_id, amm_state);
-
-            Self::deposit_event(Event::<T>::Swapped {
-                user: caller,
-                amm_id,
-                asset_type,
-                input_amount,
-                output_amount,
-            });
+            ensure!(output >= output_min, Error::<T>::SlippageExceeded);
 
             Ok(())
         }
-    }
-
-    // ---------------------------------------------------------------------------------------------
-    //                                      Trait Impls
-    // ---------------------------------------------------------------------------------------------
 
-    impl<T:Config> SimulateSwap for Pallet<T> {
-        type AmmId = T::AmmId;
-        type AssetType = AssetType;
-        type Balance = T::Balance;
+        /// Swap either asset of an AMM of two assets.
+        ///
+        /// The caller must specify the following arguments
+        /// - `amm_id`: the id of the AMM to swap against
+        /// - `asset_type`: which of the two assets in the pool to use as input
+        /// - `input_amount`: amount of input asset to add to the AMM
+        /// - `output_min`: the minimum amount of the opposite asset to get in return. Prevents
+        ///   against slippage.
+        /// - `deadline`: if given, the last block the call can be executed in. Prevents against it
+        ///   being held back and executed at stale prices.
+        ///
+        /// Otherwise the same as `swap_between`, which also swaps against AMMs of more assets.
+        #[pallet::weight(T::WeightInfo::swap())]
+        pub fn swap(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            asset_type: AssetType,
+            input_amount: T::Balance,
+            output_min: T::Balance,
+            deadline: Option<T::BlockNumber>,
+        ) -> DispatchResult {
+            let (asset_in, asset_out) = Self::pair_assets(&amm_id, asset_type)?;
+            Self::swap_between(
+                origin,
+                amm_id,
+                asset_in,
+                asset_out,
+                input_amount,
+                output_min,
+                deadline,
+            )
+        }
+
+        /// Swap any two of the AMM's assets.
+        ///
+        /// The caller must specify the following arguments
+        /// - `amm_id`: the id of the AMM to swap against
+        /// - `asset_in`: the asset to send to the AMM
+        /// - `asset_out`: the asset to get from the AMM
+        /// - `input_amount`: amount of input asset to add to the AMM
+        /// - `output_min`: the minimum amount of `asset_out` to get in return. Prevents against
+        ///   slippage.
+        /// - `deadline`: if given, the last block the call can be executed in. Prevents against it
+        ///   being held back and executed at stale prices.
+        ///
+        /// If the AMM is in batch mode, the input is held in escrow and the swap is cleared at the
+        /// end of the block together with the others submitted to the AMM, all at the same price.
+        /// It is refunded instead if that price would not pay out `output_min`.
+        #[pallet::weight(T::WeightInfo::swap())]
+        pub fn swap_between(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            input_amount: T::Balance,
+            output_min: T::Balance,
+            deadline: Option<T::BlockNumber>,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+            Self::ensure_before_deadline(deadline)?;
+
+            if Self::is_batched(&amm_id) {
+                return Self::submit_batch_swap(
+                    &caller,
+                    amm_id,
+                    asset_in,
+                    asset_out,
+                    input_amount,
+                    output_min,
+                );
+            }
+            Self::do_swap(&caller, amm_id, asset_in, asset_out, input_amount, output_min)?;
+
+            Ok(())
+        }
+
+        /// Swap against the AMM of a pair of assets, without knowing its id.
+        ///
+        /// The caller must specify the following arguments
+        /// - `asset_in`: the asset to send to the AMM
+        /// - `asset_out`: the asset to get from the AMM
+        /// - `fees_bps`: the fee tier of the AMM, as given at its creation or last set by the admin
+        /// - `input_amount`: amount of input asset to add to the AMM
+        /// - `output_min`: the minimum amount of `asset_out` to get in return. Prevents against
+        ///   slippage.
+        /// - `deadline`: if given, the last block the call can be executed in. Prevents against it
+        ///   being held back and executed at stale prices.
+        ///
+        /// Swaps against AMMs in batch mode are cleared at the end of the block, as with `swap`.
+        #[pallet::weight(T::WeightInfo::swap())]
+        pub fn swap_pair(
+            origin: OriginFor<T>,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            fees_bps: T::Balance,
+            input_amount: T::Balance,
+            output_min: T::Balance,
+            deadline: Option<T::BlockNumber>,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+            Self::ensure_before_deadline(deadline)?;
+
+            let amm_id =
+                Self::pair_amm(asset_in, asset_out, fees_bps).ok_or(Error::<T>::UnknownPair)?;
+            if Self::is_batched(&amm_id) {
+                return Self::submit_batch_swap(
+                    &caller,
+                    amm_id,
+                    asset_in,
+                    asset_out,
+                    input_amount,
+                    output_min,
+                );
+            }
+            Self::do_swap(&caller, amm_id, asset_in, asset_out, input_amount, output_min)?;
+
+            Ok(())
+        }
+
+        /// Swap any two of the AMM's assets, specifying the exact amount to get in return.
+        ///
+        /// The caller must specify the following arguments
+        /// - `amm_id`: the id of the AMM to swap against
+        /// - `asset_in`: the asset to send to the AMM
+        /// - `asset_out`: the asset to get from the AMM
+        /// - `output_amount`: amount of `asset_out` to get from the AMM
+        /// - `max_input`: the maximum amount of input asset to send to the AMM. Prevents against
+        ///   slippage.
+        /// - `deadline`: if given, the last block the call can be executed in. Prevents against it
+        ///   being held back and executed at stale prices.
+        ///
+        /// The input amount is computed with `SimulateSwap::output_price_between`, so the caller
+        /// receives at least `output_amount`.
+        #[pallet::weight(T::WeightInfo::swap())]
+        pub fn swap_exact_out(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            output_amount: T::Balance,
+            max_input: T::Balance,
+            deadline: Option<T::BlockNumber>,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+            Self::ensure_before_deadline(deadline)?;
+
+            Self::do_swap_exact_out(
+                &caller,
+                amm_id,
+                asset_in,
+                asset_out,
+                output_amount,
+                max_input,
+            )?;
+
+            Ok(())
+        }
+
+        /// Swap through a sequence of AMMs, using the output of each hop as the input of the next.
+        ///
+        /// The caller must specify the following arguments
+        /// - `asset_in`: the asset to send to the first AMM
+        /// - `route`: the ordered hops to take, each given by the id of the AMM to swap against and
+        ///   the asset to get out of it
+        /// - `input_amount`: amount of `asset_in` to add to the first AMM
+        /// - `output_min`: the minimum amount of the final asset to get in return. Prevents against
+        ///   slippage accumulated over the whole route.
+        /// - `deadline`: if given, the last block the call can be executed in. Prevents against it
+        ///   being held back and executed at stale prices.
+        ///
+        /// If any of the hops fails, the whole route is reverted.
+        #[pallet::weight(T::WeightInfo::swap().saturating_mul(route.len() as Weight))]
+        pub fn swap_route(
+            origin: OriginFor<T>,
+            asset_in: T::AssetId,
+            route: Route<T>,
+            input_amount: T::Balance,
+            output_min: T::Balance,
+            deadline: Option<T::BlockNumber>,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+            Self::ensure_before_deadline(deadline)?;
+
+            Self::do_swap_route(&caller, asset_in, route, input_amount, output_min)?;
+
+            Ok(())
+        }
+
+        /// Borrow assets from an AMM and pay them back, plus fees, within the same call.
+        ///
+        /// The caller must specify the following arguments
+        /// - `amm_id`: the id of the AMM to borrow from
+        /// - `amounts`: the amount of each asset to borrow, in the order given at creation
+        ///
+        /// The assets are sent to the caller before calling `T::FlashBorrower`, which must send
+        /// enough of them back to the AMM's account. They can be paid back in any of the AMM's
+        /// assets, which trades them like a swap. The swap fee is charged on everything paid back,
+        /// and the AMM's invariant net of fees must not decrease, or the whole call is reverted.
+        ///
+        /// Concentrated liquidity AMMs do not lend assets, and the liquidity of the AMM can't
+        /// change until the assets are paid back.
+        #[pallet::weight(1_000)]
+        pub fn flash_swap(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            amounts: BoundedVec<T::Balance, T::MaxAssetsPerPool>,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            Self::do_flash_swap(&caller, amm_id, amounts)
+        }
+
+        /// Place an order to swap against an AMM once its price allows, without having to watch it.
+        ///
+        /// The caller must specify the following arguments
+        /// - `amm_id`: the id of the AMM to swap against
+        /// - `asset_in`: the asset to send to the AMM
+        /// - `asset_out`: the asset to get from the AMM
+        /// - `input_amount`: amount of input asset to add to the AMM
+        /// - `output_min`: the amount of `asset_out` to get in return, at least, which sets the
+        ///   limit price of the order
+        ///
+        /// `input_amount` is held in escrow until the order is filled or cancelled. Orders are
+        /// checked at the end of blocks with weight to spare, and filled at once by a swap paying
+        /// out at least `output_min`.
+        #[pallet::weight(1_000)]
+        pub fn place_limit_order(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            input_amount: T::Balance,
+            output_min: T::Balance,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            ensure!(!input_amount.is_zero(), Error::<T>::ZeroAmount);
+            match Self::concentrated_amm(&amm_id) {
+                Some(amm) => {
+                    Self::concentrated_swap_indices(&amm, &asset_in, &asset_out)?;
+                }
+                None => {
+                    let amm_state = Self::try_get_amm_state(&amm_id)?;
+                    Self::swap_indices(&amm_state, &asset_in, &asset_out)?;
+                }
+            }
+
+            let escrow = Self::order_escrow_account();
+            T::Assets::transfer(asset_in, &caller, &escrow, input_amount, false)?;
+
+            let order_id = Self::limit_order_count();
+            LimitOrderCount::<T>::set(order_id.checked_add(1).ok_or(ArithmeticError::Overflow)?);
+            LimitOrders::<T>::insert(
+                order_id,
+                LimitOrder {
+                    owner: caller.clone(),
+                    amm_id,
+                    asset_in,
+                    asset_out,
+                    input_amount,
+                    output_min,
+                },
+            );
+
+            Self::deposit_event(Event::<T>::LimitOrderPlaced {
+                order_id,
+                owner: caller,
+                amm_id,
+                asset_in,
+                asset_out,
+                input_amount,
+                output_min,
+            });
+
+            Ok(())
+        }
+
+        /// Cancel a resting limit order, getting back its input from escrow. Only the owner of the
+        /// order can do so.
+        #[pallet::weight(1_000)]
+        pub fn cancel_limit_order(origin: OriginFor<T>, order_id: OrderId) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            let order = Self::try_get_owned_order(&order_id, &caller)?;
+            let escrow = Self::order_escrow_account();
+            T::Assets::transfer(order.asset_in, &escrow, &caller, order.input_amount, false)?;
+            LimitOrders::<T>::remove(order_id);
+
+            Self::deposit_event(Event::<T>::LimitOrderCancelled {
+                order_id,
+                owner: caller,
+            });
+
+            Ok(())
+        }
+
+        /// Create a new concentrated liquidity AMM, where liquidity providers choose the range of
+        /// prices their assets are traded in. As long as the price stays within range, positions
+        /// earn as much in fees as they would in a constant product AMM with far more capital.
+        ///
+        /// The caller has to specify:
+        /// - `assets`: the ids of the two assets to pool, which must be different. Prices are those
+        ///   of the first asset in terms of the second one.
+        /// - `fees_bps`: the share of input asset to be subtracted as fees for liquidity providers
+        ///   in swaps, below 10,000.
+        /// - `tick_spacing`: positions may only start and end at multiples of this tick. Each tick
+        ///   is a price change of 0.01%.
+        /// - `initial_tick`: the tick of the initial price, which is `1.0001 ^ initial_tick`.
+        ///
+        /// The AMM shares ids with the other kinds of AMM and is swapped against with the same
+        /// extrinsics, but liquidity is provided with `open_position` instead of
+        /// `provide_liquidity`. It does not record price observations. As with `create_amm`, there
+        /// can only be one AMM of two assets for each pair and `fees_bps`, and `T::PoolDeposit` is
+        /// reserved from the caller until the AMM is destroyed.
+        #[pallet::weight(1_000)]
+        pub fn create_concentrated_amm(
+            origin: OriginFor<T>,
+            assets: [T::AssetId; 2],
+            fees_bps: T::Balance,
+            tick_spacing: u32,
+            initial_tick: i32,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            ensure!(!Self::is_frozen(), Error::<T>::PalletIsFrozen);
+            ensure!(assets[0] != assets[1], Error::<T>::InvalidAssets);
+            ensure!(assets.iter().all(Self::asset_exists), Error::<T>::UnknownAsset);
+            ensure!(fees_bps < 10_000_u64.into(), Error::<T>::InvalidFees);
+            ensure!(
+                tick_spacing > 0
+                    && tick_spacing <= MAX_TICK as u32
+                    && (MIN_TICK..=MAX_TICK).contains(&initial_tick),
+                Error::<T>::InvalidTicks
+            );
+
+            let amm_id = Self::amm_count();
+            Self::index_pair(&amm_id, &assets, fees_bps)?;
+            Self::reserve_pool_deposit(&amm_id, &caller)?;
+            let amm = ConcentratedAmm {
+                assets,
+                fees_bps,
+                tick_spacing,
+                sqrt_price: sqrt_price_at_tick(initial_tick)?,
+                tick: initial_tick,
+                liquidity: 0,
+                fee_growths: Default::default(),
+                ticks: Default::default(),
+            };
+
+            AmmCount::<T>::set(
+                amm_id
+                    .checked_add(&One::one())
+                    .ok_or(ArithmeticError::Overflow)?,
+            );
+            ConcentratedAmms::<T>::insert(amm_id, amm);
+
+            Self::deposit_event(Event::<T>::AmmCreated(amm_id));
+            Ok(())
+        }
+
+        /// Open a position providing liquidity to a concentrated liquidity AMM within a range of
+        /// prices.
+        ///
+        /// The caller must specify the following parameters
+        /// - `amm_id`: the id of the AMM
+        /// - `lower_tick`, `upper_tick`: the range of ticks to provide liquidity in, both multiples
+        ///   of the AMM's tick spacing
+        /// - `amounts`: the most of each asset to deposit
+        ///
+        /// Only the first asset is deposited if the range is above the current price, and only the
+        /// second one if it is below. Otherwise, both are deposited in a ratio that depends on
+        /// where the price is within the range, providing as much liquidity as `amounts` allow.
+        ///
+        /// The position is a non-fungible record owned by the caller, earning fees from swaps while
+        /// the price is within its range.
+        #[pallet::weight(1_000)]
+        pub fn open_position(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            lower_tick: i32,
+            upper_tick: i32,
+            amounts: [T::Balance; 2],
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            let mut amm = Self::try_get_concentrated_amm(&amm_id)?;
+            Self::ensure_not_paused(&amm_id)?;
+            Self::ensure_valid_ticks(&amm, lower_tick, upper_tick)?;
+
+            let liquidity = Self::liquidity_for_amounts(&amm, lower_tick, upper_tick, amounts)?;
+            ensure!(liquidity > 0, Error::<T>::ZeroAmount);
+            let deposited = Self::position_amounts(&amm, lower_tick, upper_tick, liquidity, true)?;
+            ensure!(
+                deposited[0] <= amounts[0] && deposited[1] <= amounts[1],
+                Error::<T>::SlippageExceeded
+            );
+
+            let position_id = Self::position_count();
+            let mut position = Position {
+                owner: caller.clone(),
+                amm_id,
+                lower_tick,
+                upper_tick,
+                liquidity: 0,
+                fee_growths_inside: Default::default(),
+                fees_owed: [Zero::zero(); 2],
+            };
+            Self::update_position(&mut amm, &mut position, liquidity, true)?;
+
+            let amm_account = Self::amm_account(&amm_id);
+            for (asset, amount) in amm.assets.iter().zip(deposited) {
+                if !amount.is_zero() {
+                    T::Assets::transfer(*asset, &caller, &amm_account, amount, false)?;
+                }
+            }
+
+            PositionCount::<T>::set(position_id.checked_add(1).ok_or(ArithmeticError::Overflow)?);
+            Positions::<T>::insert(position_id, position);
+            ConcentratedAmms::<T>::insert(&amm_id, amm);
+
+            Self::deposit_event(Event::<T>::PositionOpened {
+                position_id,
+                owner: caller,
+                amm_id,
+                lower_tick,
+                upper_tick,
+                liquidity,
+                amounts: deposited,
+            });
+
+            Ok(())
+        }
+
+        /// Collect the fees earned so far by a concentrated liquidity position, without
+        /// withdrawing its liquidity. Only the owner of the position can do so.
+        #[pallet::weight(1_000)]
+        pub fn collect_fees(origin: OriginFor<T>, position_id: PositionId) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            let mut position = Self::try_get_owned_position(&position_id, &caller)?;
+            let mut amm = Self::try_get_concentrated_amm(&position.amm_id)?;
+
+            // Adding no liquidity leaves the AMM as is.
+            Self::update_position(&mut amm, &mut position, 0, true)?;
+            let amounts = position.fees_owed;
+            position.fees_owed = [Zero::zero(); 2];
+
+            Self::pay_out(&position.amm_id, &amm, &caller, amounts)?;
+            Positions::<T>::insert(position_id, position);
+
+            Self::deposit_event(Event::<T>::FeesCollected {
+                position_id,
+                owner: caller,
+                amounts,
+            });
+
+            Ok(())
+        }
+
+        /// Close a concentrated liquidity position, withdrawing its share of the AMM's assets
+        /// along with the fees it earned. Only the owner of the position can do so.
+        #[pallet::weight(1_000)]
+        pub fn close_position(origin: OriginFor<T>, position_id: PositionId) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            let mut position = Self::try_get_owned_position(&position_id, &caller)?;
+            let mut amm = Self::try_get_concentrated_amm(&position.amm_id)?;
+
+            let liquidity = position.liquidity;
+            let withdrawn = Self::position_amounts(
+                &amm,
+                position.lower_tick,
+                position.upper_tick,
+                liquidity,
+                false,
+            )?;
+            Self::update_position(&mut amm, &mut position, liquidity, false)?;
+
+            let mut amounts = position.fees_owed;
+            for (amount, withdrawn) in amounts.iter_mut().zip(withdrawn) {
+                *amount = amount.try_add(&withdrawn)?;
+            }
+
+            Self::pay_out(&position.amm_id, &amm, &caller, amounts)?;
+            ConcentratedAmms::<T>::insert(&position.amm_id, amm);
+            Positions::<T>::remove(position_id);
+
+            Self::deposit_event(Event::<T>::PositionClosed {
+                position_id,
+                owner: caller,
+                amounts,
+            });
+
+            Ok(())
+        }
+
+        /// Transfer a concentrated liquidity position to another account, along with the fees it
+        /// earned and were not collected yet. Only the owner of the position can do so.
+        #[pallet::weight(1_000)]
+        pub fn transfer_position(
+            origin: OriginFor<T>,
+            position_id: PositionId,
+            to: T::AccountId,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            let mut position = Self::try_get_owned_position(&position_id, &caller)?;
+            position.owner = to.clone();
+            Positions::<T>::insert(position_id, position);
+
+            Self::deposit_event(Event::<T>::PositionTransferred {
+                position_id,
+                from: caller,
+                to,
+            });
+
+            Ok(())
+        }
+
+        /// Create a farm emitting `reward_per_block` of `reward_asset` to the accounts staking
+        /// shares of an AMM other than a concentrated liquidity one, from block `start` until
+        /// block `end`. Each block's rewards are split between stakers in proportion to their
+        /// stake.
+        ///
+        /// The farm only emits the rewards funded with `fund_farm`, and stops early if they run
+        /// out.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn create_farm(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            reward_asset: T::AssetId,
+            reward_per_block: T::Balance,
+            start: T::BlockNumber,
+            end: T::BlockNumber,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            let amm_state = Self::try_get_amm_state(&amm_id)?;
+            ensure!(Self::asset_exists(&reward_asset), Error::<T>::UnknownAsset);
+            Self::ensure_valid_farm_period(start, end)?;
+
+            let farm_id = Self::farm_count();
+            let farm = Farm {
+                amm_id,
+                share_asset: amm_state.share_asset,
+                reward_asset,
+                reward_per_block,
+                start,
+                end,
+                total_staked: Zero::zero(),
+                reward_per_share: U256::zero(),
+                last_update: start,
+                unallocated: Zero::zero(),
+            };
+            FarmCount::<T>::set(farm_id.checked_add(1).ok_or(ArithmeticError::Overflow)?);
+            Farms::<T>::insert(farm_id, farm);
+
+            Self::deposit_event(Event::<T>::FarmCreated {
+                farm_id,
+                amm_id,
+                reward_asset,
+                reward_per_block,
+                start,
+                end,
+            });
+
+            Ok(())
+        }
+
+        /// Add `amount` of a farm's reward asset to the rewards it can emit.
+        ///
+        /// When called by `T::AdminOrigin`, the rewards come from `farm_rewards_account`, which
+        /// belongs to the pallet. Any other account can fund a farm out of its own balance.
+        #[pallet::weight(1_000)]
+        pub fn fund_farm(
+            origin: OriginFor<T>,
+            farm_id: FarmId,
+            amount: T::Balance,
+        ) -> DispatchResult {
+            let funder = match T::AdminOrigin::try_origin(origin) {
+                Ok(_) => Self::farm_rewards_account(),
+                Err(origin) => ensure_signed(origin)?,
+            };
+            ensure!(!amount.is_zero(), Error::<T>::ZeroAmount);
+
+            let mut farm = Self::try_get_farm(&farm_id)?;
+            // The new rewards are only for blocks to come, even if the farm ran out meanwhile.
+            Self::update_farm(&mut farm)?;
+            T::Assets::transfer(
+                farm.reward_asset,
+                &funder,
+                &Self::farm_account(&farm_id),
+                amount,
+                false,
+            )?;
+            farm.unallocated = farm.unallocated.try_add(&amount)?;
+            Farms::<T>::insert(farm_id, farm);
+
+            Self::deposit_event(Event::<T>::FarmFunded { farm_id, funder, amount });
+
+            Ok(())
+        }
+
+        /// Change the rewards a farm emits per block from now on, and the block it ends at. The
+        /// farm may have ended already, in which case it starts emitting again.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn set_farm_rewards(
+            origin: OriginFor<T>,
+            farm_id: FarmId,
+            reward_per_block: T::Balance,
+            end: T::BlockNumber,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            let mut farm = Self::try_get_farm(&farm_id)?;
+            Self::ensure_valid_farm_period(farm.start, end)?;
+            Self::update_farm(&mut farm)?;
+            // Blocks after the previous end are not emitted for retroactively.
+            let now = frame_system::Pallet::<T>::block_number();
+            farm.last_update = farm.last_update.max(now);
+            farm.reward_per_block = reward_per_block;
+            farm.end = end;
+            Farms::<T>::insert(farm_id, farm);
+
+            Self::deposit_event(Event::<T>::FarmUpdated { farm_id, reward_per_block, end });
+
+            Ok(())
+        }
+
+        /// Stop a farm's emissions in the current block and send the rewards it had not emitted
+        /// yet to `T::ProtocolFeeRecipient`. Stakers can still claim what they earned and unstake.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn end_farm(origin: OriginFor<T>, farm_id: FarmId) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            let mut farm = Self::try_get_farm(&farm_id)?;
+            Self::update_farm(&mut farm)?;
+            let now = frame_system::Pallet::<T>::block_number();
+            farm.end = farm.end.min(now);
+
+            let refunded = farm.unallocated;
+            if !refunded.is_zero() {
+                T::Assets::transfer(
+                    farm.reward_asset,
+                    &Self::farm_account(&farm_id),
+                    &T::ProtocolFeeRecipient::get(),
+                    refunded,
+                    false,
+                )?;
+            }
+            farm.unallocated = Zero::zero();
+            Farms::<T>::insert(farm_id, farm);
+
+            Self::deposit_event(Event::<T>::FarmEnded { farm_id, refunded });
+
+            Ok(())
+        }
+
+        /// Stake `amount` of the shares of a farm's AMM, to earn part of its rewards from the
+        /// next block on.
+        #[pallet::weight(1_000)]
+        pub fn stake(origin: OriginFor<T>, farm_id: FarmId, amount: T::Balance) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+            ensure!(!amount.is_zero(), Error::<T>::ZeroAmount);
+
+            let mut farm = Self::try_get_farm(&farm_id)?;
+            Self::update_farm(&mut farm)?;
+            let mut stake = Self::stake_of(farm_id, &caller).unwrap_or(Stake {
+                amount: Zero::zero(),
+                reward_per_share: farm.reward_per_share,
+                rewards_owed: U256::zero(),
+            });
+            Self::update_stake(&mut farm, &mut stake, amount, true)?;
+
+            let farm_account = Self::farm_account(&farm_id);
+            T::Assets::transfer(farm.share_asset, &caller, &farm_account, amount, false)?;
+            Stakes::<T>::insert(farm_id, &caller, stake);
+            Farms::<T>::insert(farm_id, farm);
+
+            Self::deposit_event(Event::<T>::Staked { farm_id, user: caller, amount });
+
+            Ok(())
+        }
+
+        /// Unstake `amount` of the shares staked in a farm. The rewards earned so far stay to be
+        /// claimed with `claim_rewards`.
+        #[pallet::weight(1_000)]
+        pub fn unstake(
+            origin: OriginFor<T>,
+            farm_id: FarmId,
+            amount: T::Balance,
+        ) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+            ensure!(!amount.is_zero(), Error::<T>::ZeroAmount);
+
+            let mut farm = Self::try_get_farm(&farm_id)?;
+            let mut stake = Self::stake_of(farm_id, &caller).ok_or(Error::<T>::InsufficientStake)?;
+            Self::update_farm(&mut farm)?;
+            Self::update_stake(&mut farm, &mut stake, amount, false)?;
+
+            let farm_account = Self::farm_account(&farm_id);
+            T::Assets::transfer(farm.share_asset, &farm_account, &caller, amount, false)?;
+            Self::save_stake(farm_id, &caller, stake);
+            Farms::<T>::insert(farm_id, farm);
+
+            Self::deposit_event(Event::<T>::Unstaked { farm_id, user: caller, amount });
+
+            Ok(())
+        }
+
+        /// Claim the rewards earned so far in a farm.
+        #[pallet::weight(1_000)]
+        pub fn claim_rewards(origin: OriginFor<T>, farm_id: FarmId) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            let mut farm = Self::try_get_farm(&farm_id)?;
+            let mut stake = Self::stake_of(farm_id, &caller).ok_or(Error::<T>::InsufficientStake)?;
+            Self::update_farm(&mut farm)?;
+            Self::update_stake(&mut farm, &mut stake, Zero::zero(), true)?;
+
+            let amount = Self::take_rewards(&mut stake)?;
+            if !amount.is_zero() {
+                let farm_account = Self::farm_account(&farm_id);
+                T::Assets::transfer(farm.reward_asset, &farm_account, &caller, amount, false)?;
+            }
+            Self::save_stake(farm_id, &caller, stake);
+            Farms::<T>::insert(farm_id, farm);
+
+            Self::deposit_event(Event::<T>::RewardsClaimed { farm_id, user: caller, amount });
+
+            Ok(())
+        }
+
+        /// Withdraw all of the shares staked in a farm without claiming rewards, in case that
+        /// fails. The rewards owed to the stake are forfeited and go back to the farm's funds.
+        #[pallet::weight(1_000)]
+        pub fn emergency_withdraw(origin: OriginFor<T>, farm_id: FarmId) -> DispatchResult {
+            let caller = ensure_signed(origin)?;
+
+            let mut farm = Self::try_get_farm(&farm_id)?;
+            let mut stake =
+                Stakes::<T>::take(farm_id, &caller).ok_or(Error::<T>::InsufficientStake)?;
+            // Emit the rewards due so far while the stake still counts, so that they are not left
+            // for the other stakers. Failing to update is what this call is for, so the stake only
+            // forfeits what it was owed before in that case.
+            let mut updated = farm.clone();
+            if Self::update_farm(&mut updated).is_ok() {
+                farm = updated;
+                let _ = Self::update_stake(&mut farm, &mut stake, Zero::zero(), false);
+            }
+            farm.total_staked = farm.total_staked.saturating_sub(stake.amount);
+            let forfeited = Self::claimable_rewards(&stake).unwrap_or_else(|_| Zero::zero());
+            farm.unallocated = farm.unallocated.saturating_add(forfeited);
+
+            let farm_account = Self::farm_account(&farm_id);
+            T::Assets::transfer(farm.share_asset, &farm_account, &caller, stake.amount, false)?;
+            Farms::<T>::insert(farm_id, farm);
+
+            Self::deposit_event(Event::<T>::EmergencyWithdrawn {
+                farm_id,
+                user: caller,
+                amount: stake.amount,
+            });
+
+            Ok(())
+        }
+
+        /// Destroy an AMM without liquidity, along with its LP share asset, refunding the
+        /// `T::PoolDeposit` reserved from its creator and what is left of the funding for the
+        /// share asset's metadata.
+        ///
+        /// Can be called by the creator of the AMM or by `T::AdminOrigin`. Other AMMs are empty
+        /// once only the shares locked by their first deposit remain, and the assets backing them
+        /// are sent to `T::ProtocolFeeRecipient`, along with any donated to the AMM. Concentrated
+        /// liquidity AMMs can only be destroyed once all of their positions are closed.
+        #[pallet::weight(1_000)]
+        pub fn destroy_amm(origin: OriginFor<T>, amm_id: T::AmmId) -> DispatchResult {
+            Self::ensure_amm_exists(&amm_id)?;
+
+            let deposit = Self::pool_deposit(&amm_id);
+            if let Err(origin) = T::AdminOrigin::try_origin(origin) {
+                let caller = ensure_signed(origin)?;
+                ensure!(
+                    matches!(&deposit, Some((creator, _)) if *creator == caller),
+                    Error::<T>::NotAmmCreator
+                );
+            }
+
+            match Self::concentrated_amm(&amm_id) {
+                Some(amm) => {
+                    // Every open position has liquidity starting and ending at some tick.
+                    ensure!(amm.ticks.is_empty(), Error::<T>::AmmNotEmpty);
+                    Self::unindex_pair(&amm_id, &amm.assets, amm.fees_bps);
+                    ConcentratedAmms::<T>::remove(&amm_id);
+                }
+                None => {
+                    let amm_state = Self::try_get_amm_state(&amm_id)?;
+                    let locked_shares =
+                        T::Assets::balance(amm_state.share_asset, &Self::pallet_account());
+                    ensure!(amm_state.total_shares <= locked_shares, Error::<T>::AmmNotEmpty);
+
+                    // Anything donated to the AMM on top of its reserves goes along with them.
+                    let amm_account = Self::amm_account(&amm_id);
+                    for asset in amm_state.assets.iter() {
+                        T::Assets::transfer(
+                            *asset,
+                            &amm_account,
+                            &T::ProtocolFeeRecipient::get(),
+                            T::Assets::balance(*asset, &amm_account),
+                            false,
+                        )?;
+                    }
+
+                    let witness = T::Assets::get_destroy_witness(&amm_state.share_asset)
+                        .ok_or(Error::<T>::InvalidShareAsset)?;
+                    T::Assets::destroy(amm_state.share_asset, witness, None)?;
+
+                    // Destroying the share asset released its metadata deposit.
+                    // Other assets donated to the AMM's account keep it alive, in which case its
+                    // existential deposit stays behind.
+                    if let Some((creator, _)) = &deposit {
+                        let free = T::Currency::free_balance(&amm_account);
+                        let (refund, existence) =
+                            if frame_system::Pallet::<T>::can_dec_provider(&amm_account) {
+                                (free, ExistenceRequirement::AllowDeath)
+                            } else {
+                                (
+                                    free.saturating_sub(T::Currency::minimum_balance()),
+                                    ExistenceRequirement::KeepAlive,
+                                )
+                            };
+                        T::Currency::transfer(&amm_account, creator, refund, existence)?;
+                    }
+
+                    Self::unindex_pair(&amm_id, &amm_state.assets, amm_state.fees_bps);
+                    AmmStates::<T>::remove(&amm_id);
+                    PriceObservations::<T>::remove(&amm_id);
+                    PriceObservationHeads::<T>::remove(&amm_id);
+                }
+            }
+            PausedAmms::<T>::remove(&amm_id);
+            BatchedAmms::<T>::remove(&amm_id);
+
+            if let Some((creator, deposit)) = deposit {
+                T::Currency::unreserve(&creator, deposit);
+                PoolDeposits::<T>::remove(&amm_id);
+            }
+
+            Self::deposit_event(Event::<T>::AmmDestroyed { amm_id });
+
+            Ok(())
+        }
+
+        /// Update the share of input asset subtracted as fees in swaps against an AMM of any kind.
+        /// AMMs of two assets move to the new fee tier, which must not be taken by another AMM of
+        /// the same pair.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn set_fees(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            fees_bps: T::Balance,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            ensure!(fees_bps < 10_000_u64.into(), Error::<T>::InvalidFees);
+
+            match Self::concentrated_amm(&amm_id) {
+                Some(mut amm) => {
+                    Self::reindex_pair(&amm_id, &amm.assets, amm.fees_bps, fees_bps)?;
+                    amm.fees_bps = fees_bps;
+                    ConcentratedAmms::<T>::insert(&amm_id, amm);
+                }
+                None => {
+                    let mut amm_state = Self::try_get_amm_state(&amm_id)?;
+                    Self::reindex_pair(&amm_id, &amm_state.assets, amm_state.fees_bps, fees_bps)?;
+                    amm_state.fees_bps = fees_bps;
+                    AmmStates::<T>::insert(&amm_id, amm_state);
+                }
+            }
+
+            Self::deposit_event(Event::<T>::FeesUpdated { amm_id, fees_bps });
+
+            Ok(())
+        }
+
+        /// Update the amplification of a StableSwap AMM. The change applies immediately and moves
+        /// prices away from the peg, so it should be done in small steps.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn set_amplification(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            amplification: u32,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            let mut amm_state = Self::try_get_amm_state(&amm_id)?;
+            ensure!(
+                matches!(amm_state.curve, Curve::StableSwap { .. }) && amplification > 0,
+                Error::<T>::InvalidCurve
+            );
+            Self::record_price_observation(&amm_id, &amm_state)?;
+
+            amm_state.curve = Curve::StableSwap { amplification };
+            AmmStates::<T>::insert(&amm_id, amm_state);
+
+            Self::deposit_event(Event::<T>::AmplificationUpdated {
+                amm_id,
+                amplification,
+            });
+
+            Ok(())
+        }
+
+        /// Halt swaps and deposits in an AMM of any kind, while still allowing liquidity providers
+        /// to withdraw.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn pause_amm(origin: OriginFor<T>, amm_id: T::AmmId) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            Self::ensure_amm_exists(&amm_id)?;
+            PausedAmms::<T>::insert(&amm_id, true);
+
+            Self::deposit_event(Event::<T>::AmmPaused { amm_id });
+
+            Ok(())
+        }
+
+        /// Allow swaps and deposits again in a paused AMM.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn unpause_amm(origin: OriginFor<T>, amm_id: T::AmmId) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            Self::ensure_amm_exists(&amm_id)?;
+            PausedAmms::<T>::remove(&amm_id);
+
+            Self::deposit_event(Event::<T>::AmmUnpaused { amm_id });
+
+            Ok(())
+        }
+
+        /// Put a constant product AMM of two assets in batch mode, or take it out of it.
+        ///
+        /// In batch mode, swaps submitted with `swap`, `swap_between` or `swap_pair` during a block
+        /// are cleared together at the end of it, at a single price for all of them, so that
+        /// ordering them within the block gives no edge. What sellers of one asset don't match with
+        /// sellers of the other is swapped against the AMM. Other ways of swapping against it are
+        /// disabled.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn set_batch_mode(
+            origin: OriginFor<T>,
+            amm_id: T::AmmId,
+            enabled: bool,
+        ) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            if enabled {
+                let amm_state = Self::try_get_amm_state(&amm_id)?;
+                ensure!(
+                    amm_state.curve == Curve::ConstantProduct && amm_state.assets.len() == 2,
+                    Error::<T>::InvalidCurve
+                );
+                BatchedAmms::<T>::insert(&amm_id, true);
+            } else {
+                Self::ensure_amm_exists(&amm_id)?;
+                BatchedAmms::<T>::remove(&amm_id);
+            }
+
+            Self::deposit_event(Event::<T>::BatchModeSet { amm_id, enabled });
+
+            Ok(())
+        }
+
+        /// Halt swaps and deposits in every AMM, as well as the creation of new ones. Liquidity
+        /// providers can still withdraw.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn freeze(origin: OriginFor<T>) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            Frozen::<T>::put(true);
+
+            Self::deposit_event(Event::<T>::Frozen);
+
+            Ok(())
+        }
+
+        /// Lift a freeze of the pallet. AMMs paused individually remain paused.
+        ///
+        /// Can only be called by `T::AdminOrigin`.
+        #[pallet::weight(1_000)]
+        pub fn unfreeze(origin: OriginFor<T>) -> DispatchResult {
+            T::AdminOrigin::ensure_origin(origin)?;
+
+            Frozen::<T>::kill();
+
+            Self::deposit_event(Event::<T>::Unfrozen);
+
+            Ok(())
+        }
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    //                                      Trait Impls
+    // ---------------------------------------------------------------------------------------------
+
+    impl<T: Config> SimulateSwap for Pallet<T> {
+        type AmmId = T::AmmId;
+        type AssetId = T::AssetId;
+        type AssetType = AssetType;
+        type Balance = T::Balance;
+
+        fn simulate_swap(
+            amm_id: Self::AmmId,
+            asset_type: Self::AssetType,
+            amount: Self::Balance,
+        ) -> Result<Self::Balance, DispatchError> {
+            let (asset_in, asset_out) = Self::pair_assets(&amm_id, asset_type)?;
+            Self::simulate_swap_between(amm_id, asset_in, asset_out, amount)
+        }
+
+        fn output_price(
+            amm_id: Self::AmmId,
+            asset_type: Self::AssetType,
+            amount: Self::Balance,
+        ) -> Result<Self::Balance, DispatchError> {
+            let (asset_out, asset_in) = Self::pair_assets(&amm_id, asset_type)?;
+            Self::output_price_between(amm_id, asset_in, asset_out, amount)
+        }
+
+        fn simulate_swap_between(
+            amm_id: Self::AmmId,
+            asset_in: Self::AssetId,
+            asset_out: Self::AssetId,
+            amount: Self::Balance,
+        ) -> Result<Self::Balance, DispatchError> {
+            if let Some(mut amm) = Self::concentrated_amm(&amm_id) {
+                let (index_in, _) = Self::concentrated_swap_indices(&amm, &asset_in, &asset_out)?;
+                let (_, output_amount, _) =
+                    Self::concentrated_swap(&mut amm, index_in, amount, true)?;
+                return Ok(output_amount);
+            }
+
+            let amm_state = Self::try_get_amm_state(&amm_id)?;
+            let (index_in, index_out) = Self::swap_indices(&amm_state, &asset_in, &asset_out)?;
+
+            let full_bps: T::Balance = 10_000_u64.into();
+            // net_amount = (10000 - fees) * amount / 10000
+            let net_amount = mul_div(
+                full_bps.try_sub(&amm_state.fees_bps)?,
+                amount,
+                full_bps,
+                Rounding::Down,
+            )?;
+
+            let input_reserves = amm_state.reserves[index_in];
+            let output_reserves_before = amm_state.reserves[index_out];
+            let input_reserves_after = input_reserves.try_add(&net_amount)?;
+
+            let output_reserves_after = match amm_state.curve {
+                // Only the two traded reserves change, so their product must stay constant.
+                Curve::ConstantProduct => mul_div(
+                    input_reserves,
+                    output_reserves_before,
+                    input_reserves_after,
+                    Rounding::Down,
+                )?,
+                Curve::StableSwap { amplification } => {
+                    let mut reserves = amm_state.reserves_u256();
+                    let d = stable_swap::invariant(&reserves, amplification)?;
+                    reserves[index_in] = input_reserves_after.into();
+                    let reserve = stable_swap::reserve(&reserves, index_out, d, amplification)?;
+                    // Round against the trader to absorb the error of Newton's method.
+                    from_u256::<T::Balance>(reserve)?.saturating_add(One::one())
+                }
+                Curve::Weighted { .. } => {
+                    ensure!(
+                        net_amount <= input_reserves.try_div(&2_u64.into())?,
+                        Error::<T>::SwapTooLarge
+                    );
+                    let output = weighted::output(
+                        input_reserves.into(),
+                        amm_state.weight(index_in).into(),
+                        output_reserves_before.into(),
+                        amm_state.weight(index_out).into(),
+                        net_amount.into(),
+                    )?;
+                    output_reserves_before.try_sub(&from_u256(output)?)?
+                }
+            };
+            // Ensure reserves are not depleted
+            let mut output_amount = output_reserves_before.try_sub(&output_reserves_after)?;
+            if output_reserves_after.is_zero() {
+                output_amount = output_amount.try_sub(&One::one())?;
+            }
+
+            Ok(output_amount)
+        }
+
+        fn output_price_between(
+            amm_id: Self::AmmId,
+            asset_in: Self::AssetId,
+            asset_out: Self::AssetId,
+            amount: Self::Balance,
+        ) -> Result<Self::Balance, DispatchError> {
+            if let Some(mut amm) = Self::concentrated_amm(&amm_id) {
+                let (index_in, _) = Self::concentrated_swap_indices(&amm, &asset_in, &asset_out)?;
+                let (input_amount, ..) =
+                    Self::concentrated_swap(&mut amm, index_in, amount, false)?;
+                return Ok(input_amount);
+            }
+
+            let amm_state = Self::try_get_amm_state(&amm_id)?;
+            let (index_in, index_out) = Self::swap_indices(&amm_state, &asset_in, &asset_out)?;
+
+            let input_reserves_before = amm_state.reserves[index_in];
+            let output_reserves_before = amm_state.reserves[index_out];
+            let output_reserves_after = output_reserves_before.try_sub(&amount)?;
+
+            ensure!(!output_reserves_after.is_zero(), Error::<T>::InsufficientLiquidity);
+
+            // Round up so the caller is never short of the requested output.
+            let input_reserves_after = match amm_state.curve {
+                Curve::ConstantProduct => mul_div(
+                    input_reserves_before,
+                    output_reserves_before,
+                    output_reserves_after,
+                    Rounding::Up,
+                )?,
+                Curve::StableSwap { amplification } => {
+                    let mut reserves = amm_state.reserves_u256();
+                    let d = stable_swap::invariant(&reserves, amplification)?;
+                    // Target one unit less of output reserves to make up for the one that
+                    // `simulate_swap` keeps in the pool.
+                    reserves[index_out] = output_reserves_after.try_sub(&One::one())?.into();
+                    let reserve = stable_swap::reserve(&reserves, index_in, d, amplification)?;
+                    from_u256::<T::Balance>(reserve)?.try_add(&One::one())?
+                }
+                Curve::Weighted { .. } => {
+                    ensure!(
+                        amount <= output_reserves_before.try_div(&3_u64.into())?,
+                        Error::<T>::SwapTooLarge
+                    );
+                    let input = weighted::input(
+                        output_reserves_before.into(),
+                        amm_state.weight(index_out).into(),
+                        input_reserves_before.into(),
+                        amm_state.weight(index_in).into(),
+                        amount.into(),
+                    )?;
+                    let input: T::Balance = from_u256(input)?;
+                    ensure!(
+                        input <= input_reserves_before.try_div(&2_u64.into())?,
+                        Error::<T>::SwapTooLarge
+                    );
+                    input_reserves_before.try_add(&input)?
+                }
+            };
+            let net_input = input_reserves_after.try_sub(&input_reserves_before)?;
+
+            // gross_input = net_input * 10000 / (10000 - fees)
+            let full_bps: T::Balance = 10_000_u64.into();
+            let gross_input = mul_div(
+                net_input,
+                full_bps,
+                full_bps.try_sub(&amm_state.fees_bps)?,
+                Rounding::Up,
+            )?;
+
+            Ok(gross_input)
+        }
+    }
+
+    impl<T: Config> PriceOracle for Pallet<T> {
+        type AmmId = T::AmmId;
+        type AssetId = T::AssetId;
+        type Balance = T::Balance;
+        type BlockNumber = T::BlockNumber;
+
+        fn twap(
+            amm_id: Self::AmmId,
+            asset: Self::AssetId,
+            window: Self::BlockNumber,
+        ) -> Result<Self::Balance, DispatchError> {
+            let amm_state = Self::try_get_amm_state(&amm_id)?;
+            let index = amm_state.asset_index(&asset).ok_or(Error::<T>::InvalidAsset)?;
+            let observations = Self::price_history(&amm_id);
+
+            let now = frame_system::Pallet::<T>::block_number();
+            let start = now
+                .checked_sub(&window)
+                .filter(|_| !window.is_zero())
+                .ok_or(Error::<T>::InsufficientPriceHistory)?;
+
+            // Cumulative price as if an observation was recorded right now.
+            let current = observations
+                .last()
+                .ok_or(Error::<T>::InsufficientPriceHistory)?
+                .accumulate(now, &amm_state, Self::unit())?;
+
+            // The price is constant between consecutive observations, so the cumulative price at
+            // `start` can be interpolated linearly from the ones surrounding it.
+            let position = observations
+                .iter()
+                .rposition(|observation| observation.block <= start)
+                .ok_or(Error::<T>::InsufficientPriceHistory)?;
+            let before = &observations[position];
+            let after = observations.get(position + 1).unwrap_or(&current);
+
+            let cumulative_before = before.price_cumulative(index);
+            let cumulative_start = if before.block == start {
+                cumulative_before
+            } else {
+                let elapsed = Self::blocks_to_balance(start - before.block);
+                let span = Self::blocks_to_balance(after.block - before.block);
+                let delta = wrapping_sub(after.price_cumulative(index), cumulative_before)?;
+                wrapping_add(cumulative_before, mul_div(delta, elapsed, span, Rounding::Down)?)?
+            };
+
+            // Accumulators wrap around, but their difference over the window does not as long as
+            // it fits in a balance.
+            let twap = wrapping_sub(current.price_cumulative(index), cumulative_start)?
+                .try_div(&Self::blocks_to_balance(window))?;
+
+            Ok(twap)
+        }
+    }
+
+    impl<T: Config> ExecuteSwap for Pallet<T> {
+        type AccountId = T::AccountId;
+        type AmmId = T::AmmId;
+        type AssetId = T::AssetId;
+        type Balance = T::Balance;
+
+        fn swap_exact_in(
+            who: &Self::AccountId,
+            amm_id: Self::AmmId,
+            asset_in: Self::AssetId,
+            asset_out: Self::AssetId,
+            input_amount: Self::Balance,
+            output_min: Self::Balance,
+        ) -> Result<Self::Balance, DispatchError> {
+            Self::transactional(|| {
+                Self::do_swap(who, amm_id, asset_in, asset_out, input_amount, output_min)
+            })
+        }
 
-        fn simulate_swap(
+        fn swap_exact_out(
+            who: &Self::AccountId,
             amm_id: Self::AmmId,
-            asset_type: Self::AssetType,
-            amount: Self::Balance,
+            asset_in: Self::AssetId,
+            asset_out: Self::AssetId,
+            output_amount: Self::Balance,
+            max_input: Self::Balance,
+        ) -> Result<Self::Balance, DispatchError> {
+            Self::transactional(|| {
+                Self::do_swap_exact_out(who, amm_id, asset_in, asset_out, output_amount, max_input)
+            })
+        }
+
+        fn swap_route(
+            who: &Self::AccountId,
+            asset_in: Self::AssetId,
+            route: &[(Self::AmmId, Self::AssetId)],
+            input_amount: Self::Balance,
+            output_min: Self::Balance,
         ) -> Result<Self::Balance, DispatchError> {
+            let route = Route::<T>::try_from(route.to_vec()).map_err(|_| Error::<T>::InvalidRoute)?;
+            Self::transactional(|| {
+                Self::do_swap_route(who, asset_in, route, input_amount, output_min)
+            })
+        }
+    }
+
+    // ---------------------------------------------------------------------------------------------
+    //                                      Helpers
+    // ---------------------------------------------------------------------------------------------
+
+    impl<T: Config> Pallet<T> {
+        pub(crate) fn try_get_amm_state(amm_id: &T::AmmId) -> Result<Amm<T>, DispatchError> {
+            Self::amm_state(amm_id).ok_or_else(|| Error::<T>::InvalidAmmId.into())
+        }
+
+        fn ensure_amm_exists(amm_id: &T::AmmId) -> DispatchResult {
+            ensure!(
+                AmmStates::<T>::contains_key(amm_id) || ConcentratedAmms::<T>::contains_key(amm_id),
+                Error::<T>::InvalidAmmId
+            );
+            Ok(())
+        }
+
+        /// Whether `asset` is registered in `T::Assets`, which only reports a minimum balance of
+        /// zero for unknown assets.
+        fn asset_exists(asset: &T::AssetId) -> bool {
+            !T::Assets::minimum_balance(*asset).is_zero()
+        }
+
+        /// Reserve `T::PoolDeposit` from the creator of a new AMM.
+        fn reserve_pool_deposit(amm_id: &T::AmmId, creator: &T::AccountId) -> DispatchResult {
+            let deposit = T::PoolDeposit::get();
+            T::Currency::reserve(creator, deposit)?;
+            PoolDeposits::<T>::insert(amm_id, (creator.clone(), deposit));
+            Ok(())
+        }
+
+        /// Name the share asset of a new AMM after the symbols of its assets, truncated to
+        /// `T::StringLimit`, with the metadata deposit paid by the AMM's account out of
+        /// `T::ShareMetadataDeposit` from its creator.
+        fn set_share_metadata(
+            creator: &T::AccountId,
+            amm_account: &T::AccountId,
+            share_asset: T::AssetId,
+            assets: &[T::AssetId],
+        ) -> DispatchResult {
+            T::Currency::transfer(
+                creator,
+                amm_account,
+                T::ShareMetadataDeposit::get(),
+                ExistenceRequirement::KeepAlive,
+            )?;
+
+            let symbols = assets.iter().map(T::Assets::symbol).collect::<Vec<_>>();
+            let mut pair = symbols.join(&b'-');
+            // Leave room for the " LP" and "-LP" suffixes.
+            pair.truncate((T::StringLimit::get() as usize).saturating_sub(3));
+            T::Assets::set(
+                share_asset,
+                amm_account,
+                [&pair[..], &b" LP"[..]].concat(),
+                [&pair[..], &b"-LP"[..]].concat(),
+                T::DefaultDecimals::get(),
+            )
+        }
+
+        /// Ensure a call with `deadline` can still be executed in the current block.
+        fn ensure_before_deadline(deadline: Option<T::BlockNumber>) -> DispatchResult {
+            if let Some(deadline) = deadline {
+                let now = frame_system::Pallet::<T>::block_number();
+                ensure!(now <= deadline, Error::<T>::DeadlineExpired);
+            }
+            Ok(())
+        }
+
+        /// Ensure swaps and deposits are allowed in an AMM.
+        pub(crate) fn ensure_not_paused(amm_id: &T::AmmId) -> DispatchResult {
+            ensure!(!Self::is_frozen(), Error::<T>::PalletIsFrozen);
+            ensure!(!Self::is_paused(amm_id), Error::<T>::AmmIsPaused);
+            Ok(())
+        }
+
+        fn concentrated_swap_indices(
+            amm: &ConcentratedAmm<T>,
+            asset_in: &T::AssetId,
+            asset_out: &T::AssetId,
+        ) -> Result<(usize, usize), DispatchError> {
+            amm.swap_indices(asset_in, asset_out)
+                .ok_or_else(|| Error::<T>::InvalidAsset.into())
+        }
+
+        /// The input and output assets of a swap of `asset_type` against an AMM of two assets.
+        pub(crate) fn pair_assets(
+            amm_id: &T::AmmId,
+            asset_type: AssetType,
+        ) -> Result<(T::AssetId, T::AssetId), DispatchError> {
+            let assets = match Self::concentrated_amm(amm_id) {
+                Some(amm) => amm.assets.to_vec(),
+                None => Self::try_get_amm_state(amm_id)?.assets.into_inner(),
+            };
+            match assets[..] {
+                [base, quote] => Ok(asset_type.order(base, quote)),
+                _ => Err(Error::<T>::AssetCountMismatch.into()),
+            }
+        }
+
+        pub(crate) fn swap_indices(
+            amm_state: &Amm<T>,
+            asset_in: &T::AssetId,
+            asset_out: &T::AssetId,
+        ) -> Result<(usize, usize), DispatchError> {
+            amm_state
+                .swap_indices(asset_in, asset_out)
+                .ok_or_else(|| Error::<T>::InvalidAsset.into())
+        }
+
+        /// The AMM of two assets holding `asset_a` and `asset_b`, in any order, with `fees_bps`.
+        pub fn pair_amm(
+            asset_a: T::AssetId,
+            asset_b: T::AssetId,
+            fees_bps: T::Balance,
+        ) -> Option<T::AmmId> {
+            Pairs::<T>::get(Self::pair_key(asset_a, asset_b, fees_bps))
+        }
+
+        /// Amount of `asset_out` one would get by swapping `amount` of `asset_in` against the AMM
+        /// of the pair with `fees_bps`. See `SimulateSwap::simulate_swap_between`.
+        pub fn quote_pair(
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            fees_bps: T::Balance,
+            amount: T::Balance,
+        ) -> Result<T::Balance, DispatchError> {
+            let amm_id =
+                Self::pair_amm(asset_in, asset_out, fees_bps).ok_or(Error::<T>::UnknownPair)?;
+            <Self as SimulateSwap>::simulate_swap_between(amm_id, asset_in, asset_out, amount)
+        }
+
+        pub(crate) fn pair_key(
+            asset_a: T::AssetId,
+            asset_b: T::AssetId,
+            fees_bps: T::Balance,
+        ) -> (T::AssetId, T::AssetId, T::Balance) {
+            (asset_a.min(asset_b), asset_a.max(asset_b), fees_bps)
+        }
+
+        /// Register a new AMM in `Pairs` if it holds two assets.
+        fn index_pair(
+            amm_id: &T::AmmId,
+            assets: &[T::AssetId],
+            fees_bps: T::Balance,
+        ) -> DispatchResult {
+            if let [asset_a, asset_b] = assets {
+                let key = Self::pair_key(*asset_a, *asset_b, fees_bps);
+                ensure!(!Pairs::<T>::contains_key(&key), Error::<T>::PairAlreadyExists);
+                Pairs::<T>::insert(key, amm_id);
+            }
+            Ok(())
+        }
+
+        /// Move an AMM of two assets to another fee tier in `Pairs`.
+        fn reindex_pair(
+            amm_id: &T::AmmId,
+            assets: &[T::AssetId],
+            old_fees_bps: T::Balance,
+            new_fees_bps: T::Balance,
+        ) -> DispatchResult {
+            if old_fees_bps == new_fees_bps {
+                return Ok(());
+            }
+            Self::unindex_pair(amm_id, assets, old_fees_bps);
+            Self::index_pair(amm_id, assets, new_fees_bps)
+        }
+
+        /// Remove an AMM of two assets from `Pairs`, unless it was never indexed.
+        fn unindex_pair(amm_id: &T::AmmId, assets: &[T::AssetId], fees_bps: T::Balance) {
+            if let [asset_a, asset_b] = assets {
+                let key = Self::pair_key(*asset_a, *asset_b, fees_bps);
+                if Pairs::<T>::get(&key).as_ref() == Some(amm_id) {
+                    Pairs::<T>::remove(&key);
+                }
+            }
+        }
+
+        /// Reserves of each asset of an AMM, if it exists.
+        pub fn pool_reserves(amm_id: T::AmmId) -> Option<Vec<T::Balance>> {
+            Self::amm_state(amm_id).map(|state| state.reserves.into_inner())
+        }
+
+        /// Amounts of each asset `who` would get by withdrawing all of their shares of an AMM.
+        pub fn lp_share_value(
+            amm_id: T::AmmId,
+            who: &T::AccountId,
+        ) -> Result<Vec<T::Balance>, DispatchError> {
             let amm_state = Self::try_get_amm_state(&amm_id)?;
+            if amm_state.total_shares.is_zero() {
+                return Ok(vec![Zero::zero(); amm_state.assets.len()]);
+            }
 
-            let full_bps: T::Balance = 10_000_u64.into();
-            // net_amount = (10000 - fees) * amount / 10000
-            let net_amount = full_bps
-                .try_sub(&amm_state.fees_bps)?
-                .try_mul(&amount)?
-                .try_div(&full_bps)?;
+            let shares = T::Assets::balance(amm_state.share_asset, who);
+            Self::share_of_reserves(&amm_state, shares)
+        }
 
-            let (output_reserves_before, output_reserves_after) = match asset_type {
-                AssetType::Base => {
-                    let base_reserves_after = amm_state.base_reserves.try_add(&net_amount)?;
-                    let quote_reserves_after = amm_state.get_k()?.try_div(&base_reserves_after)?;
+        /// Amounts of each asset backing `shares` of an AMM with liquidity.
+        fn share_of_reserves(
+            amm_state: &Amm<T>,
+            shares: T::Balance,
+        ) -> Result<Vec<T::Balance>, DispatchError> {
+            amm_state
+                .reserves
+                .iter()
+                .map(|reserve| -> Result<T::Balance, DispatchError> {
+                    Ok(mul_div(shares, *reserve, amm_state.total_shares, Rounding::Down)?)
+                })
+                .collect()
+        }
 
-                    (amm_state.quote_reserves, quote_reserves_after)
-                },
-                AssetType::Quote => {
-                    let quote_reserves_after = amm_state.quote_reserves.try_add(&net_amount)?;
-                    let base_reserves_after = amm_state.get_k()?.try_div(&quote_reserves_after)?;
+        /// The account holding the assets of an AMM, where flash swaps are paid back to.
+        pub fn amm_account(amm_id: &T::AmmId) -> T::AccountId {
+            T::PalletId::get().into_sub_account_truncating(amm_id)
+        }
 
-                    (amm_state.base_reserves, base_reserves_after)
+        /// Shares to mint to the first liquidity provider of an AMM for depositing `amounts`, once
+        /// `T::MinimumLiquidity` is locked out of the total.
+        fn initial_shares(
+            state: &Amm<T>,
+            amounts: &[T::Balance],
+        ) -> Result<T::Balance, DispatchError> {
+            let amounts = amounts.iter().map(|amount| (*amount).into()).collect::<Vec<U256>>();
+            let total = match state.curve {
+                Curve::StableSwap { amplification } => {
+                    stable_swap::invariant(&amounts, amplification)?
                 }
+                _ => constant_product::geometric_mean(&amounts)?,
             };
+            let total: T::Balance = from_u256(total)?;
 
-            // Ensure reserves are not depleted
-            let mut output_amount = output_reserves_before.try_sub(&output_reserves_after)?;
-            if output_reserves_after.is_zero() {
-                output_amount = output_amount.try_sub(&One::one())?;
+            ensure!(total > T::MinimumLiquidity::get(), Error::<T>::InsufficientInitialLiquidity);
+            Ok(total.saturating_sub(T::MinimumLiquidity::get()))
+        }
+
+        /// Mint `T::MinimumLiquidity` shares of an AMM to the pallet's account, where nobody can
+        /// withdraw them.
+        fn lock_minimum_liquidity(state: &mut Amm<T>) -> DispatchResult {
+            let minimum = T::MinimumLiquidity::get();
+            T::Assets::mint_into(state.share_asset, &Self::pallet_account(), minimum)?;
+            state.total_shares = state.total_shares.try_add(&minimum)?;
+            Ok(())
+        }
+
+        pub(crate) fn pallet_account() -> T::AccountId {
+            T::PalletId::get().into_account_truncating()
+        }
+
+        /// Shares to mint for depositing `amounts` into a StableSwap AMM with liquidity,
+        /// proportional to the increase of its invariant.
+        ///
+        /// As in Curve, the swap fee is charged on the difference between each deposited amount
+        /// and the one that would have kept the pool balance unchanged, so that an unbalanced
+        /// deposit followed by a withdrawal is no cheaper than a swap.
+        fn stable_swap_shares(
+            state: &Amm<T>,
+            amounts: &[T::Balance],
+            amplification: u32,
+        ) -> Result<T::Balance, DispatchError> {
+            let old = state.reserves_u256();
+            let new = state
+                .reserves
+                .iter()
+                .zip(amounts)
+                .map(|(reserve, amount)| -> Result<U256, ArithmeticError> {
+                    Ok(reserve.try_add(amount)?.into())
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let d0 = stable_swap::invariant(&old, amplification)?;
+            let d1 = stable_swap::invariant(&new, amplification)?;
+            ensure!(d1 > d0, Error::<T>::ZeroAmount);
+
+            // Curve charges `fee * n / (4 * (n - 1))` on the imbalance, i.e. half of the swap fee
+            // for two assets.
+            let n = U256::from(old.len());
+            let fees_bps: U256 = state.fees_bps.into();
+            let fee_numerator = fees_bps.checked_mul(n).ok_or(ArithmeticError::Overflow)?;
+            let fee_denominator = U256::from(4 * 10_000_u64)
+                .checked_mul(n - 1)
+                .ok_or(ArithmeticError::Overflow)?;
+            let mut adjusted = new;
+            for (reserve, old_reserve) in adjusted.iter_mut().zip(old) {
+                let ideal = d1
+                    .checked_mul(old_reserve)
+                    .ok_or(ArithmeticError::Overflow)?
+                    / d0;
+                let imbalance = if *reserve > ideal { *reserve - ideal } else { ideal - *reserve };
+                let fee = imbalance
+                    .checked_mul(fee_numerator)
+                    .ok_or(ArithmeticError::Overflow)?
+                    / fee_denominator;
+                *reserve = reserve.checked_sub(fee).ok_or(ArithmeticError::Underflow)?;
             }
+            let d2 = stable_swap::invariant(&adjusted, amplification)?;
 
-            Ok(output_amount)
+            let total_shares: U256 = state.total_shares.into();
+            let shares = total_shares
+                .checked_mul(d2.saturating_sub(d0))
+                .ok_or(ArithmeticError::Overflow)?
+                / d0;
+
+            Ok(from_u256(shares)?)
         }
 
-        fn output_price(
-            amm_id: Self::AmmId,
-            asset_type: Self::AssetType,
-            amount: Self::Balance,
-        ) -> Result<Self::Balance, DispatchError> {
-            let amm_state = Self::try_get_amm_state(&amm_id)?;
+        /// One whole unit of an asset with `T::DefaultDecimals` decimals.
+        pub(crate) fn unit() -> T::Balance {
+            10_u64
+                .saturating_pow(T::DefaultDecimals::get() as u32)
+                .into()
+        }
+
+        pub(crate) fn blocks_to_balance(blocks: T::BlockNumber) -> T::Balance {
+            blocks.saturated_into::<u64>().into()
+        }
+
+        /// Price observations of an AMM, oldest first.
+        pub fn price_history(amm_id: &T::AmmId) -> Vec<PriceObservation<T>> {
+            let mut observations = Self::price_observations(amm_id).into_inner();
+            let head = PriceObservationHeads::<T>::get(amm_id) as usize;
+            if head < observations.len() {
+                observations.rotate_left(head);
+            }
+            observations
+        }
+
+        /// Record the cumulative prices of an AMM, if not yet done in the current block. Must be
+        /// called before modifying the AMM's reserves.
+        fn record_price_observation(amm_id: &T::AmmId, amm_state: &Amm<T>) -> DispatchResult {
+            let now = frame_system::Pallet::<T>::block_number();
+            let head = PriceObservationHeads::<T>::get(amm_id) as usize;
+            PriceObservations::<T>::try_mutate(amm_id, |observations| -> DispatchResult {
+                // The newest observation is the one right before the oldest.
+                let len = observations.len();
+                let newest = if head == 0 { len.checked_sub(1) } else { Some(head - 1) };
+                let observation = match newest.and_then(|index| observations.get(index)) {
+                    Some(last) if last.block == now => return Ok(()),
+                    Some(last) => last.accumulate(now, amm_state, Self::unit())?,
+                    None => PriceObservation::new(now, amm_state),
+                };
+
+                if head == 0 && len < T::MaxPriceObservations::get() as usize {
+                    // Cannot fail, as there is room left.
+                    let _ = observations.try_push(observation);
+                } else if let Some(oldest) = observations.get_mut(head) {
+                    *oldest = observation;
+                    PriceObservationHeads::<T>::insert(amm_id, ((head + 1) % len) as u32);
+                }
+                // Otherwise `MaxPriceObservations` is zero, and no history is kept.
+
+                Ok(())
+            })
+        }
+
+        /// Largest deposit into a constant product or weighted AMM with liquidity that keeps the
+        /// proportion of its reserves within `max_amounts`, along with the shares it is worth.
+        /// Amounts are rounded up in favor of the pool.
+        fn proportional_deposit(
+            state: &Amm<T>,
+            max_amounts: &[T::Balance],
+        ) -> Result<(Vec<T::Balance>, T::Balance), DispatchError> {
+            let shares = max_amounts
+                .iter()
+                .zip(state.reserves.iter())
+                .map(|(amount, reserve)| {
+                    mul_div(state.total_shares, *amount, *reserve, Rounding::Down)
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .min()
+                .unwrap_or_else(Zero::zero);
+            ensure!(!shares.is_zero(), Error::<T>::ZeroAmount);
+
+            let amounts = state
+                .reserves
+                .iter()
+                .map(|reserve| mul_div(shares, *reserve, state.total_shares, Rounding::Up))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok((amounts, shares))
+        }
+
+        /// Transfer `amounts` of each asset of an AMM from `caller` to its pool and mint `shares`
+        /// for them.
+        fn deposit_liquidity(
+            caller: &T::AccountId,
+            amm_id: T::AmmId,
+            mut state: Amm<T>,
+            amounts: &[T::Balance],
+            shares: T::Balance,
+        ) -> DispatchResult {
+            let amm_account = Self::amm_account(&amm_id);
+            for (asset, amount) in state.assets.iter().zip(amounts.iter()) {
+                T::Assets::transfer(*asset, caller, &amm_account, *amount, false)?;
+            }
+
+            T::Assets::mint_into(state.share_asset, caller, shares)?;
+
+            for (reserve, amount) in state.reserves.iter_mut().zip(amounts.iter()) {
+                *reserve = reserve.try_add(amount)?;
+            }
+            state.total_shares = state.total_shares.try_add(&shares)?;
+
+            AmmStates::<T>::insert(&amm_id, state);
+
+            Self::deposit_event(Event::<T>::LiquidityAdded {
+                amm_id,
+                user: caller.clone(),
+                shares,
+            });
+
+            Ok(())
+        }
+
+        /// Burn `amount` of `caller`'s shares of an AMM and send them the assets backing them,
+        /// returning how much of each asset was sent.
+        fn do_withdraw(
+            caller: &T::AccountId,
+            amm_id: T::AmmId,
+            amount: T::Balance,
+        ) -> Result<BoundedVec<T::Balance, T::MaxAssetsPerPool>, DispatchError> {
+            let mut amm_state = Self::try_get_amm_state(&amm_id)?;
+            Self::record_price_observation(&amm_id, &amm_state)?;
+
+            T::Assets::burn_from(amm_state.share_asset, caller, amount)
+                .map_err(|_| Error::<T>::InvalidShareAmount)?;
+
+            let amounts = BoundedVec::try_from(Self::share_of_reserves(&amm_state, amount)?)
+                .map_err(|_| Error::<T>::AssetCountMismatch)?;
+
+            let amm_account = Self::amm_account(&amm_id);
+            for ((asset, reserve), asset_amount) in amm_state
+                .assets
+                .iter()
+                .zip(amm_state.reserves.iter_mut())
+                .zip(amounts.iter())
+            {
+                T::Assets::transfer(*asset, &amm_account, caller, *asset_amount, false)?;
+                *reserve = reserve.try_sub(asset_amount)?;
+            }
+
+            amm_state.total_shares = amm_state.total_shares.try_sub(&amount)?;
+
+            AmmStates::<T>::insert(&amm_id, amm_state);
+
+            Self::deposit_event(Event::<T>::LiquidityRemoved {
+                amm_id,
+                user: caller.clone(),
+                shares: amount,
+                amounts: amounts.clone(),
+            });
+
+            Ok(amounts)
+        }
+
+        /// Share of a swap fee owed to `ProtocolFeeRecipient`, rounded down in favor of liquidity
+        /// providers.
+        pub(crate) fn protocol_share(fee: U256) -> U256 {
+            let parts = U256::from(T::ProtocolFee::get().deconstruct());
+            fee.saturating_mul(parts) / U256::from(Permill::one().deconstruct())
+        }
+
+        /// Update the reserves of an AMM other than a concentrated liquidity one for a swap of
+        /// `input_amount` of `asset_in`, returning the amount of `asset_out` to send back and the
+        /// fees charged. The protocol fee does not go into the reserves.
+        pub(crate) fn swap_reserves(
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            input_amount: T::Balance,
+        ) -> Result<(T::Balance, SwapFees<T::Balance>), DispatchError> {
+            let mut amm_state = Self::try_get_amm_state(&amm_id)?;
+            let (index_in, index_out) = Self::swap_indices(&amm_state, &asset_in, &asset_out)?;
+            ensure!(amm_state.is_initialized(), Error::<T>::ZeroLiquidity);
+            Self::record_price_observation(&amm_id, &amm_state)?;
+
+            let output_amount = <Self as SimulateSwap>::simulate_swap_between(
+                amm_id,
+                asset_in,
+                asset_out,
+                input_amount,
+            )?;
 
-            let (output_reserves_after, input_reserves_before) = match asset_type {
-                AssetType::Base => (amm_state.base_reserves.try_sub(&amount)?, amm_state.quote_reserves),
-                AssetType::Quote => (amm_state.quote_reserves.try_sub(&amount)?, amm_state.base_reserves),
+            // Same rounding as the net amount traded in `simulate_swap`.
+            let full_bps: T::Balance = 10_000_u64.into();
+            let net_amount = mul_div(
+                full_bps.try_sub(&amm_state.fees_bps)?,
+                input_amount,
+                full_bps,
+                Rounding::Down,
+            )?;
+            let fee = input_amount.try_sub(&net_amount)?;
+            let protocol_fee: T::Balance = from_u256(Self::protocol_share(fee.into()))?;
+            let fees = SwapFees {
+                lp: fee.try_sub(&protocol_fee)?,
+                protocol: protocol_fee,
             };
 
-            ensure!(!output_reserves_after.is_zero(), Error::<T>::InsufficientLiquidity);
+            for (index, reserve) in amm_state.reserves.iter_mut().enumerate() {
+                if index == index_in {
+                    *reserve = reserve.try_add(&input_amount.try_sub(&fees.protocol)?)?;
+                } else if index == index_out {
+                    *reserve = reserve.try_sub(&output_amount)?;
+                }
+            }
 
-            let invariant = amm_state.get_k()?;
-            let input_reserves_after = invariant.try_div(&output_reserves_after)?;
-            let net_input = input_reserves_after.try_sub(&input_reserves_before)?;
+            AmmStates::<T>::insert(&amm_id, amm_state);
+
+            Ok((output_amount, fees))
+        }
+
+        /// Ensure `reserves` keep the invariant of an AMM at least at its value for the current
+        /// reserves of the AMM.
+        fn ensure_invariant_kept(state: &Amm<T>, reserves: &[T::Balance]) -> DispatchResult {
+            let old = state.reserves_u256();
+            let new = reserves.iter().map(|reserve| (*reserve).into()).collect::<Vec<U256>>();
+
+            let kept = match state.curve {
+                Curve::ConstantProduct => {
+                    constant_product::invariant(&new)? >= state.get_k()?
+                }
+                Curve::StableSwap { amplification } => {
+                    stable_swap::invariant(&new, amplification)?
+                        >= stable_swap::invariant(&old, amplification)?
+                }
+                // Weighted AMMs hold two assets, so the reserves either both grew or moved like in
+                // a swap, which must not have paid out more than `weighted::output`.
+                Curve::Weighted { .. } => {
+                    let (index_in, index_out) = if new[0] >= old[0] { (0, 1) } else { (1, 0) };
+                    if new[index_out] >= old[index_out] {
+                        true
+                    } else if new[index_in] <= old[index_in] {
+                        false
+                    } else {
+                        let input = new[index_in] - old[index_in];
+                        ensure!(input <= old[index_in] / 2, Error::<T>::SwapTooLarge);
+                        let output = weighted::output(
+                            old[index_in],
+                            state.weight(index_in).into(),
+                            old[index_out],
+                            state.weight(index_out).into(),
+                            input,
+                        )?;
+                        old[index_out] - new[index_out] <= output
+                    }
+                }
+            };
+            ensure!(kept, Error::<T>::InsufficientRepayment);
+
+            Ok(())
+        }
+
+        /// Lend `amounts` of each asset of an AMM to `caller` while `T::FlashBorrower` runs, then
+        /// charge swap fees on what was paid back and update the reserves.
+        fn do_flash_swap(
+            caller: &T::AccountId,
+            amm_id: T::AmmId,
+            amounts: BoundedVec<T::Balance, T::MaxAssetsPerPool>,
+        ) -> DispatchResult {
+            ensure!(amounts.iter().any(|amount| !amount.is_zero()), Error::<T>::ZeroAmount);
+            Self::ensure_not_paused(&amm_id)?;
+            ensure!(!Self::is_batched(&amm_id), Error::<T>::AmmInBatchMode);
+
+            let mut amm_state = Self::try_get_amm_state(&amm_id)?;
+            ensure!(amounts.len() == amm_state.assets.len(), Error::<T>::AssetCountMismatch);
+            ensure!(amm_state.is_initialized(), Error::<T>::ZeroLiquidity);
+            for (amount, reserve) in amounts.iter().zip(amm_state.reserves.iter()) {
+                ensure!(amount < reserve, Error::<T>::InsufficientLiquidity);
+            }
+            Self::record_price_observation(&amm_id, &amm_state)?;
+
+            // Payments are measured on the balances of the AMM's account, which may hold more than
+            // its reserves.
+            let amm_account = Self::amm_account(&amm_id);
+            let balances_before = amm_state
+                .assets
+                .iter()
+                .map(|asset| T::Assets::balance(*asset, &amm_account))
+                .collect::<Vec<_>>();
+            for (asset, amount) in amm_state.assets.iter().zip(amounts.iter()) {
+                if !amount.is_zero() {
+                    T::Assets::transfer(*asset, &amm_account, caller, *amount, false)?;
+                }
+            }
+
+            T::FlashBorrower::on_flash_swap(caller, &amm_id, &amounts)?;
+
+            // The reserves are overwritten below, which would discard any change made meanwhile.
+            let current = Self::try_get_amm_state(&amm_id)?;
+            ensure!(
+                current.reserves == amm_state.reserves
+                    && current.total_shares == amm_state.total_shares,
+                Error::<T>::ReentrantFlashSwap
+            );
 
-            // gross_input = net_input * 10000 / (10000 - fees)
             let full_bps: T::Balance = 10_000_u64.into();
-            let gross_input = net_input.try_mul(&full_bps)?
-                .try_div(&full_bps.try_sub(&amm_state.fees_bps)?)?;
+            let net_bps = full_bps.try_sub(&amm_state.fees_bps)?;
+            let mut amounts_in = Vec::with_capacity(amounts.len());
+            let mut protocol_fees = Vec::with_capacity(amounts.len());
+            let mut net_reserves = Vec::with_capacity(amounts.len());
+            for (index, asset) in amm_state.assets.iter().enumerate() {
+                let balance = T::Assets::balance(*asset, &amm_account);
+                let amount_in =
+                    balance.try_add(&amounts[index])?.saturating_sub(balances_before[index]);
+                // Same rounding as the net amount traded in `simulate_swap`.
+                let net_amount = mul_div(net_bps, amount_in, full_bps, Rounding::Down)?;
+                let fee = amount_in.try_sub(&net_amount)?;
+                let reserve = amm_state.reserves[index].try_sub(&amounts[index])?;
+
+                amounts_in.push(amount_in);
+                protocol_fees.push(from_u256::<T::Balance>(Self::protocol_share(fee.into()))?);
+                net_reserves.push(reserve.try_add(&net_amount)?);
+            }
+            Self::ensure_invariant_kept(&amm_state, &net_reserves)?;
+
+            let recipient = T::ProtocolFeeRecipient::get();
+            for (((asset, reserve), amount_out), (amount_in, protocol_fee)) in amm_state
+                .assets
+                .iter()
+                .zip(amm_state.reserves.iter_mut())
+                .zip(amounts.iter())
+                .zip(amounts_in.iter().zip(protocol_fees))
+            {
+                if !protocol_fee.is_zero() {
+                    T::Assets::transfer(*asset, &amm_account, &recipient, protocol_fee, false)?;
+                }
+                // The LP fee stays in the reserves, as for swaps.
+                let pool_input = amount_in.try_sub(&protocol_fee)?;
+                *reserve = reserve.try_sub(amount_out)?.try_add(&pool_input)?;
+            }
 
-            Ok(gross_input)
+            AmmStates::<T>::insert(&amm_id, amm_state);
+
+            Self::deposit_event(Event::<T>::FlashSwapped {
+                user: caller.clone(),
+                amm_id,
+                amounts_out: amounts,
+                amounts_in: BoundedVec::try_from(amounts_in)
+                    .map_err(|_| Error::<T>::AssetCountMismatch)?,
+            });
+
+            Ok(())
         }
-    }
 
-    // ---------------------------------------------------------------------------------------------
-    //                                      Helpers
-    // ---------------------------------------------------------------------------------------------
+        /// Swap `input_amount` of `asset_in` for `asset_out` against an AMM on behalf of `caller`,
+        /// returning the amount of `asset_out` sent back.
+        pub(crate) fn do_swap(
+            caller: &T::AccountId,
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            input_amount: T::Balance,
+            output_min: T::Balance,
+        ) -> Result<T::Balance, DispatchError> {
+            ensure!(!input_amount.is_zero(), Error::<T>::ZeroAmount);
+            Self::ensure_not_paused(&amm_id)?;
+            ensure!(!Self::is_batched(&amm_id), Error::<T>::AmmInBatchMode);
+
+            let (output_amount, fees) = match Self::concentrated_amm(&amm_id) {
+                Some(mut amm) => {
+                    let (index_in, _) =
+                        Self::concentrated_swap_indices(&amm, &asset_in, &asset_out)?;
+                    let (_, output_amount, fees) =
+                        Self::concentrated_swap(&mut amm, index_in, input_amount, true)?;
+                    ConcentratedAmms::<T>::insert(&amm_id, amm);
+                    (output_amount, fees)
+                }
+                None => Self::swap_reserves(amm_id, asset_in, asset_out, input_amount)?,
+            };
+            // Otherwise the input would be kept for nothing, even with no minimum output.
+            ensure!(!output_amount.is_zero(), Error::<T>::ZeroAmount);
+            ensure!(output_amount >= output_min, Error::<T>::SlippageExceeded);
 
-    impl<T: Config> Pallet<T> {
-        fn try_get_amm_state(amm_id: &T::AmmId) -> Result<Amm<T>, DispatchError> {
-            Self::amm_state(amm_id).ok_or_else(|| Error::<T>::InvalidAmmId.into())
+            let amm_account = Self::amm_account(&amm_id);
+            let pool_input = input_amount.try_sub(&fees.protocol)?;
+            T::Assets::transfer(asset_in, caller, &amm_account, pool_input, false)?;
+            if !fees.protocol.is_zero() {
+                let recipient = T::ProtocolFeeRecipient::get();
+                T::Assets::transfer(asset_in, caller, &recipient, fees.protocol, false)?;
+            }
+            T::Assets::transfer(asset_out, &amm_account, caller, output_amount, false)?;
+
+            Self::deposit_event(Event::<T>::Swapped {
+                user: caller.clone(),
+                amm_id,
+                asset_in,
+                asset_out,
+                input_amount,
+                output_amount,
+                lp_fee: fees.lp,
+                protocol_fee: fees.protocol,
+            });
+
+            Ok(output_amount)
         }
 
-        fn amm_account(amm_id: &T::AmmId) -> T::AccountId {
-            T::PalletId::get().into_sub_account_truncating(amm_id)
+        /// Swap for exactly `output_amount` of `asset_out`, with the input computed by
+        /// `SimulateSwap::output_price_between`. Returns the amount of `asset_in` sent.
+        pub(crate) fn do_swap_exact_out(
+            caller: &T::AccountId,
+            amm_id: T::AmmId,
+            asset_in: T::AssetId,
+            asset_out: T::AssetId,
+            output_amount: T::Balance,
+            max_input: T::Balance,
+        ) -> Result<T::Balance, DispatchError> {
+            ensure!(!output_amount.is_zero(), Error::<T>::ZeroAmount);
+
+            let input_amount = <Self as SimulateSwap>::output_price_between(
+                amm_id,
+                asset_in,
+                asset_out,
+                output_amount,
+            )?;
+            ensure!(input_amount <= max_input, Error::<T>::SlippageExceeded);
+
+            Self::do_swap(caller, amm_id, asset_in, asset_out, input_amount, output_amount)?;
+
+            Ok(input_amount)
+        }
+
+        /// Swap through each hop of `route` in turn. Returns the amount of the final asset
+        /// received.
+        pub(crate) fn do_swap_route(
+            caller: &T::AccountId,
+            asset_in: T::AssetId,
+            route: Route<T>,
+            input_amount: T::Balance,
+            output_min: T::Balance,
+        ) -> Result<T::Balance, DispatchError> {
+            ensure!(!route.is_empty(), Error::<T>::InvalidRoute);
+
+            let last = route.len() - 1;
+            let mut asset = asset_in;
+            let mut amount = input_amount;
+            for (hop, (amm_id, asset_out)) in route.iter().enumerate() {
+                // Intermediate hops are only bounded by the final slippage check.
+                let hop_min = if hop == last { output_min } else { Zero::zero() };
+                amount = Self::do_swap(caller, *amm_id, asset, *asset_out, amount, hop_min)?;
+                asset = *asset_out;
+            }
+
+            Self::deposit_event(Event::<T>::RouteSwapped {
+                user: caller.clone(),
+                asset_in,
+                route,
+                input_amount,
+                output_amount: amount,
+            });
+
+            Ok(amount)
+        }
+
+        /// Run `f` in a storage transaction, reverted if it fails.
+        fn transactional<R>(
+            f: impl FnOnce() -> Result<R, DispatchError>,
+        ) -> Result<R, DispatchError> {
+            with_transaction(|| match f() {
+                Ok(result) => TransactionOutcome::Commit(Ok(result)),
+                Err(error) => TransactionOutcome::Rollback(Err(error)),
+            })
         }
     }
 }
@@ -0,0 +1,107 @@
+//! Storage migrations of the DEX pallet.
+
+/// Migrates AMMs holding exactly a base and a quote asset to AMMs holding any number of assets.
+pub mod v1 {
+    use crate::{types::*, AmmStates, Config, Pallet};
+    use codec::Decode;
+    use frame_support::{
+        defensive,
+        pallet_prelude::*,
+        traits::{OnRuntimeUpgrade, StorageVersion},
+    };
+    use sp_std::{marker::PhantomData, vec};
+
+    /// The state of an AMM before version 1, which predates the choice of a curve.
+    #[derive(Decode)]
+    pub struct OldAmm<T: Config> {
+        pub base_asset: T::AssetId,
+        pub base_reserves: T::Balance,
+        pub quote_asset: T::AssetId,
+        pub quote_reserves: T::Balance,
+        pub share_asset: T::AssetId,
+        pub total_shares: T::Balance,
+        pub fees_bps: T::Balance,
+    }
+
+    /// Converts every AMM, keeping the base asset first and the quote asset second. AMMs all
+    /// priced swaps as a constant product then, so they keep doing so. Nothing is migrated if
+    /// `MaxAssetsPerPool` is below two, as existing AMMs would not fit.
+    pub struct MigrateToV1<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for MigrateToV1<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() >= StorageVersion::new(1) {
+                return T::DbWeight::get().reads(1);
+            }
+            // Every conversion below fits its bounds otherwise, so none of them drops a value.
+            if T::MaxAssetsPerPool::get() < 2 {
+                defensive!("MaxAssetsPerPool can't hold the AMMs being migrated to version 1");
+                return T::DbWeight::get().reads(1);
+            }
+
+            let mut translated = 0_u64;
+            AmmStates::<T>::translate::<OldAmm<T>, _>(|_, old| {
+                translated += 1;
+                Some(Amm {
+                    assets: vec![old.base_asset, old.quote_asset].try_into().ok()?,
+                    reserves: vec![old.base_reserves, old.quote_reserves].try_into().ok()?,
+                    share_asset: old.share_asset,
+                    total_shares: old.total_shares,
+                    fees_bps: old.fees_bps,
+                    curve: Curve::ConstantProduct,
+                })
+            });
+
+            StorageVersion::new(1).put::<Pallet<T>>();
+
+            T::DbWeight::get().reads_writes(translated + 1, translated + 1)
+        }
+    }
+}
+
+/// Indexes AMMs of two assets by their pair of assets and fee tier.
+pub mod v2 {
+    use crate::{AmmStates, ConcentratedAmms, Config, Pairs, Pallet};
+    use frame_support::{
+        pallet_prelude::*,
+        traits::{OnRuntimeUpgrade, StorageVersion},
+    };
+    use sp_std::marker::PhantomData;
+
+    /// Fills `Pairs` with every existing AMM of two assets. Where several AMMs share a pair and fee
+    /// tier, only the first one found is indexed, and the others remain reachable by id only.
+    /// Only runs from version 1, so that AMMs not yet migrated by `MigrateToV1` are left alone.
+    pub struct MigrateToV2<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for MigrateToV2<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if StorageVersion::get::<Pallet<T>>() != StorageVersion::new(1) {
+                return T::DbWeight::get().reads(1);
+            }
+
+            let amms = AmmStates::<T>::iter()
+                .map(|(amm_id, amm)| (amm_id, amm.assets.into_inner(), amm.fees_bps))
+                .chain(
+                    ConcentratedAmms::<T>::iter()
+                        .map(|(amm_id, amm)| (amm_id, amm.assets.to_vec(), amm.fees_bps)),
+                );
+
+            let mut reads = 1_u64;
+            let mut writes = 1_u64;
+            for (amm_id, assets, fees_bps) in amms {
+                reads += 2;
+                if let [asset_a, asset_b] = assets[..] {
+                    let key = Pallet::<T>::pair_key(asset_a, asset_b, fees_bps);
+                    if !Pairs::<T>::contains_key(&key) {
+                        writes += 1;
+                        Pairs::<T>::insert(key, amm_id);
+                    }
+                }
+            }
+
+            StorageVersion::new(2).put::<Pallet<T>>();
+
+            T::DbWeight::get().reads_writes(reads, writes)
+        }
+    }
+}
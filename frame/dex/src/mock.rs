@@ -1,7 +1,9 @@
 use crate as pallet_dex;
+use crate::traits::FlashBorrower;
 use frame_support::{
     parameter_types,
-    traits::{ConstU16, ConstU32, ConstU64, GenesisBuild},
+    dispatch::DispatchResult,
+    traits::{fungibles::Transfer, ConstU128, ConstU16, ConstU32, ConstU64, GenesisBuild},
     PalletId,
 };
 use frame_system as system;
@@ -10,6 +12,7 @@ use sp_core::H256;
 use sp_runtime::{
     testing::Header,
     traits::{BlakeTwo256, IdentityLookup, Zero},
+    Permill,
 };
 
 // -------------------------------------------------------------------------------------------------
@@ -38,11 +41,15 @@ frame_support::construct_runtime!(
 // -------------------------------------------------------------------------------------------------
 
 pub const DEFAULT_DECIMALS: u8 = 6;
+pub const MINIMUM_LIQUIDITY: Balance = 1_000;
+pub const POOL_DEPOSIT: Balance = 10;
+pub const SHARE_METADATA_DEPOSIT: Balance = 110;
+pub const TREASURY: AccountId = 99;
 
 pub type AccountId = u64;
 pub type AmmId = u64;
 pub type AssetId = u32;
-pub type Balance = u64;
+pub type Balance = u128;
 
 // -------------------------------------------------------------------------------------------------
 //                                          System
@@ -66,7 +73,7 @@ impl system::Config for Runtime {
     type BlockHashCount = ConstU64<250>;
     type Version = ();
     type PalletInfo = PalletInfo;
-    type AccountData = pallet_balances::AccountData<u64>;
+    type AccountData = pallet_balances::AccountData<Balance>;
     type OnNewAccount = ();
     type OnKilledAccount = ();
     type SystemWeightInfo = ();
@@ -87,13 +94,13 @@ pub(crate) enum Hook {
     Died(u32, u64),
 }
 thread_local! {
-    static FROZEN: RefCell<HashMap<(u32, u64), u64>> = RefCell::new(Default::default());
+    static FROZEN: RefCell<HashMap<(u32, u64), Balance>> = RefCell::new(Default::default());
     static HOOKS: RefCell<Vec<Hook>> = RefCell::new(Default::default());
 }
 
 pub struct TestFreezer;
-impl FrozenBalance<u32, u64, u64> for TestFreezer {
-    fn frozen_balance(asset: u32, who: &u64) -> Option<u64> {
+impl FrozenBalance<u32, u64, Balance> for TestFreezer {
+    fn frozen_balance(asset: u32, who: &u64) -> Option<Balance> {
         FROZEN.with(|f| f.borrow().get(&(asset, *who)).cloned())
     }
 
@@ -110,11 +117,11 @@ impl pallet_assets::Config for Runtime {
     type AssetId = AssetId;
     type Currency = Balances;
     type ForceOrigin = frame_system::EnsureRoot<AccountId>;
-    type AssetDeposit = ConstU64<1>;
-    type AssetAccountDeposit = ConstU64<10>;
-    type MetadataDepositBase = ConstU64<1>;
-    type MetadataDepositPerByte = ConstU64<1>;
-    type ApprovalDeposit = ConstU64<1>;
+    type AssetDeposit = ConstU128<1>;
+    type AssetAccountDeposit = ConstU128<10>;
+    type MetadataDepositBase = ConstU128<1>;
+    type MetadataDepositPerByte = ConstU128<1>;
+    type ApprovalDeposit = ConstU128<1>;
     type StringLimit = ConstU32<50>;
     type Freezer = TestFreezer;
     type Extra = ();
@@ -129,7 +136,7 @@ impl pallet_balances::Config for Runtime {
     type Balance = Balance;
     type DustRemoval = ();
     type Event = Event;
-    type ExistentialDeposit = ConstU64<1>;
+    type ExistentialDeposit = ConstU128<1>;
     type AccountStore = System;
     type WeightInfo = ();
     type MaxLocks = ();
@@ -144,16 +151,75 @@ impl pallet_balances::Config for Runtime {
 parameter_types! {
     pub const TestPalletId: PalletId = PalletId(*b"test_pid");
     pub const DefaultDecimals: u8 = DEFAULT_DECIMALS;
+    pub const MaxAssetsPerPool: u32 = 4;
+    pub const MaxBatchSwaps: u32 = 3;
+    pub const MaxPriceObservations: u32 = 16;
+    pub const MaxRouteLength: u32 = 4;
+    pub const MaxTicksPerPool: u32 = 16;
+    pub const MinimumLiquidity: Balance = MINIMUM_LIQUIDITY;
+    pub const PoolDeposit: Balance = POOL_DEPOSIT;
+    pub const ProtocolFeeRecipient: AccountId = TREASURY;
+    pub static ProtocolFee: Permill = Permill::zero();
+    pub const ShareMetadataDeposit: Balance = SHARE_METADATA_DEPOSIT;
 }
 
 impl pallet_dex::Config for Runtime {
+    type AdminOrigin = frame_system::EnsureRoot<AccountId>;
     type AmmId = AmmId;
     type AssetId = AssetId;
     type Assets = Assets;
     type Balance = Balance;
+    type Currency = Balances;
     type DefaultDecimals = DefaultDecimals;
     type Event = Event;
+    type FlashBorrower = TestFlashBorrower;
+    type MaxAssetsPerPool = MaxAssetsPerPool;
+    type MaxBatchSwaps = MaxBatchSwaps;
+    type MaxPriceObservations = MaxPriceObservations;
+    type MaxRouteLength = MaxRouteLength;
+    type MaxTicksPerPool = MaxTicksPerPool;
+    type MinimumLiquidity = MinimumLiquidity;
     type PalletId = TestPalletId;
+    type PoolDeposit = PoolDeposit;
+    type ProtocolFee = ProtocolFee;
+    type ProtocolFeeRecipient = ProtocolFeeRecipient;
+    type ShareMetadataDeposit = ShareMetadataDeposit;
+    type StringLimit = ConstU32<50>;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    pub static FlashRepayment: Vec<Balance> = vec![];
+    pub static FlashReentry: bool = false;
+}
+
+/// Pays back `FlashRepayment` of each asset of the AMM. If `FlashReentry` is set, first swaps the
+/// first borrowed asset against the same AMM.
+pub struct TestFlashBorrower;
+impl FlashBorrower<AccountId, AmmId, Balance> for TestFlashBorrower {
+    fn on_flash_swap(borrower: &AccountId, amm_id: &AmmId, amounts: &[Balance]) -> DispatchResult {
+        let assets = TestPallet::amm_state(amm_id).unwrap().assets;
+        let amm_account = TestPallet::amm_account(amm_id);
+
+        if FlashReentry::get() {
+            let index = amounts.iter().position(|amount| !amount.is_zero()).unwrap();
+            let (asset_in, asset_out) = (assets[index], assets[if index == 0 { 1 } else { 0 }]);
+            let origin = Origin::signed(*borrower);
+            let amount = amounts[index];
+            TestPallet::swap_between(origin, *amm_id, asset_in, asset_out, amount, 0, None)?;
+        }
+
+        for (asset, amount) in assets.iter().zip(FlashRepayment::get()) {
+            <Assets as Transfer<AccountId>>::transfer(
+                *asset,
+                borrower,
+                &amm_account,
+                amount,
+                false,
+            )?;
+        }
+        Ok(())
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -162,6 +228,8 @@ impl pallet_dex::Config for Runtime {
 
 // Build genesis storage according to the mock runtime.
 pub fn new_test_ext() -> sp_io::TestExternalities {
+    ProtocolFee::set(&Permill::zero());
+
     system::GenesisConfig::default()
         .build_storage::<Runtime>()
         .unwrap()
@@ -175,10 +243,18 @@ pub struct ExtBuilder {
     pub metadata: Vec<(AssetId, Vec<u8>, Vec<u8>, u8)>,
     /// Genesis accounts: id, account_id, balance
     pub accounts: Vec<(AssetId, AccountId, Balance)>,
+    /// Genesis native balances: account_id, balance
+    pub balances: Vec<(AccountId, Balance)>,
+    /// Share of swap fees sent to `TREASURY`
+    pub protocol_fee: Permill,
 }
 
 impl ExtBuilder {
     pub fn build(self) -> sp_io::TestExternalities {
+        ProtocolFee::set(&self.protocol_fee);
+        FlashRepayment::set(&vec![]);
+        FlashReentry::set(&false);
+
         let mut storage = frame_system::GenesisConfig::default()
             .build_storage::<Runtime>()
             .unwrap();
@@ -191,6 +267,12 @@ impl ExtBuilder {
         .assimilate_storage(&mut storage)
         .unwrap();
 
+        pallet_balances::GenesisConfig::<Runtime> {
+            balances: self.balances,
+        }
+        .assimilate_storage(&mut storage)
+        .unwrap();
+
         storage.into()
     }
 }
@@ -0,0 +1,130 @@
+//! Resting limit orders, filled against AMMs once their price allows.
+
+use crate::{
+    traits::SimulateSwap, types::*, weights::WeightInfo, Config, Error, Event, LastCheckedOrder,
+    LimitOrders, Pallet,
+};
+use frame_support::{
+    ensure,
+    pallet_prelude::{DispatchError, Get, Weight},
+    storage::{with_transaction, TransactionOutcome},
+    traits::fungibles::Transfer,
+};
+use sp_runtime::traits::AccountIdConversion;
+use sp_std::vec::Vec;
+
+impl<T: Config> Pallet<T> {
+    /// The account holding the input assets of resting limit orders.
+    pub fn order_escrow_account() -> T::AccountId {
+        T::PalletId::get().into_sub_account_truncating(b"orders")
+    }
+
+    /// The limit order `order_id`, if `who` placed it.
+    pub(crate) fn try_get_owned_order(
+        order_id: &OrderId,
+        who: &T::AccountId,
+    ) -> Result<LimitOrder<T>, DispatchError> {
+        let order = LimitOrders::<T>::get(order_id).ok_or(Error::<T>::InvalidOrderId)?;
+        ensure!(order.owner == *who, Error::<T>::NotOrderOwner);
+        Ok(order)
+    }
+
+    /// Weight of checking a resting limit order against its AMM, and of filling it if `filled`.
+    pub(crate) fn limit_order_weight(filled: bool) -> Weight {
+        let db = T::DbWeight::get();
+        let check = db.reads(3);
+        if filled {
+            // As much as a swap, plus paying out the order and removing it.
+            check.saturating_add(T::WeightInfo::swap()).saturating_add(db.reads_writes(1, 3))
+        } else {
+            check
+        }
+    }
+
+    /// Check resting limit orders in turn, filling those that became executable, for as long as
+    /// `budget` allows filling one more. Resumes after the last order checked by the previous
+    /// call, and starts over once every order was checked. Returns the weight used.
+    ///
+    /// Orders are checked in the order of their storage keys, which hash their ids, so an order
+    /// placed earlier gets no priority over a later one. An order landing behind the cursor waits
+    /// until checks start over, one pass over every order at most. Walking ids in order instead
+    /// would cost a read for each filled or cancelled id in between.
+    pub(crate) fn fill_limit_orders(budget: Weight) -> Weight {
+        // Reading and updating `LastCheckedOrder`.
+        let overhead = T::DbWeight::get().reads_writes(1, 1);
+        let max_orders = budget.saturating_sub(overhead) / Self::limit_order_weight(true).max(1);
+        if budget < overhead || max_orders == 0 {
+            return 0;
+        }
+
+        let orders = match LastCheckedOrder::<T>::get() {
+            Some(last) => LimitOrders::<T>::iter_from(LimitOrders::<T>::hashed_key_for(last)),
+            None => LimitOrders::<T>::iter(),
+        }
+        .take(max_orders as usize)
+        .collect::<Vec<_>>();
+
+        let mut used = overhead;
+        for (order_id, order) in orders.iter() {
+            let filled = Self::try_fill_limit_order(*order_id, order);
+            used = used.saturating_add(Self::limit_order_weight(filled));
+        }
+
+        let exhausted = orders.len() < max_orders as usize;
+        match orders.last() {
+            Some((last, _)) if !exhausted => LastCheckedOrder::<T>::put(last),
+            _ => LastCheckedOrder::<T>::kill(),
+        }
+
+        used
+    }
+
+    /// Swap the input of a limit order from escrow if the AMM now pays out at least the order's
+    /// `output_min`, and send the output to the order's owner. Returns whether it was filled,
+    /// leaving storage unchanged otherwise.
+    fn try_fill_limit_order(order_id: OrderId, order: &LimitOrder<T>) -> bool {
+        let output = <Self as SimulateSwap>::simulate_swap_between(
+            order.amm_id,
+            order.asset_in,
+            order.asset_out,
+            order.input_amount,
+        );
+        if !matches!(output, Ok(output) if output >= order.output_min) {
+            return false;
+        }
+
+        let escrow = Self::order_escrow_account();
+        let filled = with_transaction(|| {
+            let result = Self::do_swap(
+                &escrow,
+                order.amm_id,
+                order.asset_in,
+                order.asset_out,
+                order.input_amount,
+                order.output_min,
+            )
+            .and_then(|output_amount| {
+                T::Assets::transfer(order.asset_out, &escrow, &order.owner, output_amount, false)?;
+                Ok(output_amount)
+            });
+
+            match result {
+                Ok(output_amount) => TransactionOutcome::Commit(Ok(output_amount)),
+                Err(error) => TransactionOutcome::Rollback(Err(error)),
+            }
+        });
+
+        match filled {
+            Ok(output_amount) => {
+                LimitOrders::<T>::remove(order_id);
+                Self::deposit_event(Event::<T>::LimitOrderFilled {
+                    order_id,
+                    owner: order.owner.clone(),
+                    output_amount,
+                });
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
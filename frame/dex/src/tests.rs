@@ -1,11 +1,33 @@
-use crate::{mock::*, types::AssetType, Error, Event};
+use crate::{
+    curves::constant_product,
+    helpers::{mul_div, Rounding},
+    migrations::{v1::MigrateToV1, v2::MigrateToV2},
+    mock::*,
+    traits::{ExecuteSwap, PriceOracle, SimulateSwap},
+    types::{AssetType, Curve, PriceObservation, Route},
+    weights::WeightInfo,
+    AmmStates, CheckDeadline, Error, Event, LimitOrders, Pairs, PriceObservationHeads,
+    PriceObservations,
+};
 use frame_support::{
     assert_noop, assert_ok,
     error::BadOrigin,
     pallet_prelude::Hooks,
-    traits::fungibles::{Create, Inspect},
+    traits::{
+        fungibles::{Create, Inspect, InspectMetadata},
+        OnRuntimeUpgrade, StorageVersion,
+    },
+    weights::{DispatchInfo, Weight},
+    BoundedVec,
 };
 use pallet_assets::Error as AssetsError;
+use pallet_balances::Error as BalancesError;
+use sp_core::U256;
+use sp_runtime::{
+    traits::SignedExtension,
+    transaction_validity::{InvalidTransaction, ValidTransaction},
+    ArithmeticError, Perbill, Permill,
+};
 
 // -------------------------------------------------------------------------------------------------
 //                                          Setup
@@ -22,6 +44,7 @@ pub const DEFAULT_BASE_ASSET: AssetId = DOT;
 pub const DEFAULT_QUOTE_ASSET: AssetId = USDC;
 pub const DEFAULT_SHARE_ASSET: AssetId = 100;
 pub const DEFAULT_FEES_BPS: Balance = 30;
+pub const NATIVE_BALANCE: Balance = 1_000;
 
 impl Default for ExtBuilder {
     fn default() -> Self {
@@ -50,10 +73,26 @@ impl Default for ExtBuilder {
                 (USDC, BOB, UNIT),
                 (KSM, BOB, UNIT * 2),
             ],
+            balances: vec![
+                (ALICE, NATIVE_BALANCE),
+                (BOB, NATIVE_BALANCE),
+                (CHARLIE, NATIVE_BALANCE),
+            ],
+            protocol_fee: Permill::zero(),
         }
     }
 }
 
+/// Asset ids of a new AMM, in the order their reserves are kept.
+pub fn assets(ids: &[AssetId]) -> BoundedVec<AssetId, MaxAssetsPerPool> {
+    ids.to_vec().try_into().unwrap()
+}
+
+/// Amounts of each asset of an AMM, in the order given at creation.
+pub fn amounts(amounts: &[Balance]) -> BoundedVec<Balance, MaxAssetsPerPool> {
+    amounts.to_vec().try_into().unwrap()
+}
+
 pub fn run_to_block(n: u64) {
     while System::block_number() < n {
         if System::block_number() > 0 {
@@ -81,39 +120,39 @@ fn ext_builder_works() {
 
 #[test]
 fn anyone_can_create_amm() {
-    new_test_ext().execute_with(|| {
+    ExtBuilder::default().build().execute_with(|| {
         assert_noop!(
             TestPallet::create_amm(
                 Origin::root(),
-                DEFAULT_BASE_ASSET,
-                DEFAULT_QUOTE_ASSET,
+                assets(&[DEFAULT_BASE_ASSET, DEFAULT_QUOTE_ASSET]),
                 DEFAULT_SHARE_ASSET,
-                DEFAULT_FEES_BPS
+                DEFAULT_FEES_BPS,
+                None
             ),
             BadOrigin
         );
 
         assert_ok!(TestPallet::create_amm(
             Origin::signed(ALICE),
-            DEFAULT_BASE_ASSET,
-            DEFAULT_QUOTE_ASSET,
+            assets(&[DEFAULT_BASE_ASSET, DEFAULT_QUOTE_ASSET]),
             DEFAULT_SHARE_ASSET,
-            DEFAULT_FEES_BPS
+            DEFAULT_FEES_BPS,
+            None
         ));
     });
 }
 
 #[test]
 fn create_amm_increments_amm_counter() {
-    new_test_ext().execute_with(|| {
+    ExtBuilder::default().build().execute_with(|| {
         let before = TestPallet::amm_count();
 
         assert_ok!(TestPallet::create_amm(
             Origin::signed(ALICE),
-            DEFAULT_BASE_ASSET,
-            DEFAULT_QUOTE_ASSET,
+            assets(&[DEFAULT_BASE_ASSET, DEFAULT_QUOTE_ASSET]),
             DEFAULT_SHARE_ASSET,
-            DEFAULT_FEES_BPS
+            DEFAULT_FEES_BPS,
+            None
         ));
 
         assert_eq!(TestPallet::amm_count(), before + 1);
@@ -128,6 +167,149 @@ fn creating_the_same_asset_fails() {
             <Assets as Create<AccountId>>::create(DEFAULT_SHARE_ASSET, 0, true, 1),
             AssetsError::<Runtime>::InUse
         );
+
+        assert_noop!(
+            TestPallet::create_amm(
+                Origin::signed(ALICE),
+                assets(&[DEFAULT_SHARE_ASSET, DEFAULT_SHARE_ASSET]),
+                DEFAULT_SHARE_ASSET + 1,
+                DEFAULT_FEES_BPS,
+                None
+            ),
+            Error::<Runtime>::InvalidAssets
+        );
+        // No other asset exists yet.
+        assert_noop!(
+            TestPallet::create_amm(
+                Origin::signed(ALICE),
+                assets(&[DEFAULT_SHARE_ASSET, DOT]),
+                DEFAULT_SHARE_ASSET + 1,
+                DEFAULT_FEES_BPS,
+                None
+            ),
+            Error::<Runtime>::UnknownAsset
+        );
+    })
+}
+
+#[test]
+fn cant_create_amm_of_unknown_asset() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(
+            TestPallet::create_amm(
+                Origin::signed(ALICE),
+                assets(&[DOT, USDC, 42]),
+                DEFAULT_SHARE_ASSET,
+                DEFAULT_FEES_BPS,
+                None
+            ),
+            Error::<Runtime>::UnknownAsset
+        );
+        assert_noop!(
+            TestPallet::create_concentrated_amm(Origin::signed(ALICE), [42, USDC], 30, 60, 0),
+            Error::<Runtime>::UnknownAsset
+        );
+    })
+}
+
+#[test]
+fn cant_create_amm_with_fees_of_100_percent_or_more() {
+    ExtBuilder::default().build().execute_with(|| {
+        for fees_bps in [10_000, 20_000] {
+            assert_noop!(
+                TestPallet::create_amm(
+                    Origin::signed(ALICE),
+                    assets(&[DOT, USDC]),
+                    DEFAULT_SHARE_ASSET,
+                    fees_bps,
+                    None
+                ),
+                Error::<Runtime>::InvalidFees
+            );
+        }
+        assert_noop!(
+            TestPallet::create_concentrated_amm(Origin::signed(ALICE), [DOT, USDC], 10_000, 60, 0),
+            Error::<Runtime>::InvalidFees
+        );
+    })
+}
+
+#[test]
+fn create_amm_names_share_asset_after_pooled_assets() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        let name = <Assets as InspectMetadata<AccountId>>::name(&DEFAULT_SHARE_ASSET);
+        let symbol = <Assets as InspectMetadata<AccountId>>::symbol(&DEFAULT_SHARE_ASSET);
+        let decimals = <Assets as InspectMetadata<AccountId>>::decimals(&DEFAULT_SHARE_ASSET);
+        assert_eq!(name, b"DOT-USDC LP".to_vec());
+        assert_eq!(symbol, b"DOT-USDC-LP".to_vec());
+        assert_eq!(decimals, DEFAULT_DECIMALS);
+
+        // The AMM's account pays the metadata deposit out of the funding from ALICE.
+        let amm_account = TestPallet::amm_account(&0);
+        let metadata_deposit = 1 + (name.len() + symbol.len()) as Balance;
+        assert_eq!(Balances::reserved_balance(amm_account), metadata_deposit);
+        assert_eq!(
+            Balances::free_balance(amm_account),
+            SHARE_METADATA_DEPOSIT - metadata_deposit
+        );
+    })
+}
+
+#[test]
+fn create_amm_truncates_long_share_asset_names() {
+    let mut builder = ExtBuilder::default();
+    builder.assets.push((3, 0, true, 1));
+    builder.metadata = [DOT, USDC, KSM, 3]
+        .into_iter()
+        .zip([b"LONGSYMBOL-A", b"LONGSYMBOL-B", b"LONGSYMBOL-C", b"LONGSYMBOL-D"])
+        .map(|(asset, symbol)| (asset, symbol.to_vec(), symbol.to_vec(), DEFAULT_DECIMALS))
+        .collect();
+    builder.build().execute_with(|| {
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[DOT, USDC, KSM, 3]),
+            DEFAULT_SHARE_ASSET,
+            DEFAULT_FEES_BPS,
+            None,
+        ));
+
+        // The symbols are joined into 51 bytes, cut down so that the suffix fits in 50.
+        let name = <Assets as InspectMetadata<AccountId>>::name(&DEFAULT_SHARE_ASSET);
+        let symbol = <Assets as InspectMetadata<AccountId>>::symbol(&DEFAULT_SHARE_ASSET);
+        assert_eq!(name, b"LONGSYMBOL-A-LONGSYMBOL-B-LONGSYMBOL-C-LONGSYMB LP".to_vec());
+        assert_eq!(symbol, b"LONGSYMBOL-A-LONGSYMBOL-B-LONGSYMBOL-C-LONGSYMB-LP".to_vec());
+    })
+}
+
+#[test]
+fn create_amm_reserves_pool_deposit() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        assert_eq!(
+            Balances::free_balance(ALICE),
+            NATIVE_BALANCE - POOL_DEPOSIT - SHARE_METADATA_DEPOSIT
+        );
+        assert_eq!(Balances::reserved_balance(ALICE), POOL_DEPOSIT);
+        assert_eq!(TestPallet::pool_deposit(0), Some((ALICE, POOL_DEPOSIT)));
+
+        // Accounts without enough native currency can't create AMMs.
+        assert_noop!(
+            TestPallet::create_amm(
+                Origin::signed(7),
+                assets(&[DOT, KSM]),
+                DEFAULT_SHARE_ASSET + 1,
+                DEFAULT_FEES_BPS,
+                None
+            ),
+            BalancesError::<Runtime>::InsufficientBalance
+        );
+        assert_noop!(
+            TestPallet::create_concentrated_amm(Origin::signed(7), [DOT, KSM], 30, 60, 0),
+            BalancesError::<Runtime>::InsufficientBalance
+        );
     })
 }
 
@@ -144,54 +326,29 @@ fn cant_create_amm_with_existing_lp_asset() {
         assert_noop!(
             TestPallet::create_amm(
                 Origin::signed(ALICE),
-                DEFAULT_BASE_ASSET,
-                DEFAULT_QUOTE_ASSET,
+                assets(&[DEFAULT_BASE_ASSET, DEFAULT_QUOTE_ASSET]),
                 DEFAULT_SHARE_ASSET,
-                DEFAULT_FEES_BPS
+                DEFAULT_FEES_BPS,
+                None
             ),
             Error::<Runtime>::InvalidShareAsset
         );
     })
 }
 
-// #[test]
-// fn create_amm_creates_new_liquidity_provider_asset() {
-//     ExtBuilder::default().build().execute_with(|| {
-//         // Runtime starts with no LP asset registered
-//         assert_eq!(
-//             <Assets as InspectMetadata<AccountId>>::name(&DEFAULT_SHARE_ASSET),
-//             Vec::<u8>::new()
-//         );
-
-//         assert_ok!(TestPallet::create_amm(
-//             Origin::root(),
-//             DOT,
-//             USDC,
-//             DEFAULT_SHARE_ASSET,
-//             DEFAULT_FEES_BPS
-//         ));
-
-//         let expected: Vec<u8> = b"DOT-USDC-LP"[..].into();
-//         assert_eq!(
-//             <Assets as InspectMetadata<AccountId>>::name(&DEFAULT_SHARE_ASSET),
-//             expected
-//         )
-//     })
-// }
-
 fn default_amm() {
     assert_ok!(TestPallet::create_amm(
         Origin::signed(ALICE),
-        DOT,
-        USDC,
+        assets(&[DOT, USDC]),
         DEFAULT_SHARE_ASSET,
         DEFAULT_FEES_BPS,
+        None,
     ));
 }
 
 #[test]
 fn create_amm_emits_event() {
-    new_test_ext().execute_with(|| {
+    ExtBuilder::default().build().execute_with(|| {
         // For events to be registered
         run_to_block(1);
 
@@ -201,11 +358,49 @@ fn create_amm_emits_event() {
     })
 }
 
+#[test]
+fn cant_create_amm_without_two_distinct_assets() {
+    ExtBuilder::default().build().execute_with(|| {
+        for ids in [&[DOT][..], &[DOT, DOT], &[DOT, USDC, DOT]] {
+            assert_noop!(
+                TestPallet::create_amm(
+                    Origin::signed(ALICE),
+                    assets(ids),
+                    DEFAULT_SHARE_ASSET,
+                    DEFAULT_FEES_BPS,
+                    None
+                ),
+                Error::<Runtime>::InvalidAssets
+            );
+        }
+    })
+}
+
+#[test]
+fn cant_provide_liquidity_with_wrong_asset_count() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        for amounts in [amounts(&[UNIT]), amounts(&[UNIT, UNIT * 100, UNIT])] {
+            assert_noop!(
+                TestPallet::provide_liquidity(Origin::signed(ALICE), 0, amounts, 0, None),
+                Error::<Runtime>::AssetCountMismatch
+            );
+        }
+    })
+}
+
 #[test]
 fn cant_provide_liquidity_to_nonexistent_amm() {
     ExtBuilder::default().build().execute_with(|| {
         assert_noop!(
-            TestPallet::provide_liquidity(Origin::signed(ALICE), 0, UNIT, UNIT),
+            TestPallet::provide_liquidity(
+                Origin::signed(ALICE),
+                0,
+                amounts(&[UNIT, UNIT]),
+                0,
+                None,
+            ),
             Error::<Runtime>::InvalidAmmId,
         );
     });
@@ -222,7 +417,13 @@ fn cant_provide_liquidity_if_holds_insufficient_token() {
         default_amm();
 
         assert_noop!(
-            TestPallet::provide_liquidity(Origin::signed(ALICE), 0, UNIT * 2, UNIT * 200),
+            TestPallet::provide_liquidity(
+                Origin::signed(ALICE),
+                0,
+                amounts(&[UNIT * 2, UNIT * 200]),
+                0,
+                None,
+            ),
             AssetsError::<Runtime>::BalanceLow,
         );
     })
@@ -243,31 +444,108 @@ fn first_liquidity_provider_initializes_amm() {
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(ALICE),
             0,
-            UNIT,
-            UNIT * 100,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
         ));
 
         let amm_state = TestPallet::amm_state(0).unwrap();
-        assert_eq!(amm_state.base_reserves, UNIT);
-        assert_eq!(amm_state.quote_reserves, UNIT * 100);
+        assert_eq!(amm_state.reserves[0], UNIT);
+        assert_eq!(amm_state.reserves[1], UNIT * 100);
 
+        // sqrt(1 DOT * 100 USDC) shares, minus those locked forever.
+        assert_eq!(amm_state.total_shares, 10 * UNIT);
         assert_eq!(
             <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &ALICE),
-            amm_state.total_shares
+            10 * UNIT - MINIMUM_LIQUIDITY
+        );
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(
+                DEFAULT_SHARE_ASSET,
+                &TestPallet::pallet_account(),
+            ),
+            MINIMUM_LIQUIDITY
         );
-        assert_eq!(amm_state.total_shares, 100 * UNIT);
 
         System::assert_last_event(
             Event::LiquidityAdded {
                 amm_id: 0,
                 user: ALICE,
-                shares: 100 * UNIT,
+                shares: 10 * UNIT - MINIMUM_LIQUIDITY,
             }
             .into(),
         );
     })
 }
 
+#[test]
+fn first_deposit_must_exceed_minimum_liquidity() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        // sqrt(10 * 100_000) shares would all be locked.
+        assert_noop!(
+            TestPallet::provide_liquidity(Origin::signed(BOB), 0, amounts(&[10, 100_000]), 0, None),
+            Error::<Runtime>::InsufficientInitialLiquidity
+        );
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(BOB),
+            0,
+            amounts(&[11, 100_000]),
+            0,
+            None,
+        ));
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &BOB),
+            1_048 - MINIMUM_LIQUIDITY
+        );
+    })
+}
+
+#[test]
+fn first_depositor_cant_inflate_share_value() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        // BOB initializes the AMM with dust and withdraws all of his shares, hoping to leave a
+        // single share behind that later deposits are rounded against.
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(BOB),
+            0,
+            amounts(&[1_000, 100_000]),
+            0,
+            None,
+        ));
+        let shares = <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &BOB);
+        assert_eq!(shares, 10_000 - MINIMUM_LIQUIDITY);
+        assert_ok!(TestPallet::withdraw(Origin::signed(BOB), 0, shares, amounts(&[0, 0]), None));
+
+        // The locked shares can't be withdrawn, so a share stays worth a tiny amount of assets.
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm_state.total_shares, MINIMUM_LIQUIDITY);
+        assert_eq!(amm_state.reserves.into_inner(), vec![100, 10_000]);
+        assert_noop!(
+            TestPallet::withdraw(Origin::signed(BOB), 0, 1, amounts(&[0, 0]), None),
+            Error::<Runtime>::InvalidShareAmount
+        );
+
+        // ALICE's deposit is worth as much as she put in, rather than being rounded away.
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT / 100, UNIT]),
+            1,
+            None,
+        ));
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &ALICE),
+            MINIMUM_LIQUIDITY * 100
+        );
+        assert_eq!(TestPallet::lp_share_value(0, &ALICE), Ok(vec![UNIT / 100, UNIT]));
+    })
+}
+
 #[test]
 fn second_liquidity_provider_adds_to_the_pool() {
     ExtBuilder {
@@ -286,206 +564,220 @@ fn second_liquidity_provider_adds_to_the_pool() {
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(ALICE),
             0,
-            UNIT,
-            UNIT * 100,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
         ));
 
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(BOB),
             0,
-            UNIT / 2,
-            UNIT * 50,
+            amounts(&[UNIT / 2, UNIT * 50]),
+            0,
+            None,
         ));
 
         let amm_state = TestPallet::amm_state(0).unwrap();
-        assert_eq!(amm_state.base_reserves, UNIT + UNIT / 2);
-        assert_eq!(amm_state.quote_reserves, 150 * UNIT);
-        assert_eq!(amm_state.total_shares, 150 * UNIT);
-    })
-}
-
-#[test]
-fn cannot_withdraw_from_nonexistent_amm() {
-    new_test_ext().execute_with(|| {
-        assert_noop!(
-            TestPallet::withdraw(Origin::signed(ALICE), 0, UNIT),
-            Error::<Runtime>::InvalidAmmId
-        );
-    })
-}
-
-#[test]
-fn cannot_withdraw_if_doesnt_have_shares() {
-    ExtBuilder::default().build().execute_with(|| {
-        default_amm();
-
-        assert_noop!(
-            TestPallet::withdraw(Origin::signed(ALICE), 0, UNIT),
-            Error::<Runtime>::InvalidShareAmount
-        );
+        assert_eq!(amm_state.reserves[0], UNIT + UNIT / 2);
+        assert_eq!(amm_state.reserves[1], 150 * UNIT);
+        assert_eq!(amm_state.total_shares, 15 * UNIT);
     })
 }
 
 #[test]
-fn withdraw_returns_share_of_pool_assets() {
+fn provide_liquidity_only_takes_proportional_amounts() {
     ExtBuilder {
         accounts: vec![
             (DOT, ALICE, UNIT),
             (USDC, ALICE, UNIT * 100),
-            (DOT, BOB, UNIT / 2),
-            (USDC, BOB, UNIT * 50),
+            (DOT, BOB, UNIT),
+            (USDC, BOB, UNIT * 100),
         ],
         ..Default::default()
     }
     .build()
     .execute_with(|| {
-        run_to_block(1);
-
         default_amm();
 
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(ALICE),
             0,
-            UNIT,
-            UNIT * 100,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
         ));
 
+        assert_noop!(
+            TestPallet::provide_liquidity(
+                Origin::signed(BOB),
+                0,
+                amounts(&[0, UNIT * 100]),
+                0,
+                None,
+            ),
+            Error::<Runtime>::ZeroAmount
+        );
+
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(BOB),
             0,
-            UNIT / 2,
-            UNIT * 50,
+            amounts(&[UNIT / 2, UNIT * 100]),
+            UNIT * 5,
+            None,
         ));
-
-        assert_ok!(TestPallet::withdraw(Origin::signed(ALICE), 0, 100 * UNIT));
-        assert_eq!(
-            <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &ALICE),
-            0
-        );
-        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &ALICE), UNIT);
-        assert_eq!(
-            <Assets as Inspect<AccountId>>::balance(USDC, &ALICE),
-            UNIT * 100
-        );
-        System::assert_last_event(
-            Event::LiquidityRemoved {
-                amm_id: 0,
-                user: ALICE,
-                shares: 100 * UNIT,
-            }
-            .into(),
-        );
-
-        assert_ok!(TestPallet::withdraw(Origin::signed(BOB), 0, 50 * UNIT));
         assert_eq!(
             <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &BOB),
-            0
+            UNIT * 5
         );
         assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT / 2);
         assert_eq!(
             <Assets as Inspect<AccountId>>::balance(USDC, &BOB),
             UNIT * 50
         );
-        System::assert_last_event(
-            Event::LiquidityRemoved {
-                amm_id: 0,
-                user: BOB,
-                shares: 50 * UNIT,
-            }
-            .into(),
-        );
 
         let amm_state = TestPallet::amm_state(0).unwrap();
-        assert_eq!(amm_state.total_shares, 0);
-        assert_eq!(amm_state.base_reserves, 0);
-        assert_eq!(amm_state.quote_reserves, 0);
-    })
-}
-
-#[test]
-fn should_not_swap_against_inexistent_amm() {
-    ExtBuilder {
-        accounts: vec![(DOT, ALICE, UNIT), (USDC, ALICE, UNIT * 100)],
-        ..Default::default()
-    }
-    .build()
-    .execute_with(|| {
-        run_to_block(1);
-
-        assert_noop!(
-            TestPallet::swap(Origin::signed(ALICE), 0, AssetType::Quote, UNIT, UNIT / 100,),
-            Error::<Runtime>::InvalidAmmId
-        );
-    })
-}
-
-#[test]
-fn should_not_swap_against_unitialized_amm() {
-    ExtBuilder {
-        accounts: vec![(DOT, ALICE, UNIT), (USDC, ALICE, UNIT * 100)],
-        ..Default::default()
-    }
-    .build()
-    .execute_with(|| {
-        run_to_block(1);
-
-        default_amm();
-
-        assert_noop!(
-            TestPallet::swap(Origin::signed(ALICE), 0, AssetType::Quote, UNIT, UNIT / 100,),
-            Error::<Runtime>::ZeroLiquidity
-        );
+        assert_eq!(amm_state.reserves[0], UNIT + UNIT / 2);
+        assert_eq!(amm_state.reserves[1], UNIT * 150);
     })
 }
 
 #[test]
-fn swap_quote_returns_base_asset() {
+fn provide_liquidity_tolerates_racing_swaps() {
     ExtBuilder {
         accounts: vec![
             (DOT, ALICE, UNIT),
             (USDC, ALICE, UNIT * 100),
             (DOT, BOB, UNIT),
             (USDC, BOB, UNIT * 100),
+            (USDC, CHARLIE, UNIT * 10),
         ],
         ..Default::default()
     }
     .build()
     .execute_with(|| {
-        run_to_block(1);
-
         default_amm();
 
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(ALICE),
             0,
-            UNIT,
-            UNIT * 100,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
         ));
 
-        assert_ok!(TestPallet::swap(
+        // BOB computes a deposit worth 5 shares, but CHARLIE's swap lands first and moves the
+        // reserves to 909_338 DOT and 110 USDC.
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(CHARLIE),
+            0,
+            USDC,
+            DOT,
+            UNIT * 10,
+            0,
+            None,
+        ));
+
+        assert_noop!(
+            TestPallet::provide_liquidity(
+                Origin::signed(BOB),
+                0,
+                amounts(&[UNIT / 2, UNIT * 50]),
+                UNIT * 5,
+                None,
+            ),
+            Error::<Runtime>::SlippageExceeded
+        );
+
+        // Within a 10% tolerance, the deposit is limited by USDC and takes less DOT.
+        assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(BOB),
             0,
-            AssetType::Quote,
-            UNIT,
-            UNIT / 102,
+            amounts(&[UNIT / 2, UNIT * 50]),
+            UNIT * 45 / 10,
+            None,
         ));
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &BOB),
+            4_545_454
+        );
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DOT, &BOB),
+            UNIT - 413_336
+        );
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(USDC, &BOB),
+            UNIT * 100 - 49_999_994
+        );
+    })
+}
 
-        assert!(
-            <Assets as Inspect<AccountId>>::balance(DEFAULT_BASE_ASSET, &BOB) < UNIT + UNIT / 100
+#[test]
+fn lp_share_value_matches_withdrawal() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT),
+            (USDC, ALICE, UNIT * 100),
+            (DOT, BOB, UNIT / 2),
+            (USDC, BOB, UNIT * 50),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        default_amm();
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(BOB),
+            0,
+            amounts(&[UNIT / 2, UNIT * 50]),
+            0,
+            None,
+        ));
+
+        assert_eq!(TestPallet::pool_reserves(0), Some(vec![UNIT + UNIT / 2, UNIT * 150]));
+        assert_eq!(TestPallet::lp_share_value(0, &BOB), Ok(vec![UNIT / 2, UNIT * 50]));
+        assert_eq!(TestPallet::lp_share_value(0, &CHARLIE), Ok(vec![0, 0]));
+        assert_eq!(TestPallet::pool_reserves(1), None);
+    })
+}
+
+#[test]
+fn cannot_withdraw_from_nonexistent_amm() {
+    new_test_ext().execute_with(|| {
+        assert_noop!(
+            TestPallet::withdraw(Origin::signed(ALICE), 0, UNIT, amounts(&[0, 0]), None),
+            Error::<Runtime>::InvalidAmmId
         );
+    })
+}
 
-        // TODO: assert last event matches Event::<T>::Swapped but without computing exact expected amounts
+#[test]
+fn cannot_withdraw_if_doesnt_have_shares() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        assert_noop!(
+            TestPallet::withdraw(Origin::signed(ALICE), 0, UNIT, amounts(&[0, 0]), None),
+            Error::<Runtime>::InvalidShareAmount
+        );
     })
 }
 
 #[test]
-fn swap_quote_incurs_slippage() {
+fn withdraw_returns_share_of_pool_assets() {
     ExtBuilder {
         accounts: vec![
             (DOT, ALICE, UNIT),
             (USDC, ALICE, UNIT * 100),
-            (DOT, BOB, UNIT),
-            (USDC, BOB, UNIT * 100),
+            (DOT, BOB, UNIT / 2),
+            (USDC, BOB, UNIT * 50),
         ],
         ..Default::default()
     }
@@ -493,32 +785,230 @@ fn swap_quote_incurs_slippage() {
     .execute_with(|| {
         run_to_block(1);
 
-        // No fees, just testing slippage due to x * y = K now.
-        assert_ok!(TestPallet::create_amm(
+        default_amm();
+
+        assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(ALICE),
-            DOT,
-            USDC,
-            DEFAULT_SHARE_ASSET,
             0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(BOB),
+            0,
+            amounts(&[UNIT / 2, UNIT * 50]),
+            0,
+            None,
         ));
 
+        // ALICE leaves behind the assets backing the locked shares.
+        let shares = 10 * UNIT - MINIMUM_LIQUIDITY;
+        assert_ok!(TestPallet::withdraw(Origin::signed(ALICE), 0, shares, amounts(&[0, 0]), None));
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &ALICE),
+            0
+        );
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &ALICE), UNIT - 100);
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(USDC, &ALICE),
+            UNIT * 100 - 10_000
+        );
+        System::assert_last_event(
+            Event::LiquidityRemoved {
+                amm_id: 0,
+                user: ALICE,
+                shares,
+                amounts: amounts(&[UNIT - 100, UNIT * 100 - 10_000]),
+            }
+            .into(),
+        );
+
+        assert_ok!(TestPallet::withdraw(Origin::signed(BOB), 0, 5 * UNIT, amounts(&[0, 0]), None));
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &BOB),
+            0
+        );
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT / 2);
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(USDC, &BOB),
+            UNIT * 50
+        );
+        System::assert_last_event(
+            Event::LiquidityRemoved {
+                amm_id: 0,
+                user: BOB,
+                shares: 5 * UNIT,
+                amounts: amounts(&[UNIT / 2, UNIT * 50]),
+            }
+            .into(),
+        );
+
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm_state.total_shares, MINIMUM_LIQUIDITY);
+        assert_eq!(amm_state.reserves[0], 100);
+        assert_eq!(amm_state.reserves[1], 10_000);
+    })
+}
+
+#[test]
+fn withdraw_respects_min_amounts() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT),
+            (USDC, ALICE, UNIT * 100),
+            (USDC, BOB, UNIT * 10),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        default_amm();
+
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(ALICE),
             0,
-            UNIT,
-            UNIT * 100,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
         ));
 
-        // Current price is 100 USDC / DOT, but due to slippage BOB will get less than that amount.
         assert_noop!(
-            TestPallet::swap(Origin::signed(BOB), 0, AssetType::Quote, UNIT, UNIT / 100,),
-            Error::<Runtime>::SlippageExceeded
+            TestPallet::withdraw(Origin::signed(ALICE), 0, UNIT * 5, amounts(&[0]), None),
+            Error::<Runtime>::AssetCountMismatch
+        );
+
+        // ALICE expects half of the pool, but BOB's swap lands first and takes some of the DOT.
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT * 10, 0, None));
+        assert_noop!(
+            TestPallet::withdraw(
+                Origin::signed(ALICE),
+                0,
+                UNIT * 5,
+                amounts(&[UNIT / 2, UNIT * 50]),
+                None,
+            ),
+            Error::<Runtime>::InsufficientWithdrawal
+        );
+
+        assert_ok!(TestPallet::withdraw(
+            Origin::signed(ALICE),
+            0,
+            UNIT * 5,
+            amounts(&[UNIT * 45 / 100, UNIT * 50]),
+            None,
+        ));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &ALICE), 454_669);
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(USDC, &ALICE),
+            UNIT * 55
         );
     })
 }
 
 #[test]
-fn swap_base_returns_quote_asset() {
+fn should_not_swap_against_inexistent_amm() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT), (USDC, ALICE, UNIT * 100)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(ALICE), 0, USDC, DOT, UNIT, UNIT / 100, None),
+            Error::<Runtime>::InvalidAmmId
+        );
+    })
+}
+
+#[test]
+fn should_not_swap_against_unitialized_amm() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT), (USDC, ALICE, UNIT * 100)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(ALICE), 0, USDC, DOT, UNIT, UNIT / 100, None),
+            Error::<Runtime>::ZeroLiquidity
+        );
+    })
+}
+
+#[test]
+fn should_not_swap_for_zero_output() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm_with_liquidity();
+
+        // 1 unit of USDC is worth a hundredth of a unit of DOT, which rounds down to nothing.
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, 1, 0, None),
+            Error::<Runtime>::ZeroAmount
+        );
+
+        // Intermediate hops of a route have no minimum output of their own.
+        let route: Route<Runtime> = vec![(0, DOT), (0, USDC)].try_into().unwrap();
+        assert_noop!(
+            TestPallet::swap_route(Origin::signed(BOB), USDC, route, 1, 0, None),
+            Error::<Runtime>::ZeroAmount
+        );
+    })
+}
+
+#[test]
+fn swap_by_asset_type_against_amm_of_two_assets() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm_with_liquidity();
+
+        // DOT was given first at creation, so it is the base asset and USDC the quote asset.
+        let output =
+            <TestPallet as SimulateSwap>::simulate_swap(0, AssetType::Quote, UNIT).unwrap();
+        assert_eq!(
+            Ok(output),
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, USDC, DOT, UNIT)
+        );
+        assert_eq!(
+            <TestPallet as SimulateSwap>::output_price(0, AssetType::Quote, UNIT * 10),
+            <TestPallet as SimulateSwap>::output_price_between(0, DOT, USDC, UNIT * 10)
+        );
+
+        assert_ok!(TestPallet::swap(Origin::signed(BOB), 0, AssetType::Quote, UNIT, output, None));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 100 + output);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), 0);
+
+        // Pools of more assets have no single opposite asset to swap for.
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[DOT, USDC, KSM]),
+            DEFAULT_SHARE_ASSET + 1,
+            DEFAULT_FEES_BPS,
+            None,
+        ));
+        assert_eq!(
+            <TestPallet as SimulateSwap>::simulate_swap(1, AssetType::Base, UNIT),
+            Err(Error::<Runtime>::AssetCountMismatch.into())
+        );
+        assert_noop!(
+            TestPallet::swap(Origin::signed(BOB), 1, AssetType::Base, UNIT, 0, None),
+            Error::<Runtime>::AssetCountMismatch
+        );
+    })
+}
+
+#[test]
+fn swap_quote_returns_base_asset() {
     ExtBuilder {
         accounts: vec![
             (DOT, ALICE, UNIT),
@@ -537,27 +1027,31 @@ fn swap_base_returns_quote_asset() {
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(ALICE),
             0,
-            UNIT,
-            UNIT * 100,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
         ));
 
-        // quote - (k / (base + .5)) ~= 33.3333
-        assert_ok!(TestPallet::swap(
+        assert_ok!(TestPallet::swap_between(
             Origin::signed(BOB),
             0,
-            AssetType::Base,
-            UNIT / 2,
-            UNIT * 32
+            USDC,
+            DOT,
+            UNIT,
+            UNIT / 102,
+            None,
         ));
 
-        assert!(<Assets as Inspect<AccountId>>::balance(DEFAULT_QUOTE_ASSET, &BOB) < UNIT * 150);
+        assert!(
+            <Assets as Inspect<AccountId>>::balance(DEFAULT_BASE_ASSET, &BOB) < UNIT + UNIT / 100
+        );
 
         // TODO: assert last event matches Event::<T>::Swapped but without computing exact expected amounts
     })
 }
 
 #[test]
-fn swap_base_incurs_slippage() {
+fn swap_quote_incurs_slippage() {
     ExtBuilder {
         accounts: vec![
             (DOT, ALICE, UNIT),
@@ -574,36 +1068,36 @@ fn swap_base_incurs_slippage() {
         // No fees, just testing slippage due to x * y = K now.
         assert_ok!(TestPallet::create_amm(
             Origin::signed(ALICE),
-            DOT,
-            USDC,
+            assets(&[DOT, USDC]),
             DEFAULT_SHARE_ASSET,
             0,
+            None,
         ));
 
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(ALICE),
             0,
-            UNIT,
-            UNIT * 100,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
         ));
 
-        // Current price is 100 USDC / DOT, but due to slippage BOB will get less than expected
+        // Current price is 100 USDC / DOT, but due to slippage BOB will get less than that amount.
         assert_noop!(
-            TestPallet::swap(Origin::signed(BOB), 0, AssetType::Base, UNIT / 2, UNIT * 50),
+            TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT, UNIT / 100, None),
             Error::<Runtime>::SlippageExceeded
         );
     })
 }
 
 #[test]
-fn should_accrue_rewards_to_liquidity_providers() {
+fn swap_base_returns_quote_asset() {
     ExtBuilder {
         accounts: vec![
             (DOT, ALICE, UNIT),
             (USDC, ALICE, UNIT * 100),
-            (DOT, BOB, UNIT / 2),
-            (USDC, BOB, UNIT * 50),
-            (USDC, CHARLIE, UNIT * 10),
+            (DOT, BOB, UNIT),
+            (USDC, BOB, UNIT * 100),
         ],
         ..Default::default()
     }
@@ -616,43 +1110,3635 @@ fn should_accrue_rewards_to_liquidity_providers() {
         assert_ok!(TestPallet::provide_liquidity(
             Origin::signed(ALICE),
             0,
-            UNIT,
-            UNIT * 100,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
         ));
 
-        assert_ok!(TestPallet::provide_liquidity(
+        // quote - (k / (base + .5)) ~= 33.3333
+        assert_ok!(TestPallet::swap_between(
             Origin::signed(BOB),
             0,
+            DOT,
+            USDC,
             UNIT / 2,
-            UNIT * 50,
+            UNIT * 32,
+            None,
+        ));
+
+        assert!(<Assets as Inspect<AccountId>>::balance(DEFAULT_QUOTE_ASSET, &BOB) < UNIT * 150);
+
+        // TODO: assert last event matches Event::<T>::Swapped but without computing exact expected amounts
+    })
+}
+
+#[test]
+fn swap_base_incurs_slippage() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT),
+            (USDC, ALICE, UNIT * 100),
+            (DOT, BOB, UNIT),
+            (USDC, BOB, UNIT * 100),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        // No fees, just testing slippage due to x * y = K now.
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[DOT, USDC]),
+            DEFAULT_SHARE_ASSET,
+            0,
+            None,
+        ));
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        // Current price is 100 USDC / DOT, but due to slippage BOB will get less than expected
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, DOT, USDC, UNIT / 2, UNIT * 50, None),
+            Error::<Runtime>::SlippageExceeded
+        );
+    })
+}
+
+#[test]
+fn should_accrue_rewards_to_liquidity_providers() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT),
+            (USDC, ALICE, UNIT * 100),
+            (DOT, BOB, UNIT / 2),
+            (USDC, BOB, UNIT * 50),
+            (USDC, CHARLIE, UNIT * 10),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(BOB),
+            0,
+            amounts(&[UNIT / 2, UNIT * 50]),
+            0,
+            None,
         ));
 
         // Charlie swaps twice and returns AMM back to initial reserve proportions.
-        assert_ok!(TestPallet::swap(
+        assert_ok!(TestPallet::swap_between(
             Origin::signed(CHARLIE),
             0,
-            AssetType::Quote,
+            USDC,
+            DOT,
             UNIT * 10,
-            0
+            0,
+            None,
         ));
-        assert_ok!(TestPallet::swap(
+        assert_ok!(TestPallet::swap_between(
             Origin::signed(CHARLIE),
             0,
-            AssetType::Base,
+            DOT,
+            USDC,
             <Assets as Inspect<AccountId>>::balance(DOT, &CHARLIE),
-            0
+            0,
+            None,
         ));
         // Ensure fees were charged during the two operations.
         assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &CHARLIE), 0);
         assert!(<Assets as Inspect<AccountId>>::balance(USDC, &CHARLIE) < UNIT * 10);
 
         let amm_state = TestPallet::amm_state(0).unwrap();
-        assert_eq!(amm_state.base_reserves, UNIT + UNIT / 2);
-        assert!(amm_state.quote_reserves > UNIT * 150);
+        assert_eq!(amm_state.reserves[0], UNIT + UNIT / 2);
+        assert!(amm_state.reserves[1] > UNIT * 150);
 
         // Bob withdraws his shares and realizes his rewards
-        assert_ok!(TestPallet::withdraw(Origin::signed(BOB), 0, 50 * UNIT));
+        assert_ok!(TestPallet::withdraw(Origin::signed(BOB), 0, 5 * UNIT, amounts(&[0, 0]), None));
         assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT / 2);
         assert!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB) > UNIT * 50);
     })
 }
+
+#[test]
+fn protocol_fee_is_sent_to_recipient() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT),
+            (USDC, ALICE, UNIT * 100),
+            (USDC, BOB, UNIT),
+        ],
+        protocol_fee: Permill::from_percent(20),
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT, 0, None));
+
+        // A fifth of the 0.3% fee leaves the pool, without changing the output.
+        System::assert_last_event(
+            Event::Swapped {
+                user: BOB,
+                amm_id: 0,
+                asset_in: USDC,
+                asset_out: DOT,
+                input_amount: UNIT,
+                output_amount: 9_872,
+                lp_fee: 2_400,
+                protocol_fee: 600,
+            }
+            .into(),
+        );
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &TREASURY), 600);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), 9_872);
+
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm_state.reserves[0], UNIT - 9_872);
+        assert_eq!(amm_state.reserves[1], UNIT * 100 + UNIT - 600);
+    })
+}
+
+#[test]
+fn output_price_is_never_an_underestimate() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        for amount in [1, 7, UNIT / 3, UNIT, UNIT * 33 + 1] {
+            let input = <TestPallet as SimulateSwap>::output_price_between(0, DOT, USDC, amount)
+                .unwrap();
+            let output =
+                <TestPallet as SimulateSwap>::simulate_swap_between(0, DOT, USDC, input).unwrap();
+            assert!(output >= amount);
+        }
+    })
+}
+
+#[test]
+fn swap_exact_out_returns_requested_amount() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        let dot_required =
+            <TestPallet as SimulateSwap>::output_price_between(0, DOT, USDC, UNIT * 40).unwrap();
+
+        assert_ok!(TestPallet::swap_exact_out(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            USDC,
+            UNIT * 40,
+            dot_required,
+            None,
+        ));
+
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DOT, &BOB),
+            UNIT * 100 - dot_required
+        );
+        assert!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB) >= UNIT + UNIT * 40);
+    })
+}
+
+#[test]
+fn swap_exact_out_respects_max_input() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        let dot_required =
+            <TestPallet as SimulateSwap>::output_price_between(0, DOT, USDC, UNIT * 40).unwrap();
+
+        assert_noop!(
+            TestPallet::swap_exact_out(
+                Origin::signed(BOB),
+                0,
+                DOT,
+                USDC,
+                UNIT * 40,
+                dot_required - 1,
+                None,
+            ),
+            Error::<Runtime>::SlippageExceeded
+        );
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Deadlines
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn cant_execute_calls_after_deadline() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(5);
+        default_amm_with_liquidity();
+
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, DOT, USDC, UNIT, 0, Some(4)),
+            Error::<Runtime>::DeadlineExpired
+        );
+        assert_noop!(
+            TestPallet::provide_liquidity(
+                Origin::signed(BOB),
+                0,
+                amounts(&[UNIT / 100, UNIT]),
+                0,
+                Some(4),
+            ),
+            Error::<Runtime>::DeadlineExpired
+        );
+        assert_noop!(
+            TestPallet::withdraw(Origin::signed(ALICE), 0, UNIT, amounts(&[0, 0]), Some(4)),
+            Error::<Runtime>::DeadlineExpired
+        );
+        assert_noop!(
+            TestPallet::swap_pair(
+                Origin::signed(BOB),
+                DOT,
+                USDC,
+                DEFAULT_FEES_BPS,
+                UNIT,
+                0,
+                Some(4),
+            ),
+            Error::<Runtime>::DeadlineExpired
+        );
+    })
+}
+
+#[test]
+fn can_execute_calls_up_to_deadline() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(5);
+        default_amm_with_liquidity();
+
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            USDC,
+            UNIT / 10,
+            0,
+            Some(5),
+        ));
+        assert_ok!(TestPallet::withdraw(
+            Origin::signed(ALICE),
+            0,
+            UNIT,
+            amounts(&[0, 0]),
+            Some(6),
+        ));
+    })
+}
+
+fn swap_call(deadline: Option<u64>) -> Call {
+    Call::TestPallet(crate::Call::swap_between {
+        amm_id: 0,
+        asset_in: DOT,
+        asset_out: USDC,
+        input_amount: UNIT,
+        output_min: 0,
+        deadline,
+    })
+}
+
+#[test]
+fn check_deadline_limits_longevity_to_deadline() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(5);
+        let check = CheckDeadline::<Runtime>::new();
+        let info = DispatchInfo::default();
+
+        // Valid for inclusion in blocks 5, 6 and 7.
+        let validity = check.validate(&ALICE, &swap_call(Some(7)), &info, 0).unwrap();
+        assert_eq!(validity.longevity, 3);
+
+        let validity = check.validate(&ALICE, &swap_call(Some(5)), &info, 0).unwrap();
+        assert_eq!(validity.longevity, 1);
+    })
+}
+
+#[test]
+fn check_deadline_rejects_stale_calls() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(5);
+        let info = DispatchInfo::default();
+
+        assert_eq!(
+            CheckDeadline::<Runtime>::new().validate(&ALICE, &swap_call(Some(4)), &info, 0),
+            InvalidTransaction::Stale.into()
+        );
+        assert_eq!(
+            CheckDeadline::<Runtime>::new().pre_dispatch(&ALICE, &swap_call(Some(4)), &info, 0),
+            Err(InvalidTransaction::Stale.into())
+        );
+    })
+}
+
+#[test]
+fn check_deadline_ignores_calls_without_deadline() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(5);
+        let info = DispatchInfo::default();
+        let remark = Call::System(frame_system::Call::remark { remark: vec![] });
+
+        for call in [swap_call(None), remark] {
+            assert_eq!(
+                CheckDeadline::<Runtime>::new().validate(&ALICE, &call, &info, 0),
+                Ok(ValidTransaction::default())
+            );
+        }
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Flash swaps
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn flash_swap_repaid_with_fees() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_amm_with_liquidity();
+
+        // 10 USDC plus the 0.3% fee, rounded up.
+        FlashRepayment::set(&vec![0, 10_030_091]);
+        assert_ok!(TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[0, UNIT * 10])));
+
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm_state.reserves.into_inner(), vec![UNIT, 100_030_091]);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT - 30_091);
+        let amm_account = TestPallet::amm_account(&0);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &amm_account), 100_030_091);
+        System::assert_last_event(
+            Event::FlashSwapped {
+                user: BOB,
+                amm_id: 0,
+                amounts_out: amounts(&[0, UNIT * 10]),
+                amounts_in: amounts(&[0, 10_030_091]),
+            }
+            .into(),
+        );
+    })
+}
+
+#[test]
+fn flash_swap_must_be_repaid_with_fees() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+
+        for repayment in [vec![0, 0], vec![0, UNIT * 10], vec![0, 10_030_090]] {
+            FlashRepayment::set(&repayment);
+            assert_noop!(
+                TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[0, UNIT * 10])),
+                Error::<Runtime>::InsufficientRepayment
+            );
+        }
+    })
+}
+
+#[test]
+fn flash_swap_can_be_repaid_in_other_asset() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+
+        // (1 DOT + 0.111112 DOT net of fees) * 90 USDC >= 1 DOT * 100 USDC
+        FlashRepayment::set(&vec![111_446, 0]);
+        assert_noop!(
+            TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[0, UNIT * 10])),
+            Error::<Runtime>::InsufficientRepayment
+        );
+
+        FlashRepayment::set(&vec![111_447, 0]);
+        assert_ok!(TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[0, UNIT * 10])));
+
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm_state.reserves.into_inner(), vec![1_111_447, UNIT * 90]);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 100 - 111_447);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT * 11);
+    })
+}
+
+#[test]
+fn flash_swap_sends_protocol_fee() {
+    ExtBuilder {
+        protocol_fee: Permill::from_percent(50),
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        default_amm_with_liquidity();
+
+        FlashRepayment::set(&vec![0, 10_030_091]);
+        assert_ok!(TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[0, UNIT * 10])));
+
+        // Half of the 30_091 fee, rounded down.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &TREASURY), 15_045);
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm_state.reserves.into_inner(), vec![UNIT, 100_015_046]);
+    })
+}
+
+#[test]
+fn flash_swap_repaid_at_output_price_with_every_curve() {
+    let setups = [(constant_product_and_stable_amms as fn(), vec![0, 1]), (weighted_amm, vec![0])];
+    for (setup, amm_ids) in setups {
+        ExtBuilder {
+            accounts: vec![
+                (DOT, ALICE, UNIT * 200),
+                (USDC, ALICE, UNIT * 200),
+                (DOT, BOB, UNIT * 10),
+            ],
+            ..Default::default()
+        }
+        .build()
+        .execute_with(|| {
+            setup();
+
+            for amm_id in amm_ids {
+                let input =
+                    <TestPallet as SimulateSwap>::output_price_between(amm_id, DOT, USDC, UNIT)
+                        .unwrap();
+                FlashRepayment::set(&vec![input, 0]);
+                let amounts = amounts(&[0, UNIT]);
+                assert_ok!(TestPallet::flash_swap(Origin::signed(BOB), amm_id, amounts));
+            }
+        });
+    }
+}
+
+#[test]
+fn cant_flash_swap_invalid_amounts() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+
+        assert_noop!(
+            TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[0, 0])),
+            Error::<Runtime>::ZeroAmount
+        );
+        assert_noop!(
+            TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[UNIT])),
+            Error::<Runtime>::AssetCountMismatch
+        );
+        assert_noop!(
+            TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[UNIT, 0])),
+            Error::<Runtime>::InsufficientLiquidity
+        );
+        assert_noop!(
+            TestPallet::flash_swap(Origin::signed(BOB), 1, amounts(&[UNIT, 0])),
+            Error::<Runtime>::InvalidAmmId
+        );
+    })
+}
+
+#[test]
+fn cant_change_liquidity_during_flash_swap() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+
+        // Swapping the borrowed USDC back into the AMM moves its reserves.
+        FlashReentry::set(&true);
+        assert_noop!(
+            TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[0, UNIT * 10])),
+            Error::<Runtime>::ReentrantFlashSwap
+        );
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Limit orders
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn place_limit_order_escrows_input() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_amm();
+
+        assert_ok!(TestPallet::place_limit_order(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            USDC,
+            UNIT,
+            UNIT * 7,
+        ));
+
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 99);
+        let escrow = TestPallet::order_escrow_account();
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &escrow), UNIT);
+        let order = TestPallet::limit_order(0).unwrap();
+        assert_eq!(order.owner, BOB);
+        assert_eq!((order.asset_in, order.asset_out), (DOT, USDC));
+        assert_eq!((order.input_amount, order.output_min), (UNIT, UNIT * 7));
+        assert_eq!(TestPallet::limit_order_count(), 1);
+        System::assert_last_event(
+            Event::LimitOrderPlaced {
+                order_id: 0,
+                owner: BOB,
+                amm_id: 0,
+                asset_in: DOT,
+                asset_out: USDC,
+                input_amount: UNIT,
+                output_min: UNIT * 7,
+            }
+            .into(),
+        );
+    })
+}
+
+#[test]
+fn cant_place_invalid_limit_order() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        assert_noop!(
+            TestPallet::place_limit_order(Origin::signed(BOB), 0, DOT, USDC, 0, UNIT),
+            Error::<Runtime>::ZeroAmount
+        );
+        assert_noop!(
+            TestPallet::place_limit_order(Origin::signed(BOB), 1, DOT, USDC, UNIT, UNIT),
+            Error::<Runtime>::InvalidAmmId
+        );
+        for (asset_in, asset_out) in [(KSM, USDC), (DOT, DOT)] {
+            assert_noop!(
+                TestPallet::place_limit_order(Origin::signed(BOB), 0, asset_in, asset_out, UNIT, 1),
+                Error::<Runtime>::InvalidAsset
+            );
+        }
+        assert_noop!(
+            TestPallet::place_limit_order(Origin::signed(BOB), 0, USDC, DOT, UNIT * 2, UNIT),
+            AssetsError::<Runtime>::BalanceLow
+        );
+    })
+}
+
+#[test]
+fn only_owner_can_cancel_limit_order() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_amm();
+        assert_ok!(TestPallet::place_limit_order(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            USDC,
+            UNIT,
+            UNIT * 7,
+        ));
+
+        assert_noop!(
+            TestPallet::cancel_limit_order(Origin::signed(ALICE), 0),
+            Error::<Runtime>::NotOrderOwner
+        );
+        assert_noop!(
+            TestPallet::cancel_limit_order(Origin::signed(BOB), 1),
+            Error::<Runtime>::InvalidOrderId
+        );
+
+        assert_ok!(TestPallet::cancel_limit_order(Origin::signed(BOB), 0));
+
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 100);
+        assert!(TestPallet::limit_order(0).is_none());
+        System::assert_last_event(Event::LimitOrderCancelled { order_id: 0, owner: BOB }.into());
+    })
+}
+
+#[test]
+fn limit_orders_filled_once_price_allows() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_amm_with_liquidity();
+
+        // Selling 0.1 DOT currently pays out 9.066109 USDC.
+        assert_ok!(TestPallet::place_limit_order(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            USDC,
+            UNIT / 10,
+            UNIT * 9,
+        ));
+        assert_ok!(TestPallet::place_limit_order(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            USDC,
+            UNIT / 10,
+            9_200_000,
+        ));
+
+        TestPallet::on_idle(1, Weight::MAX);
+
+        assert!(TestPallet::limit_order(0).is_none());
+        assert!(TestPallet::limit_order(1).is_some());
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT + 9_066_109);
+
+        // Buying DOT moves its price back up, to 9.223647 USDC for 0.1 DOT.
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT * 10, 0, None));
+        run_to_block(2);
+        TestPallet::on_idle(2, Weight::MAX);
+
+        assert!(TestPallet::limit_order(1).is_none());
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), 9_289_756);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 998 / 10 + 108_688);
+        System::assert_last_event(
+            Event::LimitOrderFilled {
+                order_id: 1,
+                owner: BOB,
+                output_amount: 9_223_647,
+            }
+            .into(),
+        );
+    })
+}
+
+#[test]
+fn limit_orders_not_filled_while_amm_is_paused() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::place_limit_order(Origin::signed(BOB), 0, DOT, USDC, UNIT / 10, 0));
+        assert_ok!(TestPallet::pause_amm(Origin::root(), 0));
+
+        TestPallet::on_idle(1, Weight::MAX);
+        assert!(TestPallet::limit_order(0).is_some());
+
+        assert_ok!(TestPallet::unpause_amm(Origin::root(), 0));
+        TestPallet::on_idle(1, Weight::MAX);
+        assert!(TestPallet::limit_order(0).is_none());
+    })
+}
+
+#[test]
+fn on_idle_fills_limit_orders_within_weight_budget() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+        for _ in 0..3 {
+            assert_ok!(TestPallet::place_limit_order(
+                Origin::signed(BOB),
+                0,
+                DOT,
+                USDC,
+                UNIT / 100,
+                0,
+            ));
+        }
+        let fill_weight = TestPallet::limit_order_weight(true);
+
+        assert_eq!(TestPallet::on_idle(1, fill_weight - 1), 0);
+        assert_eq!(LimitOrders::<Runtime>::iter().count(), 3);
+
+        // The next call resumes after the last order checked.
+        assert_eq!(TestPallet::on_idle(1, fill_weight * 2), fill_weight * 2);
+        assert_eq!(LimitOrders::<Runtime>::iter().count(), 1);
+        assert_eq!(TestPallet::on_idle(2, fill_weight * 2), fill_weight);
+        assert_eq!(LimitOrders::<Runtime>::iter().count(), 0);
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Batch auctions
+// -------------------------------------------------------------------------------------------------
+
+/// Default accounts, with some USDC for CHARLIE to buy DOT in the same batch as BOB sells it.
+fn batch_ext() -> ExtBuilder {
+    let mut builder = ExtBuilder::default();
+    builder.accounts.push((USDC, CHARLIE, UNIT * 10));
+    builder
+}
+
+#[test]
+fn batch_swaps_clear_at_a_single_price() {
+    batch_ext().build().execute_with(|| {
+        run_to_block(1);
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, DOT, USDC, UNIT / 10, 0, None));
+        assert_ok!(TestPallet::swap_pair(
+            Origin::signed(CHARLIE),
+            USDC,
+            DOT,
+            DEFAULT_FEES_BPS,
+            UNIT * 5,
+            0,
+            None,
+        ));
+        System::assert_last_event(
+            Event::BatchSwapSubmitted {
+                user: CHARLIE,
+                amm_id: 0,
+                asset_in: USDC,
+                input_amount: UNIT * 5,
+                output_min: 0,
+            }
+            .into(),
+        );
+
+        // Inputs wait in escrow until the end of the block.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 999 / 10);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &CHARLIE), UNIT * 5);
+        assert_eq!(TestPallet::batch_swaps(0).len(), 2);
+        assert_eq!(TestPallet::amm_state(0).unwrap().reserves.to_vec(), vec![UNIT, UNIT * 100]);
+
+        TestPallet::on_finalize(1);
+
+        // CHARLIE's USDC covers most of BOB's DOT, and only 0.047475 DOT is sold to the AMM. BOB
+        // would have got 9.066109 USDC swapping alone.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT + 9_519_293);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &CHARLIE), 52_525);
+        assert_eq!(
+            TestPallet::amm_state(0).unwrap().reserves.to_vec(),
+            vec![1_047_475, 95_480_707]
+        );
+        assert!(TestPallet::batch_swaps(0).is_empty());
+        System::assert_has_event(
+            Event::BatchSwapFilled {
+                user: BOB,
+                amm_id: 0,
+                asset_in: DOT,
+                asset_out: USDC,
+                input_amount: UNIT / 10,
+                output_amount: 9_519_293,
+            }
+            .into(),
+        );
+        System::assert_last_event(Event::BatchCleared { amm_id: 0, price: 95_192_930 }.into());
+    })
+}
+
+#[test]
+fn batch_swaps_of_the_same_asset_share_output_pro_rata() {
+    batch_ext().build().execute_with(|| {
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, DOT, USDC, UNIT / 10, 0, None));
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(CHARLIE),
+            0,
+            USDC,
+            DOT,
+            UNIT * 5,
+            0,
+            None,
+        ));
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            USDC,
+            UNIT / 10,
+            UNIT * 8,
+            None,
+        ));
+
+        TestPallet::on_finalize(1);
+
+        // Both of BOB's swaps get 8.728048 USDC, and the unit left over from rounding them down
+        // goes to the reserves.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT + 8_728_048 * 2);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &CHARLIE), 57_287);
+        assert_eq!(
+            TestPallet::amm_state(0).unwrap().reserves.to_vec(),
+            vec![1_142_713, 87_543_904]
+        );
+    })
+}
+
+#[test]
+fn batch_swap_refunded_below_its_minimum() {
+    batch_ext().build().execute_with(|| {
+        run_to_block(1);
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, DOT, USDC, UNIT / 10, 0, None));
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(CHARLIE),
+            0,
+            USDC,
+            DOT,
+            UNIT * 5,
+            60_000,
+            None,
+        ));
+
+        TestPallet::on_finalize(1);
+
+        // Without CHARLIE's swap, BOB's clears against the AMM alone.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT + 9_066_109);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &CHARLIE), UNIT * 10);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &CHARLIE), 0);
+        System::assert_has_event(
+            Event::BatchSwapRefunded {
+                user: CHARLIE,
+                amm_id: 0,
+                asset_in: USDC,
+                input_amount: UNIT * 5,
+            }
+            .into(),
+        );
+    })
+}
+
+#[test]
+fn batch_refunded_when_amm_is_paused_before_clearing() {
+    batch_ext().build().execute_with(|| {
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, DOT, USDC, UNIT / 10, 0, None));
+        assert_ok!(TestPallet::pause_amm(Origin::root(), 0));
+
+        TestPallet::on_finalize(1);
+
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 100);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT);
+        assert_eq!(TestPallet::amm_state(0).unwrap().reserves.to_vec(), vec![UNIT, UNIT * 100]);
+    })
+}
+
+#[test]
+fn cant_submit_invalid_batch_swap() {
+    batch_ext().build().execute_with(|| {
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, DOT, USDC, 0, 0, None),
+            Error::<Runtime>::ZeroAmount
+        );
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, KSM, USDC, UNIT, 0, None),
+            Error::<Runtime>::InvalidAsset
+        );
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(CHARLIE), 0, USDC, DOT, UNIT * 20, 0, None),
+            AssetsError::<Runtime>::BalanceLow
+        );
+
+        // Up to `MaxBatchSwaps`, three in the mock.
+        for _ in 0..3 {
+            assert_ok!(TestPallet::swap_between(
+                Origin::signed(BOB),
+                0,
+                DOT,
+                USDC,
+                UNIT / 10,
+                0,
+                None,
+            ));
+        }
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, DOT, USDC, UNIT / 10, 0, None),
+            Error::<Runtime>::BatchFull
+        );
+
+        assert_ok!(TestPallet::pause_amm(Origin::root(), 0));
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(CHARLIE), 0, USDC, DOT, UNIT, 0, None),
+            Error::<Runtime>::AmmIsPaused
+        );
+    })
+}
+
+#[test]
+fn amm_in_batch_mode_only_takes_batch_swaps() {
+    batch_ext().build().execute_with(|| {
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+
+        assert_noop!(
+            TestPallet::swap_exact_out(Origin::signed(BOB), 0, DOT, USDC, UNIT, UNIT, None),
+            Error::<Runtime>::AmmInBatchMode
+        );
+        assert_noop!(
+            TestPallet::flash_swap(Origin::signed(BOB), 0, amounts(&[0, UNIT * 10])),
+            Error::<Runtime>::AmmInBatchMode
+        );
+
+        // Out of batch mode, swaps execute right away again.
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, false));
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, DOT, USDC, UNIT / 10, 0, None));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT + 9_066_109);
+    })
+}
+
+#[test]
+fn only_constant_product_pairs_can_be_batched() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_amm();
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[DOT, USDC]),
+            DEFAULT_SHARE_ASSET + 1,
+            DEFAULT_FEES_BPS + 1,
+            Some(Curve::StableSwap { amplification: 100 }),
+        ));
+
+        assert_noop!(
+            TestPallet::set_batch_mode(Origin::root(), 1, true),
+            Error::<Runtime>::InvalidCurve
+        );
+        assert_noop!(
+            TestPallet::set_batch_mode(Origin::root(), 2, true),
+            Error::<Runtime>::InvalidAmmId
+        );
+
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+        assert!(TestPallet::is_batched(0));
+        System::assert_last_event(Event::BatchModeSet { amm_id: 0, enabled: true }.into());
+    })
+}
+
+#[test]
+fn clearing_weight_reserved_for_each_batched_amm() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[DOT, KSM]),
+            DEFAULT_SHARE_ASSET + 1,
+            DEFAULT_FEES_BPS,
+            None,
+        ));
+        assert_eq!(TestPallet::on_initialize(1), 0);
+
+        // Enabling batch mode twice reserves the weight once.
+        let full_batch = <() as WeightInfo>::clear_batch(MaxBatchSwaps::get());
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+        assert_eq!(TestPallet::on_initialize(1), full_batch);
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 1, true));
+        assert_eq!(TestPallet::on_initialize(1), full_batch * 2);
+
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, false));
+        assert_eq!(TestPallet::on_initialize(1), full_batch);
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Farming
+// -------------------------------------------------------------------------------------------------
+
+/// A farm of the default AMM emitting 1/16th of a KSM per block from block 1 to 101, funded by BOB
+/// with `funding`, and one unit of ALICE's shares sent to CHARLIE. Stakes of one, two or four units
+/// of shares then earn rewards without rounding.
+fn default_farm(funding: Balance) {
+    default_amm_with_liquidity();
+    assert_ok!(TestPallet::create_farm(Origin::root(), 0, KSM, UNIT / 16, 1, 101));
+    assert_ok!(TestPallet::fund_farm(Origin::signed(BOB), 0, funding));
+    assert_ok!(Assets::transfer(Origin::signed(ALICE), DEFAULT_SHARE_ASSET, CHARLIE, UNIT));
+}
+
+#[test]
+fn create_farm_emits_event() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_amm_with_liquidity();
+
+        assert_ok!(TestPallet::create_farm(Origin::root(), 0, KSM, UNIT / 16, 1, 101));
+
+        let farm = TestPallet::farm(0).unwrap();
+        assert_eq!(farm.share_asset, DEFAULT_SHARE_ASSET);
+        assert_eq!((farm.start, farm.end), (1, 101));
+        assert_eq!(TestPallet::farm_count(), 1);
+        System::assert_last_event(
+            Event::FarmCreated {
+                farm_id: 0,
+                amm_id: 0,
+                reward_asset: KSM,
+                reward_per_block: UNIT / 16,
+                start: 1,
+                end: 101,
+            }
+            .into(),
+        );
+    })
+}
+
+#[test]
+fn cant_create_invalid_farm() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(10);
+        default_amm_with_liquidity();
+
+        assert_noop!(
+            TestPallet::create_farm(Origin::root(), 1, KSM, UNIT, 10, 20),
+            Error::<Runtime>::InvalidAmmId
+        );
+        assert_noop!(
+            TestPallet::create_farm(Origin::root(), 0, 42, UNIT, 10, 20),
+            Error::<Runtime>::UnknownAsset
+        );
+        for (start, end) in [(20, 20), (20, 15), (1, 10)] {
+            assert_noop!(
+                TestPallet::create_farm(Origin::root(), 0, KSM, UNIT, start, end),
+                Error::<Runtime>::InvalidFarmPeriod
+            );
+        }
+    })
+}
+
+#[test]
+fn farm_rewards_split_in_proportion_to_stake() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_farm(UNIT);
+
+        assert_ok!(TestPallet::stake(Origin::signed(ALICE), 0, UNIT * 3));
+        assert_ok!(TestPallet::stake(Origin::signed(CHARLIE), 0, UNIT));
+        System::assert_last_event(Event::Staked { farm_id: 0, user: CHARLIE, amount: UNIT }.into());
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &CHARLIE), 0);
+
+        // Half a KSM was emitted over 8 blocks.
+        run_to_block(9);
+        assert_eq!(TestPallet::pending_rewards(0, &ALICE), Ok(375_000));
+        assert_eq!(TestPallet::pending_rewards(0, &CHARLIE), Ok(125_000));
+
+        // The rewards earned so far stay owed after unstaking.
+        assert_ok!(TestPallet::unstake(Origin::signed(ALICE), 0, UNIT * 2));
+        System::assert_last_event(
+            Event::Unstaked { farm_id: 0, user: ALICE, amount: UNIT * 2 }.into(),
+        );
+        assert_eq!(TestPallet::farm(0).unwrap().total_staked, UNIT * 2);
+
+        run_to_block(17);
+        assert_ok!(TestPallet::claim_rewards(Origin::signed(ALICE), 0));
+        System::assert_last_event(
+            Event::RewardsClaimed { farm_id: 0, user: ALICE, amount: 625_000 }.into(),
+        );
+        assert_ok!(TestPallet::claim_rewards(Origin::signed(CHARLIE), 0));
+
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &ALICE), UNIT / 2 + 625_000);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &CHARLIE), 375_000);
+        assert_eq!(TestPallet::pending_rewards(0, &ALICE), Ok(0));
+        assert_eq!(TestPallet::farm(0).unwrap().unallocated, 0);
+    })
+}
+
+#[test]
+fn farm_stops_emitting_when_rewards_run_out() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_farm(UNIT / 4);
+        assert_ok!(TestPallet::stake(Origin::signed(ALICE), 0, UNIT));
+
+        // Four blocks' worth of rewards.
+        run_to_block(11);
+        assert_eq!(TestPallet::pending_rewards(0, &ALICE), Ok(UNIT / 4));
+
+        // New funds are only emitted from now on.
+        assert_ok!(TestPallet::fund_farm(Origin::signed(BOB), 0, UNIT / 4));
+        System::assert_last_event(
+            Event::FarmFunded { farm_id: 0, funder: BOB, amount: UNIT / 4 }.into(),
+        );
+        run_to_block(13);
+        assert_eq!(TestPallet::pending_rewards(0, &ALICE), Ok(UNIT / 4 + 125_000));
+    })
+}
+
+#[test]
+fn admin_funds_farm_from_farm_rewards_account() {
+    let mut builder = ExtBuilder::default();
+    builder.accounts.push((KSM, TREASURY, UNIT));
+    builder.build().execute_with(|| {
+        run_to_block(1);
+        default_farm(UNIT);
+
+        // Protocol fees are not spent on farms.
+        assert_noop!(
+            TestPallet::fund_farm(Origin::root(), 0, UNIT / 2),
+            AssetsError::<Runtime>::NoAccount
+        );
+
+        let rewards_account = TestPallet::farm_rewards_account();
+        assert_ok!(Assets::transfer(Origin::signed(BOB), KSM, rewards_account, UNIT));
+        assert_ok!(TestPallet::fund_farm(Origin::root(), 0, UNIT / 2));
+        System::assert_last_event(
+            Event::FarmFunded { farm_id: 0, funder: rewards_account, amount: UNIT / 2 }.into(),
+        );
+
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &TREASURY), UNIT);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &rewards_account), UNIT / 2);
+        assert_eq!(TestPallet::farm(0).unwrap().unallocated, UNIT + UNIT / 2);
+        assert_noop!(
+            TestPallet::fund_farm(Origin::signed(BOB), 0, 0),
+            Error::<Runtime>::ZeroAmount
+        );
+        assert_noop!(
+            TestPallet::fund_farm(Origin::signed(BOB), 1, UNIT),
+            Error::<Runtime>::InvalidFarmId
+        );
+    })
+}
+
+#[test]
+fn ended_farm_refunds_rewards_not_emitted() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_farm(UNIT);
+        assert_ok!(TestPallet::stake(Origin::signed(ALICE), 0, UNIT));
+
+        run_to_block(5);
+        assert_ok!(TestPallet::end_farm(Origin::root(), 0));
+
+        System::assert_last_event(Event::FarmEnded { farm_id: 0, refunded: 750_000 }.into());
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &TREASURY), 750_000);
+        assert_eq!(TestPallet::farm(0).unwrap().end, 5);
+
+        // Stakers keep what they earned until then.
+        run_to_block(10);
+        assert_ok!(TestPallet::claim_rewards(Origin::signed(ALICE), 0));
+        assert_ok!(TestPallet::unstake(Origin::signed(ALICE), 0, UNIT));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &ALICE), UNIT / 2 + 250_000);
+        assert!(TestPallet::stake_of(0, ALICE).is_none());
+    })
+}
+
+#[test]
+fn farm_rewards_change_from_the_current_block() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_farm(UNIT);
+        assert_ok!(TestPallet::stake(Origin::signed(ALICE), 0, UNIT));
+
+        run_to_block(5);
+        assert_noop!(
+            TestPallet::set_farm_rewards(Origin::root(), 0, UNIT / 8, 5),
+            Error::<Runtime>::InvalidFarmPeriod
+        );
+        assert_ok!(TestPallet::set_farm_rewards(Origin::root(), 0, UNIT / 8, 8));
+        System::assert_last_event(
+            Event::FarmUpdated { farm_id: 0, reward_per_block: UNIT / 8, end: 8 }.into(),
+        );
+
+        // Four blocks at the old rate, then three at the new one until the farm ends.
+        run_to_block(11);
+        assert_eq!(TestPallet::pending_rewards(0, &ALICE), Ok(250_000 + 375_000));
+
+        // Extending the farm does not emit for the blocks since it ended.
+        assert_ok!(TestPallet::set_farm_rewards(Origin::root(), 0, UNIT / 16, 13));
+        run_to_block(13);
+        assert_eq!(TestPallet::pending_rewards(0, &ALICE), Ok(750_000));
+    })
+}
+
+#[test]
+fn emergency_withdraw_returns_shares_without_rewards() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_farm(UNIT);
+        let shares = <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &ALICE);
+        assert_ok!(TestPallet::stake(Origin::signed(ALICE), 0, UNIT * 2));
+
+        run_to_block(3);
+        assert_ok!(TestPallet::unstake(Origin::signed(ALICE), 0, UNIT));
+        run_to_block(5);
+        assert_ok!(TestPallet::emergency_withdraw(Origin::signed(ALICE), 0));
+
+        System::assert_last_event(
+            Event::EmergencyWithdrawn { farm_id: 0, user: ALICE, amount: UNIT }.into(),
+        );
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &ALICE), shares);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &ALICE), UNIT / 2);
+        assert!(TestPallet::stake_of(0, ALICE).is_none());
+
+        // Rewards owed before go back to the farm, along with those earned since.
+        let farm = TestPallet::farm(0).unwrap();
+        assert_eq!((farm.total_staked, farm.unallocated), (0, UNIT));
+        assert_noop!(
+            TestPallet::emergency_withdraw(Origin::signed(ALICE), 0),
+            Error::<Runtime>::InsufficientStake
+        );
+    })
+}
+
+#[test]
+fn emergency_withdraw_leaves_other_stakes_unchanged() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_farm(UNIT);
+        assert_ok!(TestPallet::stake(Origin::signed(ALICE), 0, UNIT * 3));
+        assert_ok!(TestPallet::stake(Origin::signed(CHARLIE), 0, UNIT));
+
+        run_to_block(9);
+        assert_ok!(TestPallet::emergency_withdraw(Origin::signed(ALICE), 0));
+
+        // ALICE's 0.375 KSM go back to the farm rather than to CHARLIE.
+        let farm = TestPallet::farm(0).unwrap();
+        assert_eq!((farm.total_staked, farm.unallocated), (UNIT, 875_000));
+        assert_eq!(TestPallet::pending_rewards(0, &CHARLIE), Ok(125_000));
+
+        // CHARLIE earns all of the rewards from then on.
+        run_to_block(11);
+        assert_eq!(TestPallet::pending_rewards(0, &CHARLIE), Ok(250_000));
+    })
+}
+
+#[test]
+fn cant_stake_or_unstake_invalid_amounts() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_farm(UNIT);
+
+        assert_noop!(TestPallet::stake(Origin::signed(ALICE), 0, 0), Error::<Runtime>::ZeroAmount);
+        assert_noop!(
+            TestPallet::stake(Origin::signed(ALICE), 1, UNIT),
+            Error::<Runtime>::InvalidFarmId
+        );
+        assert_noop!(
+            TestPallet::stake(Origin::signed(ALICE), 0, UNIT * 9),
+            AssetsError::<Runtime>::BalanceLow
+        );
+        assert_noop!(
+            TestPallet::claim_rewards(Origin::signed(ALICE), 0),
+            Error::<Runtime>::InsufficientStake
+        );
+
+        assert_ok!(TestPallet::stake(Origin::signed(ALICE), 0, UNIT));
+        assert_noop!(
+            TestPallet::unstake(Origin::signed(ALICE), 0, UNIT + 1),
+            Error::<Runtime>::InsufficientStake
+        );
+        assert_noop!(
+            TestPallet::unstake(Origin::signed(CHARLIE), 0, UNIT),
+            Error::<Runtime>::InsufficientStake
+        );
+    })
+}
+
+#[test]
+fn farm_rewards_keep_fractions_across_claims() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+        default_amm_with_liquidity();
+        assert_ok!(Assets::transfer(Origin::signed(ALICE), DEFAULT_SHARE_ASSET, CHARLIE, 2));
+        assert_ok!(TestPallet::create_farm(Origin::root(), 0, KSM, 1, 1, 101));
+        assert_ok!(TestPallet::fund_farm(Origin::signed(BOB), 0, 30));
+        assert_ok!(TestPallet::stake(Origin::signed(ALICE), 0, 1));
+        assert_ok!(TestPallet::stake(Origin::signed(CHARLIE), 0, 2));
+
+        // CHARLIE earns two thirds of a unit per block. Paying that out rounded down would leave
+        // nothing, but the fractions add up to whole units in later claims.
+        for block in 2..=31 {
+            run_to_block(block);
+            assert_ok!(TestPallet::claim_rewards(Origin::signed(CHARLIE), 0));
+        }
+        assert_ok!(TestPallet::claim_rewards(Origin::signed(ALICE), 0));
+
+        // Out of 10 and 20 units, each loses less than one to the per-block growth of rewards
+        // per share being rounded down.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &CHARLIE), 19);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &ALICE), UNIT / 2 + 9);
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Large balances
+// -------------------------------------------------------------------------------------------------
+
+/// An amount whose square overflows the balance type many times over.
+pub const HUGE: Balance = Balance::MAX / 4;
+
+#[test]
+fn mul_div_widens_intermediate_product() {
+    assert_eq!(mul_div(Balance::MAX, Balance::MAX, Balance::MAX, Rounding::Down), Ok(Balance::MAX));
+    assert_eq!(mul_div(HUGE, 8, 4, Rounding::Down), Ok(HUGE * 2));
+    assert_eq!(mul_div::<Balance>(7, 3, 2, Rounding::Down), Ok(10));
+    assert_eq!(mul_div::<Balance>(7, 3, 2, Rounding::Up), Ok(11));
+    assert_eq!(mul_div::<Balance>(8, 3, 2, Rounding::Up), Ok(12));
+
+    assert_eq!(mul_div(Balance::MAX, 3, 2, Rounding::Down), Err(ArithmeticError::Overflow));
+    assert_eq!(mul_div::<Balance>(1, 1, 0, Rounding::Down), Err(ArithmeticError::DivisionByZero));
+}
+
+#[test]
+fn farm_rewards_near_balance_limit() {
+    let mut builder = ExtBuilder::default();
+    builder.accounts.push((KSM, CHARLIE, HUGE));
+    builder.build().execute_with(|| {
+        run_to_block(1);
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::create_farm(Origin::root(), 0, KSM, HUGE / 4, 1, 101));
+        assert_ok!(TestPallet::fund_farm(Origin::signed(CHARLIE), 0, HUGE));
+
+        // A single share earns all rewards, growing the rewards per share far beyond the balance
+        // type.
+        assert_ok!(TestPallet::stake(Origin::signed(ALICE), 0, 1));
+        run_to_block(5);
+        assert_ok!(TestPallet::claim_rewards(Origin::signed(ALICE), 0));
+
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &ALICE), UNIT / 2 + HUGE / 4 * 4);
+    })
+}
+
+#[test]
+fn geometric_mean_of_largest_balances() {
+    let max = U256::from(Balance::MAX);
+    for count in 2..=4 {
+        assert_eq!(constant_product::geometric_mean(&vec![max; count]), Ok(max));
+    }
+}
+
+#[test]
+fn constant_product_amm_handles_reserves_near_balance_limit() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, HUGE),
+            (USDC, ALICE, HUGE),
+            (DOT, BOB, HUGE / 2),
+            (USDC, BOB, HUGE / 2),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[HUGE, HUGE]),
+            0,
+            None,
+        ));
+        assert_eq!(TestPallet::amm_state(0).unwrap().total_shares, HUGE);
+
+        let output =
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, USDC, DOT, HUGE / 4).unwrap();
+        assert_eq!(output, 16_973_259_946_976_968_584_800_104_243_019_457_015);
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(BOB),
+            0,
+            USDC,
+            DOT,
+            HUGE / 4,
+            output,
+            None,
+        ));
+
+        let input =
+            <TestPallet as SimulateSwap>::output_price_between(0, DOT, USDC, HUGE / 10).unwrap();
+        assert_eq!(input, 5_939_325_086_848_166_000_701_543_553_697_840_988);
+        assert!(
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, DOT, USDC, input).unwrap()
+                >= HUGE / 10
+        );
+
+        // Depositing and withdrawing again only costs BOB the rounding of each amount.
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(BOB),
+            0,
+            amounts(&[HUGE / 8, HUGE / 8]),
+            0,
+            None,
+        ));
+        let shares = <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &BOB);
+        assert_eq!(shares, 8_507_059_173_023_461_586_584_365_185_794_205_285);
+        assert_ok!(TestPallet::withdraw(Origin::signed(BOB), 0, shares, amounts(&[0, 0]), None));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), HUGE / 2 + output - 1);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), HUGE / 2 - HUGE / 4 - 1);
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Single-sided liquidity
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn provide_liquidity_single_swaps_to_balance_deposit() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT),
+            (USDC, ALICE, UNIT * 100),
+            (DOT, BOB, UNIT),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        assert_noop!(
+            TestPallet::provide_liquidity_single(
+                Origin::signed(BOB),
+                0,
+                DOT,
+                UNIT / 10,
+                UNIT / 2,
+                None,
+            ),
+            Error::<Runtime>::SlippageExceeded
+        );
+
+        assert_ok!(TestPallet::provide_liquidity_single(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            UNIT / 10,
+            UNIT * 4 / 10,
+            None,
+        ));
+
+        // 48_882 DOT are swapped for 4_647_028 USDC, and the rest deposited along with them.
+        System::assert_has_event(
+            Event::Swapped {
+                user: BOB,
+                amm_id: 0,
+                asset_in: DOT,
+                asset_out: USDC,
+                input_amount: 48_882,
+                output_amount: 4_647_028,
+                lp_fee: 147,
+                protocol_fee: 0,
+            }
+            .into(),
+        );
+        System::assert_last_event(
+            Event::LiquidityAdded {
+                amm_id: 0,
+                user: BOB,
+                shares: 487_350,
+            }
+            .into(),
+        );
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DOT, &BOB),
+            UNIT - UNIT / 10
+        );
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), 0);
+
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm_state.reserves[0], UNIT + UNIT / 10);
+        assert_eq!(amm_state.reserves[1], UNIT * 100);
+        assert_eq!(amm_state.total_shares, UNIT * 10 + 487_350);
+    })
+}
+
+#[test]
+fn provide_liquidity_single_deposits_unbalanced_into_stable_amm() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 200),
+            (USDC, ALICE, UNIT * 200),
+            (DOT, BOB, UNIT * 10),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        constant_product_and_stable_amms();
+
+        assert_ok!(TestPallet::provide_liquidity_single(
+            Origin::signed(BOB),
+            1,
+            DOT,
+            UNIT * 10,
+            0,
+            None,
+        ));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), 0);
+        assert_eq!(TestPallet::amm_state(1).unwrap().reserves[0], UNIT * 110);
+
+        // Depositing 10 units worth of assets into a 200 unit pool, minus fees on the imbalance.
+        let shares = <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET + 1, &BOB);
+        let total_shares = TestPallet::amm_state(1).unwrap().total_shares;
+        assert!(shares < (total_shares - shares) * 10 / 200);
+        assert!(shares > (total_shares - shares) * 99 / 2000);
+    })
+}
+
+#[test]
+fn cant_provide_liquidity_single_to_unsupported_amm() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT * 200), (USDC, ALICE, UNIT * 100)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        default_amm();
+        assert_noop!(
+            TestPallet::provide_liquidity_single(Origin::signed(ALICE), 0, DOT, UNIT, 0, None),
+            Error::<Runtime>::ZeroLiquidity
+        );
+        assert_noop!(
+            TestPallet::provide_liquidity_single(Origin::signed(ALICE), 0, KSM, UNIT, 0, None),
+            Error::<Runtime>::InvalidAsset
+        );
+
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[DOT, USDC]),
+            DEFAULT_SHARE_ASSET + 1,
+            DEFAULT_FEES_BPS * 2,
+            Some(Curve::Weighted {
+                base_weight: Perbill::from_percent(80)
+            }),
+        ));
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            1,
+            amounts(&[UNIT * 100, UNIT * 25]),
+            0,
+            None,
+        ));
+        assert_noop!(
+            TestPallet::provide_liquidity_single(Origin::signed(ALICE), 1, DOT, UNIT, 0, None),
+            Error::<Runtime>::InvalidCurve
+        );
+    })
+}
+
+#[test]
+fn withdraw_single_returns_one_asset() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT), (USDC, ALICE, UNIT * 100)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        default_amm();
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        assert_noop!(
+            TestPallet::withdraw_single(
+                Origin::signed(ALICE),
+                0,
+                UNIT * 5,
+                DOT,
+                UNIT * 3 / 4,
+                None,
+            ),
+            Error::<Runtime>::SlippageExceeded
+        );
+
+        // Half of the pool is withdrawn, then the 50 USDC are swapped for 249_625 DOT.
+        assert_ok!(TestPallet::withdraw_single(
+            Origin::signed(ALICE),
+            0,
+            UNIT * 5,
+            DOT,
+            UNIT * 7 / 10,
+            None,
+        ));
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DOT, &ALICE),
+            UNIT / 2 + 249_625
+        );
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &ALICE), 0);
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &ALICE),
+            UNIT * 5 - MINIMUM_LIQUIDITY
+        );
+
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm_state.reserves[0], UNIT / 2 - 249_625);
+        assert_eq!(amm_state.reserves[1], UNIT * 100);
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Routed swaps
+// -------------------------------------------------------------------------------------------------
+
+/// Sets up a DOT/USDC AMM with id 0 and a KSM/DOT AMM with id 1, both initialized by ALICE.
+fn ksm_dot_usdc_amms() {
+    default_amm();
+    assert_ok!(TestPallet::create_amm(
+        Origin::signed(ALICE),
+        assets(&[KSM, DOT]),
+        DEFAULT_SHARE_ASSET + 1,
+        DEFAULT_FEES_BPS,
+        None,
+    ));
+
+    assert_ok!(TestPallet::provide_liquidity(
+        Origin::signed(ALICE),
+        0,
+        amounts(&[UNIT, UNIT * 100]),
+        0,
+        None,
+    ));
+    assert_ok!(TestPallet::provide_liquidity(
+        Origin::signed(ALICE),
+        1,
+        amounts(&[UNIT * 10, UNIT]),
+        0,
+        None,
+    ));
+}
+
+#[test]
+fn swap_route_chains_hops() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 2),
+            (USDC, ALICE, UNIT * 100),
+            (KSM, ALICE, UNIT * 10),
+            (KSM, BOB, UNIT),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        ksm_dot_usdc_amms();
+
+        // KSM -> DOT -> USDC
+        let dot_out =
+            <TestPallet as SimulateSwap>::simulate_swap_between(1, KSM, DOT, UNIT).unwrap();
+        let usdc_out =
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, DOT, USDC, dot_out).unwrap();
+
+        let route: Route<Runtime> = vec![(1, DOT), (0, USDC)].try_into().unwrap();
+        assert_ok!(TestPallet::swap_route(
+            Origin::signed(BOB),
+            KSM,
+            route.clone(),
+            UNIT,
+            usdc_out - 1,
+            None,
+        ));
+
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &BOB), 0);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), 0);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), usdc_out);
+
+        System::assert_has_event(
+            Event::Swapped {
+                user: BOB,
+                amm_id: 1,
+                asset_in: KSM,
+                asset_out: DOT,
+                input_amount: UNIT,
+                output_amount: dot_out,
+                lp_fee: UNIT * 3 / 1000,
+                protocol_fee: 0,
+            }
+            .into(),
+        );
+        System::assert_last_event(
+            Event::RouteSwapped {
+                user: BOB,
+                asset_in: KSM,
+                route,
+                input_amount: UNIT,
+                output_amount: usdc_out,
+            }
+            .into(),
+        );
+    })
+}
+
+#[test]
+fn swap_route_rejects_invalid_hops() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 2),
+            (USDC, ALICE, UNIT * 100),
+            (KSM, ALICE, UNIT * 10),
+            (KSM, BOB, UNIT),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        ksm_dot_usdc_amms();
+
+        assert_noop!(
+            TestPallet::swap_route(Origin::signed(BOB), KSM, Default::default(), UNIT, 0, None),
+            Error::<Runtime>::InvalidRoute
+        );
+
+        // KSM -> DOT, then DOT -> KSM through an AMM that does not hold KSM
+        let route: Route<Runtime> = vec![(1, DOT), (0, KSM)].try_into().unwrap();
+        assert_noop!(
+            TestPallet::swap_route(Origin::signed(BOB), KSM, route, UNIT, 0, None),
+            Error::<Runtime>::InvalidAsset
+        );
+    })
+}
+
+#[test]
+fn swap_route_reverts_all_hops_on_final_slippage() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 2),
+            (USDC, ALICE, UNIT * 100),
+            (KSM, ALICE, UNIT * 10),
+            (KSM, BOB, UNIT),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        ksm_dot_usdc_amms();
+
+        // 1 KSM is worth ~10 USDC at spot prices, but fees and slippage along the route reduce it.
+        let route: Route<Runtime> = vec![(1, DOT), (0, USDC)].try_into().unwrap();
+        assert_noop!(
+            TestPallet::swap_route(Origin::signed(BOB), KSM, route, UNIT, UNIT * 10, None),
+            Error::<Runtime>::SlippageExceeded
+        );
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Swap execution
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn execute_swap_exact_in_swaps_like_extrinsic() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm_with_liquidity();
+
+        let usdc_out =
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, DOT, USDC, UNIT / 10).unwrap();
+        assert_eq!(
+            <TestPallet as ExecuteSwap>::swap_exact_in(&BOB, 0, DOT, USDC, UNIT / 10, usdc_out),
+            Ok(usdc_out)
+        );
+
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 100 - UNIT / 10);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT + usdc_out);
+        System::assert_last_event(
+            Event::Swapped {
+                user: BOB,
+                amm_id: 0,
+                asset_in: DOT,
+                asset_out: USDC,
+                input_amount: UNIT / 10,
+                output_amount: usdc_out,
+                lp_fee: UNIT * 3 / 10_000,
+                protocol_fee: 0,
+            }
+            .into(),
+        );
+
+        assert_noop!(
+            <TestPallet as ExecuteSwap>::swap_exact_in(&BOB, 0, DOT, USDC, 0, 0),
+            Error::<Runtime>::ZeroAmount
+        );
+        assert_noop!(
+            <TestPallet as ExecuteSwap>::swap_exact_in(&BOB, 0, DOT, KSM, UNIT / 10, 0),
+            Error::<Runtime>::InvalidAsset
+        );
+    })
+}
+
+#[test]
+fn execute_swap_exact_out_returns_input_amount() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+
+        let dot_required =
+            <TestPallet as SimulateSwap>::output_price_between(0, DOT, USDC, UNIT * 40).unwrap();
+        assert_noop!(
+            <TestPallet as ExecuteSwap>::swap_exact_out(
+                &BOB,
+                0,
+                DOT,
+                USDC,
+                UNIT * 40,
+                dot_required - 1,
+            ),
+            Error::<Runtime>::SlippageExceeded
+        );
+
+        assert_eq!(
+            <TestPallet as ExecuteSwap>::swap_exact_out(
+                &BOB,
+                0,
+                DOT,
+                USDC,
+                UNIT * 40,
+                dot_required,
+            ),
+            Ok(dot_required)
+        );
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(DOT, &BOB),
+            UNIT * 100 - dot_required
+        );
+        assert!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB) >= UNIT + UNIT * 40);
+    })
+}
+
+#[test]
+fn execute_swap_route_reverts_all_hops_on_failure() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 2),
+            (USDC, ALICE, UNIT * 100),
+            (KSM, ALICE, UNIT * 10),
+            (KSM, BOB, UNIT),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        ksm_dot_usdc_amms();
+
+        // Not called from an extrinsic, so the first hop is only reverted by the trait itself.
+        assert_noop!(
+            <TestPallet as ExecuteSwap>::swap_route(
+                &BOB,
+                KSM,
+                &[(1, DOT), (0, USDC)],
+                UNIT,
+                UNIT * 10,
+            ),
+            Error::<Runtime>::SlippageExceeded
+        );
+        assert_noop!(
+            <TestPallet as ExecuteSwap>::swap_route(&BOB, KSM, &[(1, DOT); 5], UNIT, 0),
+            Error::<Runtime>::InvalidRoute
+        );
+
+        let dot_out =
+            <TestPallet as SimulateSwap>::simulate_swap_between(1, KSM, DOT, UNIT).unwrap();
+        let usdc_out =
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, DOT, USDC, dot_out).unwrap();
+        assert_eq!(
+            <TestPallet as ExecuteSwap>::swap_route(
+                &BOB,
+                KSM,
+                &[(1, DOT), (0, USDC)],
+                UNIT,
+                usdc_out,
+            ),
+            Ok(usdc_out)
+        );
+
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(KSM, &BOB), 0);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), usdc_out);
+        System::assert_last_event(
+            Event::RouteSwapped {
+                user: BOB,
+                asset_in: KSM,
+                route: vec![(1, DOT), (0, USDC)].try_into().unwrap(),
+                input_amount: UNIT,
+                output_amount: usdc_out,
+            }
+            .into(),
+        );
+    })
+}
+
+#[test]
+fn execute_swap_rejects_amm_in_batch_mode() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::set_batch_mode(Origin::root(), 0, true));
+
+        assert_noop!(
+            <TestPallet as ExecuteSwap>::swap_exact_in(&BOB, 0, DOT, USDC, UNIT / 10, 0),
+            Error::<Runtime>::AmmInBatchMode
+        );
+        assert_noop!(
+            <TestPallet as ExecuteSwap>::swap_exact_out(&BOB, 0, DOT, USDC, UNIT, UNIT),
+            Error::<Runtime>::AmmInBatchMode
+        );
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Price oracle
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn twap_averages_prices_over_window() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+        let initial_price = 100 * UNIT;
+
+        run_to_block(11);
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(BOB),
+            0,
+            USDC,
+            DOT,
+            UNIT / 2,
+            0,
+            None,
+        ));
+        let price = TestPallet::amm_state(0).unwrap().spot_price(0, 1, UNIT).unwrap();
+        assert!(price > initial_price);
+
+        run_to_block(21);
+        assert_eq!(TestPallet::twap(0, DOT, 10), Ok(price));
+        assert_eq!(
+            TestPallet::twap(0, DOT, 20),
+            Ok((initial_price * 10 + price * 10) / 20)
+        );
+        // Starts in between observations at blocks 1 and 11
+        assert_eq!(
+            TestPallet::twap(0, DOT, 15),
+            Ok((initial_price * 5 + price * 10) / 15)
+        );
+    })
+}
+
+#[test]
+fn twap_survives_accumulators_wrapping_around() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm_with_liquidity();
+        let initial_price = 100 * UNIT;
+
+        // Start the accumulators close to the largest balance, as after a long price history.
+        let start = Balance::MAX - initial_price * 5;
+        let observation = PriceObservation {
+            block: 1,
+            price_cumulatives: amounts(&[start, start]),
+        };
+        PriceObservations::<Runtime>::insert(0, BoundedVec::try_from(vec![observation]).unwrap());
+
+        run_to_block(11);
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT / 2, 0, None));
+        let price = TestPallet::amm_state(0).unwrap().spot_price(0, 1, UNIT).unwrap();
+        assert!(TestPallet::price_observations(0)[1].price_cumulative(0) < initial_price * 5);
+
+        run_to_block(21);
+        assert_eq!(TestPallet::twap(0, DOT, 10), Ok(price));
+        assert_eq!(
+            TestPallet::twap(0, DOT, 20),
+            Ok((initial_price * 10 + price * 10) / 20)
+        );
+        assert_eq!(
+            TestPallet::twap(0, DOT, 15),
+            Ok((initial_price * 5 + price * 10) / 15)
+        );
+    })
+}
+
+#[test]
+fn twap_ignores_swaps_in_current_block() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        run_to_block(11);
+        let before = TestPallet::twap(0, DOT, 10).unwrap();
+        assert_eq!(before, 100 * UNIT);
+
+        // Dumping DOT into the pool crashes its spot price...
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            USDC,
+            UNIT * 50,
+            0,
+            None,
+        ));
+        let spot_output =
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, DOT, USDC, UNIT).unwrap();
+        assert!(spot_output < UNIT);
+
+        // ...but not the average price within the same block.
+        assert_eq!(TestPallet::twap(0, DOT, 10), Ok(before));
+    })
+}
+
+#[test]
+fn price_history_overwrites_oldest_observations() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm_with_liquidity();
+
+        // One observation per block, for more blocks than `MaxPriceObservations`.
+        for block in 2..=20 {
+            run_to_block(block);
+            assert_ok!(TestPallet::swap_between(
+                Origin::signed(BOB),
+                0,
+                DOT,
+                USDC,
+                UNIT / 100,
+                0,
+                None,
+            ));
+        }
+
+        let blocks = TestPallet::price_history(&0).iter().map(|o| o.block).collect::<Vec<_>>();
+        assert_eq!(blocks, (5..=20_u64).collect::<Vec<_>>());
+        assert_eq!(PriceObservationHeads::<Runtime>::get(0), 4);
+        assert_eq!(TestPallet::price_observations(0)[3].block, 20);
+
+        run_to_block(21);
+        let price = TestPallet::amm_state(0).unwrap().spot_price(0, 1, UNIT).unwrap();
+        assert_eq!(TestPallet::twap(0, DOT, 1), Ok(price));
+        assert_ok!(TestPallet::twap(0, DOT, 16));
+        assert_noop!(
+            TestPallet::twap(0, DOT, 17),
+            Error::<Runtime>::InsufficientPriceHistory
+        );
+    })
+}
+
+#[test]
+fn twap_requires_price_history() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+
+        assert_noop!(
+            TestPallet::twap(0, DOT, 1),
+            Error::<Runtime>::InsufficientPriceHistory
+        );
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT, UNIT * 100]),
+            0,
+            None,
+        ));
+
+        run_to_block(11);
+        assert_noop!(
+            TestPallet::twap(0, DOT, 0),
+            Error::<Runtime>::InsufficientPriceHistory
+        );
+        assert_noop!(
+            TestPallet::twap(0, DOT, 11),
+            Error::<Runtime>::InsufficientPriceHistory
+        );
+        assert_noop!(
+            TestPallet::twap(0, DOT, 20),
+            Error::<Runtime>::InsufficientPriceHistory
+        );
+        assert_ok!(TestPallet::twap(0, DOT, 10));
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          StableSwap
+// -------------------------------------------------------------------------------------------------
+
+pub const DEFAULT_AMPLIFICATION: u32 = 100;
+pub const STABLE_FEES_BPS: Balance = 4;
+
+/// Sets up a constant product DOT/USDC AMM with id 0 and a StableSwap one with id 1 in a lower
+/// fee tier, both with 100 units of each asset provided by ALICE.
+fn constant_product_and_stable_amms() {
+    default_amm();
+    assert_ok!(TestPallet::create_amm(
+        Origin::signed(ALICE),
+        assets(&[DOT, USDC]),
+        DEFAULT_SHARE_ASSET + 1,
+        STABLE_FEES_BPS,
+        Some(Curve::StableSwap {
+            amplification: DEFAULT_AMPLIFICATION
+        }),
+    ));
+
+    for amm_id in [0, 1] {
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            amm_id,
+            amounts(&[UNIT * 100, UNIT * 100]),
+            0,
+            None,
+        ));
+    }
+}
+
+#[test]
+fn cant_create_stable_amm_without_amplification() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(
+            TestPallet::create_amm(
+                Origin::signed(ALICE),
+                assets(&[DOT, USDC]),
+                DEFAULT_SHARE_ASSET,
+                DEFAULT_FEES_BPS,
+                Some(Curve::StableSwap { amplification: 0 }),
+            ),
+            Error::<Runtime>::InvalidCurve
+        );
+    })
+}
+
+#[test]
+fn stable_swap_has_less_slippage_near_peg() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT * 200), (USDC, ALICE, UNIT * 200)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        constant_product_and_stable_amms();
+
+        let constant_product =
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, USDC, DOT, UNIT * 10).unwrap();
+        let stable =
+            <TestPallet as SimulateSwap>::simulate_swap_between(1, USDC, DOT, UNIT * 10).unwrap();
+
+        // Only the 0.04% fee and a tiny amount of slippage are lost in the stable AMM.
+        assert!(stable > constant_product);
+        assert!(stable > UNIT * 10 * 9_960 / 10_000);
+        assert!(stable < UNIT * 10);
+    })
+}
+
+#[test]
+fn stable_swap_output_price_is_never_an_underestimate() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT * 200), (USDC, ALICE, UNIT * 200)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        constant_product_and_stable_amms();
+
+        for amount in [1, UNIT / 3, UNIT * 10, UNIT * 90] {
+            let input = <TestPallet as SimulateSwap>::output_price_between(1, USDC, DOT, amount)
+                .unwrap();
+            let output =
+                <TestPallet as SimulateSwap>::simulate_swap_between(1, USDC, DOT, input).unwrap();
+            assert!(output >= amount);
+        }
+    })
+}
+
+#[test]
+fn stable_swap_of_dust_underflows_instead_of_paying_nothing() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT * 200), (USDC, ALICE, UNIT * 200)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        constant_product_and_stable_amms();
+
+        // Fees take the whole unit, and the unit kept in the pool against Newton's method error
+        // leaves the output reserves above where they started.
+        assert_eq!(
+            <TestPallet as SimulateSwap>::simulate_swap_between(1, USDC, DOT, 1),
+            Err(ArithmeticError::Underflow.into())
+        );
+    })
+}
+
+#[test]
+fn stable_swap_twap_follows_the_curve_rather_than_the_reserves() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT * 100), (USDC, ALICE, UNIT * 200)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[DOT, USDC]),
+            DEFAULT_SHARE_ASSET,
+            STABLE_FEES_BPS,
+            Some(Curve::StableSwap {
+                amplification: DEFAULT_AMPLIFICATION
+            }),
+        ));
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(ALICE),
+            0,
+            amounts(&[UNIT * 100, UNIT * 200]),
+            0,
+            None,
+        ));
+
+        // The reserves are 1:2, but the pool still trades DOT for barely more than one USDC.
+        let amm = TestPallet::amm_state(0).unwrap();
+        let dot_price = amm.spot_price(0, 1, UNIT).unwrap();
+        let usdc_price = amm.spot_price(1, 0, UNIT).unwrap();
+        assert!(dot_price > UNIT && dot_price < UNIT * 101 / 100);
+        assert!(usdc_price < UNIT && usdc_price > UNIT * 99 / 100);
+
+        // Selling a little DOT pays out close to the spot price.
+        let output = <TestPallet as SimulateSwap>::simulate_swap_between(0, DOT, USDC, UNIT)
+            .unwrap();
+        assert!(output < dot_price && output > dot_price * 99 / 100);
+
+        run_to_block(11);
+        assert_eq!(TestPallet::twap(0, DOT, 10), Ok(dot_price));
+        assert_eq!(TestPallet::twap(0, USDC, 10), Ok(usdc_price));
+    })
+}
+
+#[test]
+fn stable_swap_accepts_unbalanced_deposits() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 200),
+            (USDC, ALICE, UNIT * 200),
+            (DOT, BOB, UNIT * 10),
+            (USDC, BOB, UNIT * 10),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        constant_product_and_stable_amms();
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(BOB),
+            1,
+            amounts(&[UNIT * 10, UNIT * 5]),
+            0,
+            None,
+        ));
+
+        // Depositing 15 units worth of assets into a 200 unit pool, minus fees on the imbalance.
+        let shares = <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET + 1, &BOB);
+        let total_shares = TestPallet::amm_state(1).unwrap().total_shares;
+        assert!(shares < (total_shares - shares) * 15 / 200);
+        assert!(shares > (total_shares - shares) * 149 / 2000);
+    })
+}
+
+/// Sets up a StableSwap DOT/USDC/KSM AMM with id 0 and 100 units of each asset provided by ALICE.
+fn three_asset_stable_amm() {
+    assert_ok!(TestPallet::create_amm(
+        Origin::signed(ALICE),
+        assets(&[DOT, USDC, KSM]),
+        DEFAULT_SHARE_ASSET,
+        DEFAULT_FEES_BPS,
+        Some(Curve::StableSwap {
+            amplification: DEFAULT_AMPLIFICATION
+        }),
+    ));
+
+    assert_ok!(TestPallet::provide_liquidity(
+        Origin::signed(ALICE),
+        0,
+        amounts(&[UNIT * 100, UNIT * 100, UNIT * 100]),
+        0,
+        None,
+    ));
+}
+
+#[test]
+fn stable_swap_pool_swaps_between_any_two_assets() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 100),
+            (USDC, ALICE, UNIT * 100),
+            (KSM, ALICE, UNIT * 100),
+            (KSM, BOB, UNIT * 10),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        three_asset_stable_amm();
+
+        let output =
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, KSM, USDC, UNIT * 10).unwrap();
+        assert!(output > UNIT * 10 * 9_950 / 10_000);
+        assert!(output < UNIT * 10);
+
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(BOB),
+            0,
+            KSM,
+            USDC,
+            UNIT * 10,
+            output,
+            None,
+        ));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), output);
+
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(
+            amm_state.reserves.into_inner(),
+            vec![UNIT * 100, UNIT * 100 - output, UNIT * 110]
+        );
+
+        // The asset left untouched by the swap can still be bought with either of the others.
+        let input = <TestPallet as SimulateSwap>::output_price_between(0, USDC, DOT, UNIT).unwrap();
+        let output =
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, USDC, DOT, input).unwrap();
+        assert!(output >= UNIT);
+    })
+}
+
+#[test]
+fn stable_swap_pool_withdraws_every_asset() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 100),
+            (USDC, ALICE, UNIT * 100),
+            (KSM, ALICE, UNIT * 100),
+            (DOT, BOB, UNIT * 10),
+            (USDC, BOB, UNIT * 10),
+            (KSM, BOB, UNIT * 10),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        three_asset_stable_amm();
+
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(BOB),
+            0,
+            amounts(&[UNIT * 10, UNIT * 10, UNIT * 10]),
+            0,
+            None,
+        ));
+        let shares = <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &BOB);
+        assert_eq!(shares, UNIT * 30);
+        assert_eq!(
+            TestPallet::lp_share_value(0, &BOB),
+            Ok(vec![UNIT * 10, UNIT * 10, UNIT * 10])
+        );
+
+        assert_ok!(TestPallet::withdraw(Origin::signed(BOB), 0, shares, amounts(&[0, 0, 0]), None));
+        for asset in [DOT, USDC, KSM] {
+            assert_eq!(<Assets as Inspect<AccountId>>::balance(asset, &BOB), UNIT * 10);
+        }
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Weighted
+// -------------------------------------------------------------------------------------------------
+
+/// Sets up an 80/20 DOT/USDC AMM with id 0 where ALICE provided 100 DOT and 25 USDC, pricing DOT
+/// at 1 USDC.
+fn weighted_amm() {
+    assert_ok!(TestPallet::create_amm(
+        Origin::signed(ALICE),
+        assets(&[DOT, USDC]),
+        DEFAULT_SHARE_ASSET,
+        DEFAULT_FEES_BPS,
+        Some(Curve::Weighted {
+            base_weight: Perbill::from_percent(80)
+        }),
+    ));
+
+    assert_ok!(TestPallet::provide_liquidity(
+        Origin::signed(ALICE),
+        0,
+        amounts(&[UNIT * 100, UNIT * 25]),
+        0,
+        None,
+    ));
+}
+
+#[test]
+fn cant_create_weighted_amm_with_extreme_weights() {
+    ExtBuilder::default().build().execute_with(|| {
+        for percent in [0, 1, 99, 100] {
+            assert_noop!(
+                TestPallet::create_amm(
+                    Origin::signed(ALICE),
+                    assets(&[DOT, USDC]),
+                    DEFAULT_SHARE_ASSET,
+                    DEFAULT_FEES_BPS,
+                    Some(Curve::Weighted {
+                        base_weight: Perbill::from_percent(percent)
+                    }),
+                ),
+                Error::<Runtime>::InvalidCurve
+            );
+        }
+    })
+}
+
+#[test]
+fn cant_create_weighted_amm_with_more_than_two_assets() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(
+            TestPallet::create_amm(
+                Origin::signed(ALICE),
+                assets(&[DOT, USDC, KSM]),
+                DEFAULT_SHARE_ASSET,
+                DEFAULT_FEES_BPS,
+                Some(Curve::Weighted {
+                    base_weight: Perbill::from_percent(50)
+                }),
+            ),
+            Error::<Runtime>::InvalidCurve
+        );
+    })
+}
+
+#[test]
+fn weighted_amm_prices_assets_by_weight() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 100),
+            (USDC, ALICE, UNIT * 25),
+            (USDC, BOB, UNIT),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        weighted_amm();
+
+        let amm = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm.spot_price(0, 1, UNIT), Ok(UNIT));
+        assert_eq!(amm.spot_price(1, 0, UNIT), Ok(UNIT));
+
+        // out = 100 * (1 - (25 / (25 + 0.997)) ^ (0.2 / 0.8)) ~= 0.97287
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT, 0, None));
+        let output = <Assets as Inspect<AccountId>>::balance(DOT, &BOB);
+        assert!(output > UNIT * 97_286 / 100_000);
+        assert!(output < UNIT * 97_287 / 100_000);
+    })
+}
+
+#[test]
+fn weighted_amm_output_price_is_never_an_underestimate() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT * 100), (USDC, ALICE, UNIT * 25)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        weighted_amm();
+
+        for (asset_in, asset_out, amount) in [
+            (USDC, DOT, 1),
+            (USDC, DOT, UNIT / 3),
+            (USDC, DOT, UNIT * 5),
+            (DOT, USDC, 7),
+            (DOT, USDC, UNIT),
+            (DOT, USDC, UNIT * 5),
+        ] {
+            let input =
+                <TestPallet as SimulateSwap>::output_price_between(0, asset_in, asset_out, amount)
+                    .unwrap();
+            let output =
+                <TestPallet as SimulateSwap>::simulate_swap_between(0, asset_in, asset_out, input)
+                    .unwrap();
+            assert!(output >= amount);
+        }
+    })
+}
+
+#[test]
+fn weighted_amm_rejects_swaps_too_large_for_reserves() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 100),
+            (USDC, ALICE, UNIT * 25),
+            (USDC, BOB, UNIT * 20),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        weighted_amm();
+
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT * 20, 0, None),
+            Error::<Runtime>::SwapTooLarge
+        );
+        assert_noop!(
+            <TestPallet as SimulateSwap>::output_price_between(0, USDC, DOT, UNIT * 40),
+            Error::<Runtime>::SwapTooLarge
+        );
+    })
+}
+
+#[test]
+fn weighted_amm_deposits_and_withdrawals_are_proportional() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 100),
+            (USDC, ALICE, UNIT * 25),
+            (DOT, BOB, UNIT * 10),
+            (USDC, BOB, UNIT * 10),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        weighted_amm();
+
+        // Only 2 USDC are needed along with 8 DOT.
+        assert_ok!(TestPallet::provide_liquidity(
+            Origin::signed(BOB),
+            0,
+            amounts(&[UNIT * 8, UNIT * 10]),
+            0,
+            None,
+        ));
+        let shares = <Assets as Inspect<AccountId>>::balance(DEFAULT_SHARE_ASSET, &BOB);
+        assert_eq!(shares, UNIT * 4);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 2);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT * 8);
+
+        assert_ok!(TestPallet::withdraw(Origin::signed(BOB), 0, shares, amounts(&[0, 0]), None));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 10);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), UNIT * 10);
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Concentrated liquidity
+// -------------------------------------------------------------------------------------------------
+
+pub const DEFAULT_TICK_SPACING: u32 = 60;
+
+/// Sets up a concentrated liquidity DOT/USDC AMM with id 0, priced at 1 USDC per DOT.
+fn concentrated_amm() {
+    assert_ok!(TestPallet::create_concentrated_amm(
+        Origin::signed(ALICE),
+        [DOT, USDC],
+        DEFAULT_FEES_BPS,
+        DEFAULT_TICK_SPACING,
+        0,
+    ));
+}
+
+/// Sets up `concentrated_amm` with positions 0, 1 and 2 opened by ALICE with up to 100 units of
+/// each asset, respectively around the price, above it and below it.
+fn concentrated_amm_with_positions() {
+    concentrated_amm();
+    for (lower_tick, upper_tick) in [(-600, 600), (600, 1200), (-1200, -600)] {
+        assert_ok!(TestPallet::open_position(
+            Origin::signed(ALICE),
+            0,
+            lower_tick,
+            upper_tick,
+            [UNIT * 100, UNIT * 100],
+        ));
+    }
+}
+
+#[test]
+fn cant_create_concentrated_amm_with_invalid_parameters() {
+    ExtBuilder::default().build().execute_with(|| {
+        assert_noop!(
+            TestPallet::create_concentrated_amm(Origin::signed(ALICE), [DOT, DOT], 30, 60, 0),
+            Error::<Runtime>::InvalidAssets
+        );
+        assert_noop!(
+            TestPallet::create_concentrated_amm(Origin::signed(ALICE), [DOT, USDC], 30, 0, 0),
+            Error::<Runtime>::InvalidTicks
+        );
+        assert_noop!(
+            TestPallet::create_concentrated_amm(
+                Origin::signed(ALICE),
+                [DOT, USDC],
+                30,
+                60,
+                887_273
+            ),
+            Error::<Runtime>::InvalidTicks
+        );
+    })
+}
+
+#[test]
+fn concentrated_amm_shares_ids_with_other_amms() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm();
+        assert_ok!(TestPallet::create_concentrated_amm(
+            Origin::signed(ALICE),
+            [DOT, USDC],
+            5,
+            DEFAULT_TICK_SPACING,
+            0
+        ));
+
+        System::assert_last_event(Event::AmmCreated(1).into());
+        assert_eq!(TestPallet::amm_count(), 2);
+        assert!(TestPallet::amm_state(1).is_none());
+        assert_eq!(TestPallet::concentrated_amm(1).unwrap().assets, [DOT, USDC]);
+
+        assert_noop!(
+            TestPallet::open_position(Origin::signed(ALICE), 0, -600, 600, [UNIT, UNIT]),
+            Error::<Runtime>::InvalidAmmId
+        );
+        assert_noop!(
+            TestPallet::provide_liquidity(
+                Origin::signed(ALICE),
+                1,
+                amounts(&[UNIT, UNIT]),
+                0,
+                None,
+            ),
+            Error::<Runtime>::InvalidAmmId
+        );
+    })
+}
+
+#[test]
+fn open_position_deposits_assets_needed_within_range() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT * 300), (USDC, ALICE, UNIT * 300)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        concentrated_amm_with_positions();
+
+        // The price is in the middle of the first range, so both assets were needed equally.
+        // Only DOT is needed above the price and only USDC below it.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &ALICE), UNIT * 100);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &ALICE), UNIT * 100);
+
+        let position = TestPallet::position(2).unwrap();
+        assert_eq!(position.owner, ALICE);
+        assert_eq!((position.lower_tick, position.upper_tick), (-1200, -600));
+        System::assert_last_event(
+            Event::PositionOpened {
+                position_id: 2,
+                owner: ALICE,
+                amm_id: 0,
+                lower_tick: -1200,
+                upper_tick: -600,
+                liquidity: position.liquidity,
+                amounts: [0, UNIT * 100],
+            }
+            .into(),
+        );
+
+        // Only the position around the price is active.
+        let amm = TestPallet::concentrated_amm(0).unwrap();
+        assert_eq!(amm.liquidity, TestPallet::position(0).unwrap().liquidity);
+        assert_eq!(amm.ticks.len(), 4);
+        assert_eq!(TestPallet::position_count(), 3);
+    })
+}
+
+#[test]
+fn cant_open_position_with_invalid_ticks() {
+    ExtBuilder::default().build().execute_with(|| {
+        concentrated_amm();
+
+        for (lower_tick, upper_tick) in [(-30, 600), (600, 600), (600, -600), (-887_280, 600)] {
+            assert_noop!(
+                TestPallet::open_position(
+                    Origin::signed(ALICE),
+                    0,
+                    lower_tick,
+                    upper_tick,
+                    [UNIT, UNIT]
+                ),
+                Error::<Runtime>::InvalidTicks
+            );
+        }
+
+        assert_noop!(
+            TestPallet::open_position(Origin::signed(ALICE), 0, -600, 600, [0, 0]),
+            Error::<Runtime>::ZeroAmount
+        );
+    })
+}
+
+#[test]
+fn concentrated_amm_swaps_with_less_slippage() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 300),
+            (USDC, ALICE, UNIT * 300),
+            (USDC, BOB, UNIT),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        concentrated_amm_with_positions();
+
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT, 0, None));
+
+        // A constant product AMM holding 100 DOT and 100 USDC would only give ~0.98716 DOT.
+        let output = <Assets as Inspect<AccountId>>::balance(DOT, &BOB);
+        assert!(output > UNIT * 996 / 1000);
+        assert!(output < UNIT * 997 / 1000);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), 0);
+
+        System::assert_last_event(
+            Event::Swapped {
+                user: BOB,
+                amm_id: 0,
+                asset_in: USDC,
+                asset_out: DOT,
+                input_amount: UNIT,
+                output_amount: output,
+                lp_fee: UNIT * 3 / 1000,
+                protocol_fee: 0,
+            }
+            .into(),
+        );
+        assert!(TestPallet::concentrated_amm(0).unwrap().tick > 0);
+    })
+}
+
+#[test]
+fn concentrated_amm_swaps_across_ticks() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 300),
+            (USDC, ALICE, UNIT * 300),
+            (DOT, BOB, UNIT * 150),
+            (USDC, BOB, UNIT * 150),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        concentrated_amm_with_positions();
+
+        // Pushes the price into the range above, where only the second position is active.
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(BOB),
+            0,
+            USDC,
+            DOT,
+            UNIT * 150,
+            0,
+            None,
+        ));
+        let amm = TestPallet::concentrated_amm(0).unwrap();
+        assert!(amm.tick >= 600);
+        assert_eq!(amm.liquidity, TestPallet::position(1).unwrap().liquidity);
+
+        // And back into the first range.
+        assert_ok!(TestPallet::swap_between(
+            Origin::signed(BOB),
+            0,
+            DOT,
+            USDC,
+            UNIT * 150,
+            0,
+            None,
+        ));
+        let amm = TestPallet::concentrated_amm(0).unwrap();
+        assert!(amm.tick < 0 && amm.tick >= -600);
+        assert_eq!(amm.liquidity, TestPallet::position(0).unwrap().liquidity);
+    })
+}
+
+#[test]
+fn concentrated_amm_rejects_swaps_beyond_liquidity() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 300),
+            (USDC, ALICE, UNIT * 300),
+            (USDC, BOB, UNIT * 400),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        concentrated_amm_with_positions();
+
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT * 400, 0, None),
+            Error::<Runtime>::InsufficientLiquidity
+        );
+        assert_noop!(
+            <TestPallet as SimulateSwap>::output_price_between(0, USDC, DOT, UNIT * 200),
+            Error::<Runtime>::InsufficientLiquidity
+        );
+    })
+}
+
+#[test]
+fn concentrated_output_price_is_never_an_underestimate() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 300),
+            (USDC, ALICE, UNIT * 300),
+            (USDC, BOB, UNIT * 200),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        concentrated_amm_with_positions();
+
+        for (asset_in, asset_out) in [(USDC, DOT), (DOT, USDC)] {
+            for amount in [1, 7, UNIT / 3, UNIT, UNIT * 50, UNIT * 150] {
+                let input = <TestPallet as SimulateSwap>::output_price_between(
+                    0,
+                    asset_in,
+                    asset_out,
+                    amount,
+                )
+                .unwrap();
+                let output = <TestPallet as SimulateSwap>::simulate_swap_between(
+                    0,
+                    asset_in,
+                    asset_out,
+                    input,
+                )
+                .unwrap();
+                assert!(output >= amount);
+            }
+        }
+
+        let usdc_required =
+            <TestPallet as SimulateSwap>::output_price_between(0, USDC, DOT, UNIT * 150).unwrap();
+        assert_ok!(TestPallet::swap_exact_out(
+            Origin::signed(BOB),
+            0,
+            USDC,
+            DOT,
+            UNIT * 150,
+            usdc_required,
+            None,
+        ));
+        assert!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB) >= UNIT * 150);
+        assert_eq!(
+            <Assets as Inspect<AccountId>>::balance(USDC, &BOB),
+            UNIT * 200 - usdc_required
+        );
+    })
+}
+
+#[test]
+fn concentrated_positions_earn_fees_within_range() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 100),
+            (USDC, ALICE, UNIT * 100),
+            (DOT, CHARLIE, UNIT * 100),
+            (USDC, BOB, UNIT * 10),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        concentrated_amm();
+        assert_ok!(TestPallet::open_position(
+            Origin::signed(ALICE),
+            0,
+            -600,
+            600,
+            [UNIT * 100, UNIT * 100],
+        ));
+        assert_ok!(TestPallet::open_position(
+            Origin::signed(CHARLIE),
+            0,
+            600,
+            1200,
+            [UNIT * 100, UNIT * 100],
+        ));
+
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT * 10, 0, None));
+
+        // The whole 0.3% fee goes to ALICE, minus rounding.
+        assert_ok!(TestPallet::collect_fees(Origin::signed(ALICE), 0));
+        let fees = <Assets as Inspect<AccountId>>::balance(USDC, &ALICE);
+        assert!(fees >= UNIT * 10 * 30 / 10_000 - 2);
+        assert!(fees <= UNIT * 10 * 30 / 10_000);
+        System::assert_last_event(
+            Event::FeesCollected {
+                position_id: 0,
+                owner: ALICE,
+                amounts: [0, fees],
+            }
+            .into(),
+        );
+
+        // Fees are only paid once.
+        assert_ok!(TestPallet::collect_fees(Origin::signed(ALICE), 0));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &ALICE), fees);
+
+        // CHARLIE's range was never active.
+        assert_ok!(TestPallet::collect_fees(Origin::signed(CHARLIE), 1));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &CHARLIE), 0);
+    })
+}
+
+#[test]
+fn concentrated_positions_earn_fees_minus_protocol_fee() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 100),
+            (USDC, ALICE, UNIT * 100),
+            (USDC, BOB, UNIT),
+        ],
+        protocol_fee: Permill::from_percent(20),
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        concentrated_amm();
+        assert_ok!(TestPallet::open_position(
+            Origin::signed(ALICE),
+            0,
+            -600,
+            600,
+            [UNIT * 100, UNIT * 100],
+        ));
+
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT, 0, None));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &TREASURY), 600);
+
+        // ALICE only earns the remaining 2_400, minus rounding.
+        assert_ok!(TestPallet::collect_fees(Origin::signed(ALICE), 0));
+        let fees = <Assets as Inspect<AccountId>>::balance(USDC, &ALICE);
+        assert!(fees >= 2_398);
+        assert!(fees <= 2_400);
+    })
+}
+
+#[test]
+fn close_position_returns_assets_and_fees() {
+    ExtBuilder {
+        accounts: vec![
+            (DOT, ALICE, UNIT * 100),
+            (USDC, ALICE, UNIT * 100),
+            (USDC, BOB, UNIT * 10),
+        ],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        concentrated_amm();
+        assert_ok!(TestPallet::open_position(
+            Origin::signed(ALICE),
+            0,
+            -600,
+            600,
+            [UNIT * 100, UNIT * 100],
+        ));
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT * 10, 0, None));
+        let output = <Assets as Inspect<AccountId>>::balance(DOT, &BOB);
+
+        assert_ok!(TestPallet::close_position(Origin::signed(ALICE), 0));
+
+        // ALICE gets back the pool's assets, including BOB's input and fees, minus rounding.
+        let dot = <Assets as Inspect<AccountId>>::balance(DOT, &ALICE);
+        let usdc = <Assets as Inspect<AccountId>>::balance(USDC, &ALICE);
+        assert!(dot >= UNIT * 100 - output - 2 && dot <= UNIT * 100 - output);
+        assert!(usdc >= UNIT * 110 - 3 && usdc <= UNIT * 110);
+        System::assert_last_event(
+            Event::PositionClosed {
+                position_id: 0,
+                owner: ALICE,
+                amounts: [dot, usdc],
+            }
+            .into(),
+        );
+
+        assert!(TestPallet::position(0).is_none());
+        let amm = TestPallet::concentrated_amm(0).unwrap();
+        assert_eq!(amm.liquidity, 0);
+        assert!(amm.ticks.is_empty());
+    })
+}
+
+#[test]
+fn only_owner_can_operate_position() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT * 100), (USDC, ALICE, UNIT * 100)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        run_to_block(1);
+
+        concentrated_amm();
+        assert_ok!(TestPallet::open_position(
+            Origin::signed(ALICE),
+            0,
+            -600,
+            600,
+            [UNIT * 100, UNIT * 100],
+        ));
+
+        assert_noop!(
+            TestPallet::collect_fees(Origin::signed(BOB), 0),
+            Error::<Runtime>::NotPositionOwner
+        );
+        assert_noop!(
+            TestPallet::close_position(Origin::signed(BOB), 0),
+            Error::<Runtime>::NotPositionOwner
+        );
+        assert_noop!(
+            TestPallet::transfer_position(Origin::signed(BOB), 0, BOB),
+            Error::<Runtime>::NotPositionOwner
+        );
+        assert_noop!(
+            TestPallet::close_position(Origin::signed(ALICE), 1),
+            Error::<Runtime>::InvalidPositionId
+        );
+
+        assert_ok!(TestPallet::transfer_position(Origin::signed(ALICE), 0, BOB));
+        System::assert_last_event(
+            Event::PositionTransferred {
+                position_id: 0,
+                from: ALICE,
+                to: BOB,
+            }
+            .into(),
+        );
+
+        assert_noop!(
+            TestPallet::close_position(Origin::signed(ALICE), 0),
+            Error::<Runtime>::NotPositionOwner
+        );
+        assert_ok!(TestPallet::close_position(Origin::signed(BOB), 0));
+        assert!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB) >= UNIT * 100 - 1);
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Pair index
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn only_one_amm_per_pair_and_fee_tier() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        assert_noop!(
+            TestPallet::create_amm(
+                Origin::signed(ALICE),
+                assets(&[USDC, DOT]),
+                DEFAULT_SHARE_ASSET + 1,
+                DEFAULT_FEES_BPS,
+                Some(Curve::StableSwap {
+                    amplification: DEFAULT_AMPLIFICATION
+                }),
+            ),
+            Error::<Runtime>::PairAlreadyExists
+        );
+        assert_noop!(
+            TestPallet::create_concentrated_amm(
+                Origin::signed(ALICE),
+                [USDC, DOT],
+                DEFAULT_FEES_BPS,
+                DEFAULT_TICK_SPACING,
+                0
+            ),
+            Error::<Runtime>::PairAlreadyExists
+        );
+
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[USDC, DOT]),
+            DEFAULT_SHARE_ASSET + 1,
+            STABLE_FEES_BPS,
+            Some(Curve::StableSwap {
+                amplification: DEFAULT_AMPLIFICATION
+            }),
+        ));
+        // Pools of more than two assets are not indexed.
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[DOT, USDC, KSM]),
+            DEFAULT_SHARE_ASSET + 2,
+            DEFAULT_FEES_BPS,
+            Some(Curve::StableSwap {
+                amplification: DEFAULT_AMPLIFICATION
+            }),
+        ));
+
+        assert_eq!(TestPallet::pair_amm(DOT, USDC, DEFAULT_FEES_BPS), Some(0));
+        assert_eq!(TestPallet::pair_amm(USDC, DOT, DEFAULT_FEES_BPS), Some(0));
+        assert_eq!(TestPallet::pair_amm(DOT, USDC, STABLE_FEES_BPS), Some(1));
+        assert_eq!(TestPallet::pair_amm(DOT, KSM, DEFAULT_FEES_BPS), None);
+    })
+}
+
+#[test]
+fn set_fees_moves_amm_to_another_fee_tier() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+        assert_ok!(TestPallet::create_concentrated_amm(
+            Origin::signed(ALICE),
+            [DOT, USDC],
+            5,
+            DEFAULT_TICK_SPACING,
+            0
+        ));
+
+        assert_noop!(
+            TestPallet::set_fees(Origin::root(), 0, 5),
+            Error::<Runtime>::PairAlreadyExists
+        );
+
+        assert_ok!(TestPallet::set_fees(Origin::root(), 0, 100));
+        assert_eq!(TestPallet::pair_amm(DOT, USDC, DEFAULT_FEES_BPS), None);
+        assert_eq!(TestPallet::pair_amm(DOT, USDC, 100), Some(0));
+
+        // The previous fee tier is free for a new AMM.
+        assert_ok!(TestPallet::create_amm(
+            Origin::signed(ALICE),
+            assets(&[DOT, USDC]),
+            DEFAULT_SHARE_ASSET + 1,
+            DEFAULT_FEES_BPS,
+            None,
+        ));
+        assert_eq!(TestPallet::pair_amm(DOT, USDC, DEFAULT_FEES_BPS), Some(2));
+    })
+}
+
+#[test]
+fn swap_pair_swaps_against_amm_of_pair() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+
+        assert_noop!(
+            TestPallet::swap_pair(Origin::signed(BOB), USDC, DOT, 100, UNIT, 0, None),
+            Error::<Runtime>::UnknownPair
+        );
+        assert_eq!(
+            TestPallet::quote_pair(USDC, DOT, 100, UNIT),
+            Err(Error::<Runtime>::UnknownPair.into())
+        );
+
+        let output = TestPallet::quote_pair(USDC, DOT, DEFAULT_FEES_BPS, UNIT).unwrap();
+        assert_eq!(
+            output,
+            <TestPallet as SimulateSwap>::simulate_swap_between(0, USDC, DOT, UNIT).unwrap()
+        );
+
+        assert_ok!(TestPallet::swap_pair(
+            Origin::signed(BOB),
+            USDC,
+            DOT,
+            DEFAULT_FEES_BPS,
+            UNIT,
+            output,
+            None,
+        ));
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &BOB), 0);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &BOB), UNIT * 100 + output);
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Destruction
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn creator_can_destroy_empty_amm() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm_with_liquidity();
+
+        assert_noop!(
+            TestPallet::destroy_amm(Origin::signed(ALICE), 1),
+            Error::<Runtime>::InvalidAmmId
+        );
+        assert_noop!(
+            TestPallet::destroy_amm(Origin::signed(ALICE), 0),
+            Error::<Runtime>::AmmNotEmpty
+        );
+
+        let shares = UNIT * 10 - MINIMUM_LIQUIDITY;
+        assert_ok!(TestPallet::withdraw(Origin::signed(ALICE), 0, shares, amounts(&[0, 0]), None));
+        assert_noop!(
+            TestPallet::destroy_amm(Origin::signed(BOB), 0),
+            Error::<Runtime>::NotAmmCreator
+        );
+
+        assert_ok!(TestPallet::destroy_amm(Origin::signed(ALICE), 0));
+        System::assert_last_event(Event::AmmDestroyed { amm_id: 0 }.into());
+
+        // The assets backing the locked shares go to the protocol.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(DOT, &TREASURY), 100);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &TREASURY), 10_000);
+
+        assert!(TestPallet::amm_state(0).is_none());
+        assert!(TestPallet::pool_deposit(0).is_none());
+        assert_eq!(TestPallet::pair_amm(DOT, USDC, DEFAULT_FEES_BPS), None);
+        assert_eq!(Balances::free_balance(ALICE), NATIVE_BALANCE);
+        assert_eq!(Balances::free_balance(TestPallet::amm_account(&0)), 0);
+        assert_eq!(Balances::reserved_balance(TestPallet::amm_account(&0)), 0);
+        assert_eq!(Balances::reserved_balance(ALICE), 0);
+
+        // Both the pair and the share asset can be used again.
+        default_amm();
+        assert_eq!(TestPallet::pair_amm(DOT, USDC, DEFAULT_FEES_BPS), Some(1));
+    })
+}
+
+#[test]
+fn destroy_amm_sweeps_donations() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm_with_liquidity();
+        let shares = UNIT * 10 - MINIMUM_LIQUIDITY;
+        assert_ok!(TestPallet::withdraw(Origin::signed(ALICE), 0, shares, amounts(&[0, 0]), None));
+
+        // Holding an asset that is not sufficient takes a consumer reference on the AMM's account,
+        // which then can't be reaped.
+        let donated_asset = 3;
+        assert_ok!(Assets::force_create(Origin::root(), donated_asset, ALICE, false, 1));
+        assert_ok!(Assets::mint(Origin::signed(ALICE), donated_asset, BOB, UNIT));
+        let amm_account = TestPallet::amm_account(&0);
+        assert_ok!(Assets::transfer(Origin::signed(BOB), donated_asset, amm_account, UNIT));
+        assert_ok!(Assets::transfer(Origin::signed(BOB), USDC, amm_account, UNIT));
+
+        assert_ok!(TestPallet::destroy_amm(Origin::signed(ALICE), 0));
+
+        // Donations of pooled assets go to the protocol along with the reserves.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &TREASURY), 10_000 + UNIT);
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(USDC, &amm_account), 0);
+
+        // The AMM's account is kept alive by the other donation, with its existential deposit.
+        assert_eq!(<Assets as Inspect<AccountId>>::balance(donated_asset, &amm_account), UNIT);
+        assert_eq!(Balances::free_balance(amm_account), 1);
+        assert_eq!(Balances::free_balance(ALICE), NATIVE_BALANCE - 1);
+        assert_eq!(Balances::reserved_balance(ALICE), 0);
+    })
+}
+
+#[test]
+fn admin_can_destroy_concentrated_amm_without_positions() {
+    ExtBuilder::default().build().execute_with(|| {
+        concentrated_amm();
+        assert_ok!(TestPallet::open_position(Origin::signed(ALICE), 0, -600, 600, [UNIT, UNIT]));
+
+        assert_noop!(TestPallet::destroy_amm(Origin::root(), 0), Error::<Runtime>::AmmNotEmpty);
+
+        assert_ok!(TestPallet::close_position(Origin::signed(ALICE), 0));
+        assert_ok!(TestPallet::destroy_amm(Origin::root(), 0));
+
+        assert!(TestPallet::concentrated_amm(0).is_none());
+        assert_eq!(Balances::reserved_balance(ALICE), 0);
+        assert_noop!(TestPallet::destroy_amm(Origin::root(), 0), Error::<Runtime>::InvalidAmmId);
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Administration
+// -------------------------------------------------------------------------------------------------
+
+/// Sets up the default AMM with 1 DOT and 100 USDC provided by ALICE.
+fn default_amm_with_liquidity() {
+    default_amm();
+    assert_ok!(TestPallet::provide_liquidity(
+        Origin::signed(ALICE),
+        0,
+        amounts(&[UNIT, UNIT * 100]),
+        0,
+        None,
+    ));
+}
+
+#[test]
+fn only_admin_origin_can_administer() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+
+        assert_noop!(TestPallet::set_fees(Origin::signed(ALICE), 0, 10), BadOrigin);
+        assert_noop!(TestPallet::set_amplification(Origin::signed(ALICE), 0, 10), BadOrigin);
+        assert_noop!(TestPallet::pause_amm(Origin::signed(ALICE), 0), BadOrigin);
+        assert_noop!(TestPallet::unpause_amm(Origin::signed(ALICE), 0), BadOrigin);
+        assert_noop!(TestPallet::set_batch_mode(Origin::signed(ALICE), 0, true), BadOrigin);
+        assert_noop!(
+            TestPallet::create_farm(Origin::signed(ALICE), 0, KSM, UNIT, 1, 101),
+            BadOrigin
+        );
+        assert_noop!(TestPallet::set_farm_rewards(Origin::signed(ALICE), 0, UNIT, 101), BadOrigin);
+        assert_noop!(TestPallet::end_farm(Origin::signed(ALICE), 0), BadOrigin);
+        assert_noop!(TestPallet::freeze(Origin::signed(ALICE)), BadOrigin);
+        assert_noop!(TestPallet::unfreeze(Origin::signed(ALICE)), BadOrigin);
+    })
+}
+
+#[test]
+fn admin_can_update_fees() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::create_concentrated_amm(
+            Origin::signed(ALICE),
+            [DOT, USDC],
+            5,
+            DEFAULT_TICK_SPACING,
+            0
+        ));
+
+        assert_noop!(
+            TestPallet::set_fees(Origin::root(), 0, 10_000),
+            Error::<Runtime>::InvalidFees
+        );
+        assert_noop!(
+            TestPallet::set_fees(Origin::root(), 2, 10),
+            Error::<Runtime>::InvalidAmmId
+        );
+
+        let quote =
+            || <TestPallet as SimulateSwap>::simulate_swap_between(0, USDC, DOT, UNIT).unwrap();
+        let output_before = quote();
+        assert_ok!(TestPallet::set_fees(Origin::root(), 0, 100));
+        System::assert_last_event(
+            Event::FeesUpdated {
+                amm_id: 0,
+                fees_bps: 100,
+            }
+            .into(),
+        );
+        assert_eq!(TestPallet::amm_state(0).unwrap().fees_bps, 100);
+        assert!(quote() < output_before);
+
+        assert_ok!(TestPallet::set_fees(Origin::root(), 1, 10));
+        assert_eq!(TestPallet::concentrated_amm(1).unwrap().fees_bps, 10);
+    })
+}
+
+#[test]
+fn admin_can_update_stable_swap_amplification() {
+    ExtBuilder {
+        accounts: vec![(DOT, ALICE, UNIT * 200), (USDC, ALICE, UNIT * 200)],
+        ..Default::default()
+    }
+    .build()
+    .execute_with(|| {
+        constant_product_and_stable_amms();
+
+        assert_noop!(
+            TestPallet::set_amplification(Origin::root(), 0, 10),
+            Error::<Runtime>::InvalidCurve
+        );
+        assert_noop!(
+            TestPallet::set_amplification(Origin::root(), 1, 0),
+            Error::<Runtime>::InvalidCurve
+        );
+
+        assert_ok!(TestPallet::set_amplification(Origin::root(), 1, 10));
+        assert_eq!(
+            TestPallet::amm_state(1).unwrap().curve,
+            Curve::StableSwap { amplification: 10 }
+        );
+    })
+}
+
+#[test]
+fn paused_amm_only_allows_withdrawals() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm_with_liquidity();
+
+        assert_noop!(
+            TestPallet::pause_amm(Origin::root(), 1),
+            Error::<Runtime>::InvalidAmmId
+        );
+        assert_ok!(TestPallet::pause_amm(Origin::root(), 0));
+        System::assert_last_event(Event::AmmPaused { amm_id: 0 }.into());
+
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT, 0, None),
+            Error::<Runtime>::AmmIsPaused
+        );
+        assert_noop!(
+            TestPallet::provide_liquidity(Origin::signed(BOB), 0, amounts(&[UNIT, UNIT]), 0, None),
+            Error::<Runtime>::AmmIsPaused
+        );
+        assert_noop!(
+            TestPallet::provide_liquidity_single(Origin::signed(BOB), 0, DOT, UNIT, 0, None),
+            Error::<Runtime>::AmmIsPaused
+        );
+        assert_ok!(TestPallet::withdraw(
+            Origin::signed(ALICE),
+            0,
+            UNIT * 5,
+            amounts(&[0, 0]),
+            None,
+        ));
+
+        assert_ok!(TestPallet::unpause_amm(Origin::root(), 0));
+        System::assert_last_event(Event::AmmUnpaused { amm_id: 0 }.into());
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT, 0, None));
+    })
+}
+
+#[test]
+fn frozen_pallet_only_allows_withdrawals() {
+    ExtBuilder::default().build().execute_with(|| {
+        run_to_block(1);
+
+        default_amm_with_liquidity();
+        assert_ok!(TestPallet::create_concentrated_amm(
+            Origin::signed(ALICE),
+            [DOT, USDC],
+            5,
+            DEFAULT_TICK_SPACING,
+            0
+        ));
+
+        assert_ok!(TestPallet::freeze(Origin::root()));
+        System::assert_last_event(Event::Frozen.into());
+
+        assert_noop!(
+            TestPallet::create_amm(
+                Origin::signed(ALICE),
+                assets(&[DOT, KSM]),
+                DEFAULT_SHARE_ASSET + 1,
+                DEFAULT_FEES_BPS,
+                None,
+            ),
+            Error::<Runtime>::PalletIsFrozen
+        );
+        assert_noop!(
+            TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT, 0, None),
+            Error::<Runtime>::PalletIsFrozen
+        );
+        assert_noop!(
+            TestPallet::open_position(Origin::signed(BOB), 1, -600, 600, [UNIT, UNIT]),
+            Error::<Runtime>::PalletIsFrozen
+        );
+        assert_ok!(TestPallet::withdraw(
+            Origin::signed(ALICE),
+            0,
+            UNIT * 5,
+            amounts(&[0, 0]),
+            None,
+        ));
+
+        assert_ok!(TestPallet::unfreeze(Origin::root()));
+        System::assert_last_event(Event::Unfrozen.into());
+        assert_ok!(TestPallet::swap_between(Origin::signed(BOB), 0, USDC, DOT, UNIT, 0, None));
+    })
+}
+
+// -------------------------------------------------------------------------------------------------
+//                                          Migrations
+// -------------------------------------------------------------------------------------------------
+
+#[test]
+fn migration_to_v1_converts_pairs_to_pools() {
+    ExtBuilder::default().build().execute_with(|| {
+        // Base asset, base reserves, quote asset, quote reserves, share asset, total shares and
+        // fees, as encoded before version 1.
+        frame_support::storage::unhashed::put(
+            &AmmStates::<Runtime>::hashed_key_for(0),
+            &(DOT, UNIT, USDC, UNIT * 100, DEFAULT_SHARE_ASSET, UNIT * 100, DEFAULT_FEES_BPS),
+        );
+        StorageVersion::new(0).put::<TestPallet>();
+
+        MigrateToV1::<Runtime>::on_runtime_upgrade();
+
+        assert_eq!(StorageVersion::get::<TestPallet>(), StorageVersion::new(1));
+
+        let amm_state = TestPallet::amm_state(0).unwrap();
+        assert_eq!(amm_state.assets.into_inner(), vec![DOT, USDC]);
+        assert_eq!(amm_state.reserves.into_inner(), vec![UNIT, UNIT * 100]);
+        assert_eq!(amm_state.share_asset, DEFAULT_SHARE_ASSET);
+        assert_eq!(amm_state.total_shares, UNIT * 100);
+        assert_eq!(amm_state.curve, Curve::ConstantProduct);
+    })
+}
+
+#[test]
+fn migration_to_v2_indexes_pairs() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+        assert_ok!(TestPallet::create_concentrated_amm(
+            Origin::signed(ALICE),
+            [USDC, DOT],
+            5,
+            DEFAULT_TICK_SPACING,
+            0
+        ));
+        // As before version 2, when AMMs were not indexed.
+        Pairs::<Runtime>::remove((DOT, USDC, DEFAULT_FEES_BPS));
+        Pairs::<Runtime>::remove((DOT, USDC, 5));
+        StorageVersion::new(1).put::<TestPallet>();
+
+        MigrateToV2::<Runtime>::on_runtime_upgrade();
+
+        assert_eq!(StorageVersion::get::<TestPallet>(), StorageVersion::new(2));
+        assert_eq!(TestPallet::pair_amm(DOT, USDC, DEFAULT_FEES_BPS), Some(0));
+        assert_eq!(TestPallet::pair_amm(USDC, DOT, 5), Some(1));
+        assert_eq!(Pairs::<Runtime>::iter().count(), 2);
+    })
+}
+
+#[test]
+fn migration_to_v2_waits_for_version_1() {
+    ExtBuilder::default().build().execute_with(|| {
+        default_amm();
+        Pairs::<Runtime>::remove((DOT, USDC, DEFAULT_FEES_BPS));
+        // As if `MigrateToV1` bailed out and left the AMMs in their old layout.
+        StorageVersion::new(0).put::<TestPallet>();
+
+        MigrateToV2::<Runtime>::on_runtime_upgrade();
+
+        assert_eq!(StorageVersion::get::<TestPallet>(), StorageVersion::new(0));
+        assert_eq!(Pairs::<Runtime>::iter().count(), 0);
+    })
+}
@@ -1,29 +1,133 @@
-use sp_runtime::DispatchError;
+use sp_runtime::{DispatchError, DispatchResult};
 
-/// For querying results of swaps without modifying storage. Doubles as a price oracle.
+/// For querying results of swaps without modifying storage.
+///
+/// Results are based on the current reserves, which can be moved by anyone within a single block.
+/// Use `PriceOracle` where a manipulation-resistant price is needed.
 pub trait SimulateSwap {
     type AmmId;
+    type AssetId;
     type AssetType;
     type Balance;
 
     /// Compute the amount of the opposite asset one would get if sending `amount` of `asset_type`
-    /// to the AMM corresponding to `amm_id`.
-    ///
-    /// Takes slippage and fees into account, yielding the net amount of asset that would be
-    /// returned by the swap operation.
+    /// to the AMM of two assets corresponding to `amm_id`. See `simulate_swap_between`.
     fn simulate_swap(
         amm_id: Self::AmmId,
         asset_type: Self::AssetType,
         amount: Self::Balance,
     ) -> Result<Self::Balance, DispatchError>;
 
-    /// Compute the amount of input asset one would need to get `amount` of `asset_type` back.
-    ///
-    /// Takes slippage and fees into account. Price may be an underestimate due to rounding errors
-    /// in integer division.
+    /// Compute the amount of the opposite asset one would need to get `amount` of `asset_type`
+    /// back from the AMM of two assets corresponding to `amm_id`. See `output_price_between`.
     fn output_price(
         amm_id: Self::AmmId,
         asset_type: Self::AssetType,
         amount: Self::Balance,
     ) -> Result<Self::Balance, DispatchError>;
+
+    /// Compute the amount of `asset_out` one would get if sending `amount` of `asset_in` to the
+    /// AMM corresponding to `amm_id`.
+    ///
+    /// Takes slippage and fees into account, yielding the net amount of asset that would be
+    /// returned by the swap operation.
+    fn simulate_swap_between(
+        amm_id: Self::AmmId,
+        asset_in: Self::AssetId,
+        asset_out: Self::AssetId,
+        amount: Self::Balance,
+    ) -> Result<Self::Balance, DispatchError>;
+
+    /// Compute the amount of `asset_in` one would need to get `amount` of `asset_out` back.
+    ///
+    /// Takes slippage and fees into account. Integer divisions are rounded up, so sending the
+    /// returned amount to `simulate_swap_between` yields at least `amount`.
+    fn output_price_between(
+        amm_id: Self::AmmId,
+        asset_in: Self::AssetId,
+        asset_out: Self::AssetId,
+        amount: Self::Balance,
+    ) -> Result<Self::Balance, DispatchError>;
+}
+
+/// For executing swaps on behalf of an account, from other pallets.
+///
+/// Swaps go through the same checks and emit the same events as the corresponding extrinsics,
+/// and are reverted entirely if they fail. Deadlines are left to the caller, and AMMs in batch
+/// mode are rejected since their swaps only clear at the end of the block.
+pub trait ExecuteSwap {
+    type AccountId;
+    type AmmId;
+    type AssetId;
+    type Balance;
+
+    /// Send `input_amount` of `asset_in` from `who` to the AMM corresponding to `amm_id`, for at
+    /// least `output_min` of `asset_out`. Returns the amount of `asset_out` received.
+    fn swap_exact_in(
+        who: &Self::AccountId,
+        amm_id: Self::AmmId,
+        asset_in: Self::AssetId,
+        asset_out: Self::AssetId,
+        input_amount: Self::Balance,
+        output_min: Self::Balance,
+    ) -> Result<Self::Balance, DispatchError>;
+
+    /// Get at least `output_amount` of `asset_out` from the AMM corresponding to `amm_id` for
+    /// `who`, sending at most `max_input` of `asset_in`. Returns the amount of `asset_in` sent.
+    fn swap_exact_out(
+        who: &Self::AccountId,
+        amm_id: Self::AmmId,
+        asset_in: Self::AssetId,
+        asset_out: Self::AssetId,
+        output_amount: Self::Balance,
+        max_input: Self::Balance,
+    ) -> Result<Self::Balance, DispatchError>;
+
+    /// Send `input_amount` of `asset_in` from `who` through a sequence of AMMs, each hop given by
+    /// the id of the AMM and the asset to get out of it, for at least `output_min` of the final
+    /// asset. Returns the amount of the final asset received.
+    fn swap_route(
+        who: &Self::AccountId,
+        asset_in: Self::AssetId,
+        route: &[(Self::AmmId, Self::AssetId)],
+        input_amount: Self::Balance,
+        output_min: Self::Balance,
+    ) -> Result<Self::Balance, DispatchError>;
+}
+
+/// Time-weighted average prices, resistant to manipulation within a single block.
+pub trait PriceOracle {
+    type AmmId;
+    type AssetId;
+    type Balance;
+    type BlockNumber;
+
+    /// Compute the average price of `asset` over the last `window` blocks of the AMM
+    /// corresponding to `amm_id`. Prices are given in terms of the first asset of the pool, or
+    /// in terms of the second one for the first asset itself.
+    ///
+    /// Prices are scaled by one unit of the AMM's default decimals. Swaps in the current block do
+    /// not affect the result.
+    fn twap(
+        amm_id: Self::AmmId,
+        asset: Self::AssetId,
+        window: Self::BlockNumber,
+    ) -> Result<Self::Balance, DispatchError>;
+}
+
+/// Hook into flash swaps, which lend an AMM's assets for the duration of a single call.
+pub trait FlashBorrower<AccountId, AmmId, Balance> {
+    /// Called once `borrower` received `amounts` of each asset of the AMM `amm_id`, in the order
+    /// given at its creation.
+    ///
+    /// Before returning, enough assets must be sent back to the AMM's account for its invariant,
+    /// net of the swap fees charged on them, not to decrease. Otherwise the whole flash swap is
+    /// reverted.
+    fn on_flash_swap(borrower: &AccountId, amm_id: &AmmId, amounts: &[Balance]) -> DispatchResult;
+}
+
+impl<AccountId, AmmId, Balance> FlashBorrower<AccountId, AmmId, Balance> for () {
+    fn on_flash_swap(_: &AccountId, _: &AmmId, _: &[Balance]) -> DispatchResult {
+        Ok(())
+    }
 }
@@ -1,35 +1,410 @@
-use crate::{helpers::TryMul, Config};
-use frame_support::pallet_prelude::{Decode, Encode, MaxEncodedLen, TypeInfo};
-use sp_runtime::{traits::Zero, ArithmeticError};
+use crate::{
+    curves::{constant_product, stable_swap},
+    helpers::{from_u256, wrapping_add, wrapping_mul},
+    Config,
+};
+use frame_support::{
+    pallet_prelude::{BoundedVec, Decode, Encode, MaxEncodedLen, TypeInfo},
+    traits::Currency,
+    CloneNoBound,
+};
+use sp_core::{U256, U512};
+use sp_runtime::{
+    traits::{Saturating, Zero},
+    ArithmeticError, DispatchError, Perbill, SaturatedConversion,
+};
+use sp_std::vec::Vec;
+
+#[cfg(feature = "std")]
+use serde::{Deserialize, Serialize};
 
 /// The state of a particular AMM.
 #[derive(Encode, Decode, MaxEncodedLen, TypeInfo)]
 #[scale_info(skip_type_params(T))]
 #[codec(mel_bound())]
 pub struct Amm<T: Config> {
-    pub base_asset: T::AssetId,
-    pub base_reserves: T::Balance,
-    pub quote_asset: T::AssetId,
-    pub quote_reserves: T::Balance,
+    /// Ids of the assets in the pool, in the order their reserves are kept.
+    pub assets: BoundedVec<T::AssetId, T::MaxAssetsPerPool>,
+    /// Reserves of each of `assets`, in the same order.
+    pub reserves: BoundedVec<T::Balance, T::MaxAssetsPerPool>,
     pub share_asset: T::AssetId,
     pub total_shares: T::Balance,
     pub fees_bps: T::Balance,
+    pub curve: Curve,
 }
 
 impl<T: Config> Amm<T> {
-    /// Compute the invariant for this AMM, i.e., the `K` in `x * y = K`.
-    pub fn get_k(&self) -> Result<T::Balance, ArithmeticError> {
-        self.base_reserves.try_mul(&self.quote_reserves)
+    /// Compute the constant product invariant for this AMM, i.e., the `K` in `x * y = K`,
+    /// generalised to the product of all of its reserves.
+    pub fn get_k(&self) -> Result<U512, ArithmeticError> {
+        constant_product::invariant(&self.reserves_u256())
+    }
+
+    /// Whether the AMM holds some of each of its assets.
+    pub fn is_initialized(&self) -> bool {
+        !self.reserves.is_empty() && self.reserves.iter().all(|reserve| !reserve.is_zero())
+    }
+
+    /// The position of `asset` in the pool, if the AMM holds it.
+    pub fn asset_index(&self, asset: &T::AssetId) -> Option<usize> {
+        self.assets.iter().position(|a| a == asset)
+    }
+
+    /// The positions of the input and output assets of a swap, if the AMM holds both and they
+    /// are different.
+    pub fn swap_indices(
+        &self,
+        asset_in: &T::AssetId,
+        asset_out: &T::AssetId,
+    ) -> Option<(usize, usize)> {
+        let indices = (self.asset_index(asset_in)?, self.asset_index(asset_out)?);
+        (indices.0 != indices.1).then(|| indices)
+    }
+
+    /// The reserves of every asset in the pool, widened for curve math.
+    pub fn reserves_u256(&self) -> Vec<U256> {
+        self.reserves.iter().map(|reserve| (*reserve).into()).collect()
+    }
+
+    /// The weight of the asset at `index`, in parts per billion for weighted AMMs. All assets
+    /// weigh the same unless the AMM uses a weighted curve, which only holds two assets.
+    pub fn weight(&self, index: usize) -> u32 {
+        match self.curve {
+            Curve::Weighted { base_weight } if index == 0 => base_weight.deconstruct(),
+            Curve::Weighted { base_weight } => base_weight.left_from_one().deconstruct(),
+            _ => 1,
+        }
     }
 
-    pub fn is_initialized(&self) -> Result<bool, ArithmeticError> {
-        Ok(!self.get_k()?.is_zero())
+    /// The asset each asset of the pool is priced in by `spot_price` and price observations: the
+    /// first asset for every other one, and the second asset for the first one.
+    pub fn reference_index(index: usize) -> usize {
+        if index == 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Spot price of the asset at `index` in terms of the asset at `quote_index`, scaled by
+    /// `unit`. Zero if the AMM has no liquidity.
+    ///
+    /// StableSwap AMMs are priced at the margin of their invariant, which stays close to one near
+    /// the balanced point however the reserves compare. Other AMMs are priced by the ratio of
+    /// their weighted reserves.
+    pub fn spot_price(
+        &self,
+        index: usize,
+        quote_index: usize,
+        unit: T::Balance,
+    ) -> Result<T::Balance, DispatchError> {
+        if !self.is_initialized() {
+            return Ok(Zero::zero());
+        }
+        if let Curve::StableSwap { amplification } = self.curve {
+            let reserves = self.reserves_u256();
+            let price =
+                stable_swap::spot_price(&reserves, index, quote_index, amplification, unit.into())?;
+            return Ok(from_u256(price)?);
+        }
+
+        // price = (quote_reserves / quote_weight) / (reserves / weight)
+        let reserves: U256 = self.reserves[index].into();
+        let quote_reserves: U256 = self.reserves[quote_index].into();
+        let numerator = quote_reserves
+            .checked_mul(unit.into())
+            .and_then(|n| n.checked_mul(self.weight(index).into()))
+            .ok_or(ArithmeticError::Overflow)?;
+        let denominator = reserves
+            .checked_mul(self.weight(quote_index).into())
+            .ok_or(ArithmeticError::Overflow)?;
+
+        Ok(from_u256(numerator.checked_div(denominator).ok_or(ArithmeticError::DivisionByZero)?)?)
     }
 }
 
-/// For indicating the input to swaps
+/// For indicating the input to swaps against AMMs of two assets, the base asset being the first
+/// one given at their creation and the quote asset the second.
 #[derive(Clone, Copy, Debug, Decode, Encode, MaxEncodedLen, PartialEq, Eq, TypeInfo)]
 pub enum AssetType {
     Base,
     Quote,
 }
+
+impl AssetType {
+    /// Order the `base` and `quote` assets as the input and output of a swap of this type.
+    pub fn order<A>(self, base: A, quote: A) -> (A, A) {
+        match self {
+            Self::Base => (base, quote),
+            Self::Quote => (quote, base),
+        }
+    }
+}
+
+/// The bonding curve an AMM prices swaps with.
+#[derive(Clone, Copy, Debug, Decode, Encode, MaxEncodedLen, PartialEq, Eq, TypeInfo)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub enum Curve {
+    /// Uniswap-style `x * y = K`.
+    ConstantProduct,
+    /// Curve-style StableSwap for assets expected to trade near 1:1. Higher `amplification`
+    /// flattens the curve around the balanced point, lowering slippage there.
+    StableSwap { amplification: u32 },
+    /// Balancer-style `B ^ w_B * Q ^ (1 - w_B) = V`, where the base asset makes up `base_weight`
+    /// of the pool's value and the quote asset the rest.
+    Weighted { base_weight: Perbill },
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Self::ConstantProduct
+    }
+}
+
+/// Prices of an AMM's assets summed over every block since the first observation, for computing
+/// time-weighted averages. Each asset is priced in terms of the one given by
+/// `Amm::reference_index`. Accumulators wrap around on overflow, as in Uniswap v2, since only their
+/// differences between observations are meaningful.
+#[derive(Clone, Decode, Encode, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+#[codec(mel_bound())]
+pub struct PriceObservation<T: Config> {
+    pub block: T::BlockNumber,
+    pub price_cumulatives: BoundedVec<T::Balance, T::MaxAssetsPerPool>,
+}
+
+impl<T: Config> PriceObservation<T> {
+    /// The first observation of an AMM, at block `now`.
+    pub fn new(now: T::BlockNumber, amm_state: &Amm<T>) -> Self {
+        let mut price_cumulatives = amm_state.reserves.clone();
+        for cumulative in price_cumulatives.iter_mut() {
+            *cumulative = Zero::zero();
+        }
+
+        Self {
+            block: now,
+            price_cumulatives,
+        }
+    }
+
+    /// The observation at block `now`, given that `amm_state` holds the reserves since the block of
+    /// this observation.
+    pub fn accumulate(
+        &self,
+        now: T::BlockNumber,
+        amm_state: &Amm<T>,
+        unit: T::Balance,
+    ) -> Result<Self, DispatchError> {
+        let elapsed: T::Balance = now
+            .saturating_sub(self.block)
+            .saturated_into::<u64>()
+            .into();
+
+        let mut price_cumulatives = self.price_cumulatives.clone();
+        for (index, cumulative) in price_cumulatives.iter_mut().enumerate() {
+            let price = amm_state.spot_price(index, Amm::<T>::reference_index(index), unit)?;
+            *cumulative = wrapping_add(*cumulative, wrapping_mul(price, elapsed)?)?;
+        }
+
+        Ok(Self {
+            block: now,
+            price_cumulatives,
+        })
+    }
+
+    /// The cumulative price of the asset at `index` in terms of its reference asset.
+    pub fn price_cumulative(&self, index: usize) -> T::Balance {
+        self.price_cumulatives.get(index).copied().unwrap_or_else(Zero::zero)
+    }
+}
+
+/// The state of a concentrated liquidity AMM, where each liquidity provider picks the range of
+/// prices their assets are traded in. See [`crate::curves::concentrated`].
+#[derive(CloneNoBound, Encode, Decode, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+#[codec(mel_bound())]
+pub struct ConcentratedAmm<T: Config> {
+    /// Ids of the assets in the pool. Prices are those of the first asset in terms of the second.
+    pub assets: [T::AssetId; 2],
+    pub fees_bps: T::Balance,
+    /// Positions may only start and end at multiples of this tick.
+    pub tick_spacing: u32,
+    /// Square root of the current price, with 96 fractional bits.
+    pub sqrt_price: U256,
+    /// The greatest tick whose price is at most the current one.
+    pub tick: i32,
+    /// Total liquidity of the positions whose range includes `tick`.
+    pub liquidity: u128,
+    /// Fees earned per unit of liquidity since the AMM was created in each asset, with 128
+    /// fractional bits. Wraps around on overflow, as only differences are meaningful.
+    pub fee_growths: [U256; 2],
+    /// Ticks where some position starts or ends, sorted.
+    pub ticks: BoundedVec<(i32, Tick), T::MaxTicksPerPool>,
+}
+
+impl<T: Config> ConcentratedAmm<T> {
+    /// The position of `asset` in the pool, if the AMM holds it.
+    pub fn asset_index(&self, asset: &T::AssetId) -> Option<usize> {
+        self.assets.iter().position(|a| a == asset)
+    }
+
+    /// The positions of the input and output assets of a swap, if the AMM holds both and they
+    /// are different.
+    pub fn swap_indices(
+        &self,
+        asset_in: &T::AssetId,
+        asset_out: &T::AssetId,
+    ) -> Option<(usize, usize)> {
+        let indices = (self.asset_index(asset_in)?, self.asset_index(asset_out)?);
+        (indices.0 != indices.1).then(|| indices)
+    }
+
+    /// Fees earned per unit of liquidity within `[lower_tick, upper_tick)` in each asset, wrapping
+    /// around like `fee_growths`. Both ticks must be initialized for the result to be meaningful.
+    pub fn fee_growths_inside(&self, lower_tick: i32, upper_tick: i32) -> [U256; 2] {
+        let outside = |tick: i32| {
+            self.ticks
+                .binary_search_by_key(&tick, |(tick, _)| *tick)
+                .map(|index| self.ticks[index].1.fee_growths_outside)
+                .unwrap_or_default()
+        };
+        let (lower, upper) = (outside(lower_tick), outside(upper_tick));
+
+        let mut inside = [U256::zero(); 2];
+        for (index, growth) in inside.iter_mut().enumerate() {
+            let global = self.fee_growths[index];
+            let below = if self.tick >= lower_tick {
+                lower[index]
+            } else {
+                global.overflowing_sub(lower[index]).0
+            };
+            let above = if self.tick < upper_tick {
+                upper[index]
+            } else {
+                global.overflowing_sub(upper[index]).0
+            };
+            *growth = global.overflowing_sub(below).0.overflowing_sub(above).0;
+        }
+        inside
+    }
+}
+
+/// Liquidity changes and fee accounting at a tick where concentrated liquidity positions start or
+/// end.
+#[derive(Clone, Debug, Decode, Default, Encode, MaxEncodedLen, PartialEq, Eq, TypeInfo)]
+pub struct Tick {
+    /// Total liquidity of the positions starting or ending at this tick.
+    pub liquidity_gross: u128,
+    /// Liquidity to add when the price crosses this tick upwards, or to remove when downwards.
+    pub liquidity_net: i128,
+    /// Fees earned per unit of liquidity on the other side of this tick from the current price.
+    pub fee_growths_outside: [U256; 2],
+}
+
+/// Unique identifier of a concentrated liquidity position.
+pub type PositionId = u64;
+
+/// Liquidity provided to a concentrated liquidity AMM within a range of ticks. Unlike shares of
+/// other AMMs, positions are not fungible and only their owner may withdraw from them.
+#[derive(CloneNoBound, Encode, Decode, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+#[codec(mel_bound())]
+pub struct Position<T: Config> {
+    pub owner: T::AccountId,
+    pub amm_id: T::AmmId,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+    pub liquidity: u128,
+    /// Fees earned per unit of liquidity within the range as of the last update of the position.
+    pub fee_growths_inside: [U256; 2],
+    /// Fees earned as of the last update of the position and not collected yet.
+    pub fees_owed: [T::Balance; 2],
+}
+
+/// Unique identifier of a limit order.
+pub type OrderId = u64;
+
+/// An order to swap `input_amount` of `asset_in` for at least `output_min` of `asset_out` against
+/// an AMM, resting until the AMM's price allows it. Its input is held in escrow until the order is
+/// filled or cancelled.
+#[derive(CloneNoBound, Encode, Decode, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+#[codec(mel_bound())]
+pub struct LimitOrder<T: Config> {
+    pub owner: T::AccountId,
+    pub amm_id: T::AmmId,
+    pub asset_in: T::AssetId,
+    pub asset_out: T::AssetId,
+    pub input_amount: T::Balance,
+    pub output_min: T::Balance,
+}
+
+/// A swap of `input_amount` of `asset_in` for at least `output_min` of the other asset of an AMM in
+/// batch mode, waiting in escrow to be cleared with the rest of the block's swaps.
+#[derive(CloneNoBound, Encode, Decode, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+#[codec(mel_bound())]
+pub struct BatchSwap<T: Config> {
+    pub user: T::AccountId,
+    pub asset_in: T::AssetId,
+    pub input_amount: T::Balance,
+    pub output_min: T::Balance,
+}
+
+/// Unique identifier of a liquidity mining farm.
+pub type FarmId = u32;
+
+/// A farm emitting `reward_per_block` of `reward_asset` between blocks `start` and `end` to the
+/// accounts staking shares of an AMM, in proportion to their stake. Emissions also stop once the
+/// rewards funded run out.
+#[derive(CloneNoBound, Encode, Decode, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+#[codec(mel_bound())]
+pub struct Farm<T: Config> {
+    pub amm_id: T::AmmId,
+    pub share_asset: T::AssetId,
+    pub reward_asset: T::AssetId,
+    pub reward_per_block: T::Balance,
+    pub start: T::BlockNumber,
+    pub end: T::BlockNumber,
+    /// Shares staked in the farm by all accounts.
+    pub total_staked: T::Balance,
+    /// Rewards emitted per staked share since the farm started, with 128 fractional bits.
+    pub reward_per_share: U256,
+    /// The block up to which rewards were emitted.
+    pub last_update: T::BlockNumber,
+    /// Rewards funded and not emitted yet.
+    pub unallocated: T::Balance,
+}
+
+/// Shares of an AMM staked by an account in a farm.
+#[derive(CloneNoBound, Encode, Decode, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+#[codec(mel_bound())]
+pub struct Stake<T: Config> {
+    pub amount: T::Balance,
+    /// Rewards emitted per staked share as of the last update of the stake.
+    pub reward_per_share: U256,
+    /// Rewards earned as of the last update of the stake and not claimed yet, with 128
+    /// fractional bits. Only whole units are paid out, and the fraction left carries over.
+    pub rewards_owed: U256,
+}
+
+/// Balance of the native currency, in which `PoolDeposit` is reserved.
+pub type NativeBalanceOf<T> = <<T as Config>::Currency as Currency<
+    <T as frame_system::Config>::AccountId,
+>>::Balance;
+
+/// Ordered hops of a routed swap: the AMM to swap against and the asset to get out of it, using
+/// the output of the previous hop as input.
+pub type Route<T> =
+    BoundedVec<(<T as Config>::AmmId, <T as Config>::AssetId), <T as Config>::MaxRouteLength>;
+
+/// Fees charged on the input of a swap, on top of the amount actually traded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SwapFees<Balance> {
+    /// Kept by the AMM for its liquidity providers.
+    pub lp: Balance,
+    /// Sent to the protocol fee recipient.
+    pub protocol: Balance,
+}
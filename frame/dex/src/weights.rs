@@ -0,0 +1,69 @@
+//! Weights of the DEX pallet.
+//!
+//! The pallet is not benchmarked yet, so these charge the same flat weight for computation as its
+//! other calls, plus the storage each call accesses.
+
+use frame_support::{
+    traits::Get,
+    weights::{constants::RocksDbWeight, Weight},
+};
+use sp_std::marker::PhantomData;
+
+/// The flat weight charged for the computation of a swap.
+const SWAP_COMPUTATION: Weight = 1_000;
+
+/// The computation of clearing a batch of `s` swaps. Pricing the batch takes up to `s` rounds over
+/// all of its swaps, each leaving one out, and the excess is swapped against the AMM.
+fn clear_batch_computation(s: u32) -> Weight {
+    let s = Weight::from(s);
+    SWAP_COMPUTATION.saturating_mul(s.saturating_mul(s).saturating_add(1))
+}
+
+/// Weight functions needed by the DEX pallet.
+pub trait WeightInfo {
+    fn swap() -> Weight;
+    fn clear_batch(s: u32) -> Weight;
+}
+
+/// Weights for the DEX pallet, using the database weights of the runtime.
+pub struct SubstrateWeight<T>(PhantomData<T>);
+impl<T: frame_system::Config> WeightInfo for SubstrateWeight<T> {
+    // Storage: Dex Frozen (r:1 w:0)
+    // Storage: Dex PausedAmms (r:1 w:0)
+    // Storage: Dex BatchedAmms (r:1 w:0)
+    // Storage: Dex ConcentratedAmms (r:1 w:0)
+    // Storage: Dex AmmStates (r:1 w:1)
+    // Storage: Dex PriceObservations (r:1 w:1)
+    // Storage: Dex PriceObservationHeads (r:1 w:1)
+    // Storage: Assets Asset (r:2 w:0)
+    // Storage: Assets Account (r:5 w:5)
+    fn swap() -> Weight {
+        SWAP_COMPUTATION.saturating_add(T::DbWeight::get().reads_writes(14, 8))
+    }
+    // Storage: Dex BatchSwaps (r:1 w:1)
+    // Storage: Dex Frozen (r:1 w:0)
+    // Storage: Dex PausedAmms (r:1 w:0)
+    // Storage: Dex AmmStates (r:2 w:1)
+    // Storage: Dex PriceObservations (r:1 w:1)
+    // Storage: Dex PriceObservationHeads (r:1 w:1)
+    // Storage: Assets Asset (r:2 w:0)
+    // Storage: Assets Account (r:7 w:7)
+    // Storage: Assets Account (r:s w:s)
+    fn clear_batch(s: u32) -> Weight {
+        clear_batch_computation(s)
+            .saturating_add(T::DbWeight::get().reads_writes(16, 11))
+            .saturating_add(T::DbWeight::get().reads_writes(s.into(), s.into()))
+    }
+}
+
+// For tests, and runtimes without their own database weights.
+impl WeightInfo for () {
+    fn swap() -> Weight {
+        SWAP_COMPUTATION.saturating_add(RocksDbWeight::get().reads_writes(14, 8))
+    }
+    fn clear_batch(s: u32) -> Weight {
+        clear_batch_computation(s)
+            .saturating_add(RocksDbWeight::get().reads_writes(16, 11))
+            .saturating_add(RocksDbWeight::get().reads_writes(s.into(), s.into()))
+    }
+}
@@ -11,7 +11,7 @@ use sp_core::H256;
 use sp_runtime::{
     testing::Header,
     traits::{BlakeTwo256, IdentityLookup, Zero},
-    BuildStorage,
+    BuildStorage, Permill,
 };
 
 // -------------------------------------------------------------------------------------------------
@@ -87,7 +87,10 @@ impl frame_system::Config for Test {
 // Copied from https://github.com/paritytech/substrate/blob/master/frame/assets/src/mock.rs
 // -------------------------------------------------------------------------------------------------
 
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+};
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub(crate) enum Hook {
@@ -155,16 +158,41 @@ impl pallet_balances::Config for Test {
 parameter_types! {
     pub const TestPalletId: PalletId = PalletId(*b"test_pid");
     pub const DefaultDecimals: u8 = DEFAULT_DECIMALS;
+    pub const MaxAssetsPerPool: u32 = 4;
+    pub const MaxBatchSwaps: u32 = 8;
+    pub const MaxPriceObservations: u32 = 16;
+    pub const MaxRouteLength: u32 = 4;
+    pub const MaxTicksPerPool: u32 = 16;
+    pub const MinimumLiquidity: Balance = 1_000;
+    pub const PoolDeposit: Balance = 0;
+    pub const ProtocolFee: Permill = Permill::zero();
+    pub const ProtocolFeeRecipient: AccountId = 99;
+    pub const ShareMetadataDeposit: Balance = 50;
 }
 
 impl pallet_dex::Config for Test {
+    type AdminOrigin = frame_system::EnsureRoot<AccountId>;
     type AmmId = AmmId;
     type AssetId = AssetId;
     type Assets = Assets;
     type Balance = Balance;
+    type Currency = Balances;
     type DefaultDecimals = DefaultDecimals;
     type Event = Event;
+    type FlashBorrower = ();
+    type MaxAssetsPerPool = MaxAssetsPerPool;
+    type MaxBatchSwaps = MaxBatchSwaps;
+    type MaxPriceObservations = MaxPriceObservations;
+    type MaxRouteLength = MaxRouteLength;
+    type MaxTicksPerPool = MaxTicksPerPool;
+    type MinimumLiquidity = MinimumLiquidity;
     type PalletId = TestPalletId;
+    type PoolDeposit = PoolDeposit;
+    type ProtocolFee = ProtocolFee;
+    type ProtocolFeeRecipient = ProtocolFeeRecipient;
+    type ShareMetadataDeposit = ShareMetadataDeposit;
+    type StringLimit = ConstU32<50>;
+    type WeightInfo = ();
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -215,7 +243,15 @@ pub(crate) fn new_test_ext(
     GenesisConfig {
         //
         balances: BalancesConfig {
-            balances: users.iter().map(|(user, _, _)| (*user, 10)).collect(),
+            // Kitty owners and holders of assets, who may create AMMs.
+            balances: users
+                .iter()
+                .map(|(user, _, _)| *user)
+                .chain(accounts.iter().map(|(_, account, _)| *account))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .map(|account| (account, 100))
+                .collect(),
         },
         substrate_kitties: SubstrateKittiesConfig {
             kitties: users
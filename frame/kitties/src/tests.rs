@@ -440,18 +440,19 @@ fn can_use_dex_to_obtain_token_for_kitty_purchase() {
     .execute_with(|| {
         assert_ok!(Dex::create_amm(
             Origin::signed(CHARLIE),
-            DOT,
-            USDC,
+            vec![DOT, USDC].try_into().unwrap(),
             DEFAULT_SHARE_ASSET,
             30, // 30 bps, or 0.3%
+            None,
         ));
 
         // Charlie initializes the AMM by providing liquidity
         assert_ok!(Dex::provide_liquidity(
             Origin::signed(CHARLIE),
             0,
-            UNIT * 5,
-            UNIT * 500,
+            vec![UNIT * 5, UNIT * 500].try_into().unwrap(),
+            0,
+            None,
         ));
 
         // Alice sets a price of 40 USDC for her kitty
@@ -471,13 +472,15 @@ fn can_use_dex_to_obtain_token_for_kitty_purchase() {
             40 * UNIT
         ).unwrap();
 
-        // Bob uses the DOT amount computed to aquire the USDC needed.
-        assert_ok!(Dex::swap(
+        // Bob uses the DOT amount computed to aquire exactly the USDC needed.
+        assert_ok!(Dex::swap_exact_out(
             Origin::signed(BOB),
             0,
-            AssetType::Base,
-            dot_required * 1001 / 1000, // Send an extra bit to account for rounding errors
-            0
+            DOT,
+            USDC,
+            40 * UNIT,
+            dot_required,
+            None,
         ));
 
         // Bob can buy Alice's kitty, specifying some limit_price
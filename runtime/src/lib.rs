@@ -8,6 +8,7 @@ include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
 use frame_support::PalletId;
 use frame_system::EnsureRoot;
+use pallet_dex::traits::SimulateSwap;
 use pallet_grandpa::{
 	fg_primitives, AuthorityId as GrandpaId, AuthorityList as GrandpaAuthorityList,
 };
@@ -16,9 +17,12 @@ use sp_consensus_aura::sr25519::AuthorityId as AuraId;
 use sp_core::{crypto::KeyTypeId, OpaqueMetadata};
 use sp_runtime::{
 	create_runtime_str, generic, impl_opaque_keys,
-	traits::{AccountIdLookup, BlakeTwo256, Block as BlockT, IdentifyAccount, NumberFor, Verify},
+	traits::{
+		AccountIdConversion, AccountIdLookup, BlakeTwo256, Block as BlockT, IdentifyAccount,
+		NumberFor, Verify,
+	},
 	transaction_validity::{TransactionSource, TransactionValidity},
-	ApplyExtrinsicResult, MultiSignature,
+	ApplyExtrinsicResult, DispatchError, MultiSignature,
 };
 use sp_std::prelude::*;
 #[cfg(feature = "std")]
@@ -39,6 +43,7 @@ pub use frame_support::{
 };
 pub use frame_system::Call as SystemCall;
 pub use pallet_balances::Call as BalancesCall;
+pub use pallet_dex::CheckDeadline;
 pub use pallet_timestamp::Call as TimestampCall;
 use pallet_transaction_payment::CurrencyAdapter;
 #[cfg(any(feature = "std", test))]
@@ -100,10 +105,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	//   `spec_version`, and `authoring_version` are the same between Wasm and native.
 	// This value is set to 100 to notify Polkadot-JS App (https://polkadot.js.org/apps) to use
 	//   the compatible custom types.
-	spec_version: 100,
+	spec_version: 101,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
-	transaction_version: 1,
+	transaction_version: 2,
 	state_version: 1,
 };
 
@@ -306,16 +311,44 @@ pub type AmmId = u32;
 parameter_types! {
     pub const TestPalletId: PalletId = PalletId(*b"test_pid");
     pub const DefaultDecimals: u8 = 6;
+    pub const MaxAssetsPerPool: u32 = 4;
+    pub const MaxBatchSwaps: u32 = 64;
+    pub const MaxPriceObservations: u32 = 600;
+    pub const MaxRouteLength: u32 = 4;
+    pub const MaxTicksPerPool: u32 = 128;
+    pub const MinimumLiquidity: Balance = 1_000;
+    pub const PoolDeposit: Balance = 10 * DOLLARS;
+    pub const ProtocolFee: Permill = Permill::from_percent(20);
+    // Protocol fees accrue to a keyless account, like a treasury's. Nobody can sign for it, so
+    // spending them is left to governance, which can move them with `Assets::force_transfer`.
+    pub const ProtocolFeePalletId: PalletId = PalletId(*b"py/dexfe");
+    pub ProtocolFeeRecipient: AccountId = ProtocolFeePalletId::get().into_account_truncating();
+    pub const ShareMetadataDeposit: Balance = 100 * DOLLARS;
 }
 
 impl pallet_dex::Config for Runtime {
+    type AdminOrigin = EnsureRoot<AccountId>;
     type AmmId = AmmId;
     type AssetId = AssetId;
     type Assets = Assets;
     type Balance = Balance;
+    type Currency = Balances;
     type DefaultDecimals = DefaultDecimals;
     type Event = Event;
+    type FlashBorrower = ();
+    type MaxAssetsPerPool = MaxAssetsPerPool;
+    type MaxBatchSwaps = MaxBatchSwaps;
+    type MaxPriceObservations = MaxPriceObservations;
+    type MaxRouteLength = MaxRouteLength;
+    type MaxTicksPerPool = MaxTicksPerPool;
+    type MinimumLiquidity = MinimumLiquidity;
     type PalletId = TestPalletId;
+    type PoolDeposit = PoolDeposit;
+    type ProtocolFee = ProtocolFee;
+    type ProtocolFeeRecipient = ProtocolFeeRecipient;
+    type ShareMetadataDeposit = ShareMetadataDeposit;
+    type StringLimit = StringLimit;
+    type WeightInfo = pallet_dex::weights::SubstrateWeight<Runtime>;
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -378,6 +411,7 @@ pub type SignedExtra = (
 	frame_system::CheckNonce<Runtime>,
 	frame_system::CheckWeight<Runtime>,
 	pallet_transaction_payment::ChargeTransactionPayment<Runtime>,
+	pallet_dex::CheckDeadline<Runtime>,
 );
 /// Unchecked extrinsic type as expected by this runtime.
 pub type UncheckedExtrinsic = generic::UncheckedExtrinsic<Address, Call, Signature, SignedExtra>;
@@ -390,6 +424,10 @@ pub type Executive = frame_executive::Executive<
 	frame_system::ChainContext<Runtime>,
 	Runtime,
 	AllPalletsWithSystem,
+	(
+		pallet_dex::migrations::v1::MigrateToV1<Runtime>,
+		pallet_dex::migrations::v2::MigrateToV2<Runtime>,
+	),
 >;
 
 #[cfg(feature = "runtime-benchmarks")]
@@ -538,6 +576,47 @@ impl_runtime_apis! {
 		}
 	}
 
+	impl pallet_dex_rpc_runtime_api::DexApi<Block, AmmId, AssetId, AccountId, Balance> for Runtime {
+		fn quote_by_input(
+			amm_id: AmmId,
+			asset_in: AssetId,
+			asset_out: AssetId,
+			amount: Balance,
+		) -> Result<Balance, DispatchError> {
+			<Dex as SimulateSwap>::simulate_swap_between(amm_id, asset_in, asset_out, amount)
+		}
+
+		fn quote_by_output(
+			amm_id: AmmId,
+			asset_in: AssetId,
+			asset_out: AssetId,
+			amount: Balance,
+		) -> Result<Balance, DispatchError> {
+			<Dex as SimulateSwap>::output_price_between(amm_id, asset_in, asset_out, amount)
+		}
+
+		fn pair_amm(asset_a: AssetId, asset_b: AssetId, fees_bps: Balance) -> Option<AmmId> {
+			Dex::pair_amm(asset_a, asset_b, fees_bps)
+		}
+
+		fn quote_pair_by_input(
+			asset_in: AssetId,
+			asset_out: AssetId,
+			fees_bps: Balance,
+			amount: Balance,
+		) -> Result<Balance, DispatchError> {
+			Dex::quote_pair(asset_in, asset_out, fees_bps, amount)
+		}
+
+		fn pool_reserves(amm_id: AmmId) -> Option<Vec<Balance>> {
+			Dex::pool_reserves(amm_id)
+		}
+
+		fn lp_share_value(amm_id: AmmId, who: AccountId) -> Result<Vec<Balance>, DispatchError> {
+			Dex::lp_share_value(amm_id, &who)
+		}
+	}
+
 	#[cfg(feature = "runtime-benchmarks")]
 	impl frame_benchmarking::Benchmark<Block> for Runtime {
 		fn benchmark_metadata(extra: bool) -> (